@@ -0,0 +1,1444 @@
+use super::name::{is_lead_name_char, AmlName, NameString};
+use super::namespace::Namespace;
+use super::region;
+use super::value::{AmlValue, FieldKind, FieldUnit, Method, OpRegion, Reference};
+use super::AmlError;
+use crate::acpi::AddressSpace;
+use crate::logging::Level;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt::Write;
+
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ALIAS_OP: u8 = 0x06;
+const NAME_OP: u8 = 0x08;
+const BYTE_PREFIX: u8 = 0x0A;
+const WORD_PREFIX: u8 = 0x0B;
+const DWORD_PREFIX: u8 = 0x0C;
+const STRING_PREFIX: u8 = 0x0D;
+const QWORD_PREFIX: u8 = 0x0E;
+const SCOPE_OP: u8 = 0x10;
+const BUFFER_OP: u8 = 0x11;
+const PACKAGE_OP: u8 = 0x12;
+const VAR_PACKAGE_OP: u8 = 0x13;
+const METHOD_OP: u8 = 0x14;
+const EXTERNAL_OP: u8 = 0x15;
+const DUAL_NAME_PREFIX: u8 = 0x2E;
+const MULTI_NAME_PREFIX: u8 = 0x2F;
+const EXT_OP_PREFIX: u8 = 0x5B;
+const ROOT_CHAR: u8 = b'\\';
+const PARENT_PREFIX_CHAR: u8 = b'^';
+const LOCAL0_OP: u8 = 0x60;
+const LOCAL7_OP: u8 = 0x67;
+const ARG0_OP: u8 = 0x68;
+const ARG6_OP: u8 = 0x6E;
+const STORE_OP: u8 = 0x70;
+const REF_OF_OP: u8 = 0x71;
+const ADD_OP: u8 = 0x72;
+const CONCAT_OP: u8 = 0x73;
+const SUBTRACT_OP: u8 = 0x74;
+const INCREMENT_OP: u8 = 0x75;
+const DECREMENT_OP: u8 = 0x76;
+const MULTIPLY_OP: u8 = 0x77;
+const DIVIDE_OP: u8 = 0x78;
+const SHIFT_LEFT_OP: u8 = 0x79;
+const SHIFT_RIGHT_OP: u8 = 0x7A;
+const AND_OP: u8 = 0x7B;
+const NAND_OP: u8 = 0x7C;
+const OR_OP: u8 = 0x7D;
+const NOR_OP: u8 = 0x7E;
+const XOR_OP: u8 = 0x7F;
+const NOT_OP: u8 = 0x80;
+const FIND_SET_LEFT_BIT_OP: u8 = 0x81;
+const FIND_SET_RIGHT_BIT_OP: u8 = 0x82;
+const DEREF_OF_OP: u8 = 0x83;
+const CONCAT_RES_OP: u8 = 0x84;
+const MOD_OP: u8 = 0x85;
+const NOTIFY_OP: u8 = 0x86;
+const SIZE_OF_OP: u8 = 0x87;
+const INDEX_OP: u8 = 0x88;
+const MATCH_OP: u8 = 0x89;
+const CREATE_DWORD_FIELD_OP: u8 = 0x8A;
+const CREATE_WORD_FIELD_OP: u8 = 0x8B;
+const CREATE_BYTE_FIELD_OP: u8 = 0x8C;
+const CREATE_BIT_FIELD_OP: u8 = 0x8D;
+const OBJECT_TYPE_OP: u8 = 0x8E;
+const CREATE_QWORD_FIELD_OP: u8 = 0x8F;
+const LAND_OP: u8 = 0x90;
+const LOR_OP: u8 = 0x91;
+const LNOT_OP: u8 = 0x92;
+const LEQUAL_OP: u8 = 0x93;
+const LGREATER_OP: u8 = 0x94;
+const LLESS_OP: u8 = 0x95;
+const TO_BUFFER_OP: u8 = 0x96;
+const TO_DECIMAL_STRING_OP: u8 = 0x97;
+const TO_HEX_STRING_OP: u8 = 0x98;
+const TO_INTEGER_OP: u8 = 0x99;
+const TO_STRING_OP: u8 = 0x9C;
+const COPY_OBJECT_OP: u8 = 0x9D;
+const MID_OP: u8 = 0x9E;
+const CONTINUE_OP: u8 = 0x9F;
+const IF_OP: u8 = 0xA0;
+const ELSE_OP: u8 = 0xA1;
+const WHILE_OP: u8 = 0xA2;
+const NOOP_OP: u8 = 0xA3;
+const RETURN_OP: u8 = 0xA4;
+const BREAK_OP: u8 = 0xA5;
+const BREAKPOINT_OP: u8 = 0xCC;
+const ONES_OP: u8 = 0xFF;
+
+// Second byte of the extended (0x5B prefixed) opcodes.
+const MUTEX_OP: u8 = 0x01;
+const EVENT_OP: u8 = 0x02;
+const COND_REF_OF_OP: u8 = 0x12;
+const CREATE_FIELD_OP: u8 = 0x13;
+const LOAD_TABLE_OP: u8 = 0x1F;
+const LOAD_OP: u8 = 0x20;
+const STALL_OP: u8 = 0x21;
+const SLEEP_OP: u8 = 0x22;
+const ACQUIRE_OP: u8 = 0x23;
+const SIGNAL_OP: u8 = 0x24;
+const WAIT_OP: u8 = 0x25;
+const RESET_OP: u8 = 0x26;
+const RELEASE_OP: u8 = 0x27;
+const FROM_BCD_OP: u8 = 0x28;
+const TO_BCD_OP: u8 = 0x29;
+const UNLOAD_OP: u8 = 0x2A;
+const REVISION_OP: u8 = 0x30;
+const DEBUG_OP: u8 = 0x31;
+const FATAL_OP: u8 = 0x32;
+const TIMER_OP: u8 = 0x33;
+const OP_REGION_OP: u8 = 0x80;
+const FIELD_OP: u8 = 0x81;
+const DEVICE_OP: u8 = 0x82;
+const PROCESSOR_OP: u8 = 0x83;
+const POWER_RES_OP: u8 = 0x84;
+const THERMAL_ZONE_OP: u8 = 0x85;
+const INDEX_FIELD_OP: u8 = 0x86;
+const BANK_FIELD_OP: u8 = 0x87;
+const DATA_REGION_OP: u8 = 0x88;
+
+/// Revision of this interpreter, returned by the Revision operator.
+const INTERPRETER_REVISION: u64 = 1;
+/// Limits that stop broken firmware from hanging the kernel. Every nested call
+/// recurses through the decoder, so the depth also has to fit on the kernel
+/// stack (KERNEL_STACK_SIZE in main.rs); real tables stay well below it.
+const MAX_CALL_DEPTH: usize = 16;
+const MAX_LOOP_ITERATIONS: usize = 0x10_0000;
+
+fn is_name_lead(byte: u8) -> bool {
+    is_lead_name_char(byte)
+        || byte == ROOT_CHAR
+        || byte == PARENT_PREFIX_CHAR
+        || byte == DUAL_NAME_PREFIX
+        || byte == MULTI_NAME_PREFIX
+}
+
+/// A window of AML bytecode being decoded.
+#[derive(Clone, Copy)]
+pub struct Stream {
+    data: &'static [u8],
+    pos: usize,
+    end: usize,
+}
+
+impl Stream {
+    pub fn new(data: &'static [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            end: data.len(),
+        }
+    }
+
+    /// The part of this stream up to `end`, usually the end of a PkgLength.
+    fn until(&self, end: usize) -> Self {
+        Self {
+            data: self.data,
+            pos: self.pos,
+            end,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.end
+    }
+
+    fn peek(&self) -> Result<u8, AmlError> {
+        self.peek_at(0).ok_or(AmlError::UnexpectedEnd)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        if self.pos + offset < self.end {
+            Some(self.data[self.pos + offset])
+        } else {
+            None
+        }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'static [u8], AmlError> {
+        if self.pos + count > self.end {
+            return Err(AmlError::UnexpectedEnd);
+        }
+        let bytes = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, AmlError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, AmlError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn dword(&mut self) -> Result<u32, AmlError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn qword(&mut self) -> Result<u64, AmlError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Decode a PkgLength. Bits 6-7 of the lead byte count the bytes that follow,
+    /// which hold the higher bits of the length.
+    fn pkg_length_value(&mut self) -> Result<usize, AmlError> {
+        let lead = self.byte()?;
+        let count = (lead >> 6) as usize;
+        if count == 0 {
+            return Ok((lead & 0x3f) as usize);
+        }
+        let mut length = (lead & 0x0f) as usize;
+        for i in 0..count {
+            length |= (self.byte()? as usize) << (4 + 8 * i);
+        }
+        Ok(length)
+    }
+
+    /// Decode a PkgLength and return where the package ends. The length counts
+    /// itself, so it is relative to where it starts.
+    fn pkg_length(&mut self) -> Result<usize, AmlError> {
+        let start = self.pos;
+        let end = start + self.pkg_length_value()?;
+        if end > self.end || end < self.pos {
+            return Err(AmlError::UnexpectedEnd);
+        }
+        Ok(end)
+    }
+
+    fn name_seg(&mut self) -> Result<[u8; 4], AmlError> {
+        let mut segment = [0u8; 4];
+        segment.copy_from_slice(self.bytes(4)?);
+        if !is_lead_name_char(segment[0]) || !segment[1..].iter().all(|c| super::name::is_name_char(*c)) {
+            return Err(AmlError::InvalidName);
+        }
+        Ok(segment)
+    }
+
+    fn name_string(&mut self) -> Result<NameString, AmlError> {
+        let mut name = NameString {
+            root: false,
+            parents: 0,
+            segments: Vec::new(),
+        };
+        if self.peek()? == ROOT_CHAR {
+            name.root = true;
+            self.pos += 1;
+        } else {
+            while self.peek()? == PARENT_PREFIX_CHAR {
+                name.parents += 1;
+                self.pos += 1;
+            }
+        }
+        let count = match self.peek()? {
+            ZERO_OP => {
+                self.pos += 1;
+                0
+            }
+            DUAL_NAME_PREFIX => {
+                self.pos += 1;
+                2
+            }
+            MULTI_NAME_PREFIX => {
+                self.pos += 1;
+                self.byte()? as usize
+            }
+            _ => 1,
+        };
+        for _ in 0..count {
+            name.segments.push(self.name_seg()?);
+        }
+        Ok(name)
+    }
+}
+
+/// How a list of terms finished.
+enum Flow {
+    Normal,
+    Return(AmlValue),
+    Break,
+    Continue,
+}
+
+/// State of one method invocation, or of a table being loaded.
+struct Context {
+    scope: AmlName,
+    locals: [AmlValue; 8],
+    args: [AmlValue; 7],
+    /// Objects the method created, they go away when it returns.
+    created: Vec<AmlName>,
+    in_method: bool,
+}
+
+impl Context {
+    fn new(scope: AmlName, in_method: bool) -> Self {
+        Self {
+            scope,
+            locals: core::array::from_fn(|_| AmlValue::Uninitialized),
+            args: core::array::from_fn(|_| AmlValue::Uninitialized),
+            created: Vec::new(),
+            in_method,
+        }
+    }
+}
+
+pub struct Interpreter<'a> {
+    pub namespace: &'a mut Namespace,
+    /// All ones at the integer width of the DSDT: 32 bits before revision 2.
+    ones: u64,
+    depth: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(namespace: &'a mut Namespace, dsdt_revision: u8) -> Self {
+        Self {
+            namespace,
+            ones: if dsdt_revision < 2 { u32::MAX as u64 } else { u64::MAX },
+            depth: 0,
+        }
+    }
+
+    /// Run the top level code of a DSDT or SSDT, which defines its objects.
+    pub fn load_table(&mut self, aml: &'static [u8]) -> Result<(), AmlError> {
+        let mut context = Context::new(AmlName::root(), false);
+        self.term_list(&mut context, &mut Stream::new(aml))?;
+        Ok(())
+    }
+
+    /// Evaluate the object at `path`: run it if it is a method, read it otherwise.
+    pub fn evaluate(&mut self, path: &AmlName, args: Vec<AmlValue>) -> Result<AmlValue, AmlError> {
+        let path = self.namespace.resolve_alias(path);
+        match self.namespace.get(&path).cloned() {
+            Some(AmlValue::Method(method)) => self.invoke(&path, method, args),
+            Some(AmlValue::NativeMethod { function, .. }) => function(&args),
+            Some(_) => {
+                let mut context = Context::new(path.parent().unwrap_or_else(AmlName::root), false);
+                self.read(&mut context, &Reference::Name(path))
+            }
+            None => Err(AmlError::NotFound(path)),
+        }
+    }
+
+    fn invoke(&mut self, path: &AmlName, method: Method, args: Vec<AmlValue>) -> Result<AmlValue, AmlError> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(AmlError::NestingTooDeep);
+        }
+        // On the heap: with its locals and arguments it's too big to keep one per
+        // nested call on the stack.
+        let mut context = Box::new(Context::new(path.clone(), true));
+        for (slot, arg) in context.args.iter_mut().zip(args) {
+            *slot = arg;
+        }
+
+        self.depth += 1;
+        let result = self.term_list(&mut context, &mut Stream::new(method.code));
+        self.depth -= 1;
+        for name in context.created.iter().rev() {
+            self.namespace.remove(name);
+        }
+
+        match result? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(AmlValue::Uninitialized),
+        }
+    }
+
+    fn term_list(&mut self, context: &mut Context, stream: &mut Stream) -> Result<Flow, AmlError> {
+        while !stream.at_end() {
+            let flow = match self.term(context, stream) {
+                Ok(flow) => flow,
+                // While loading, one bad definition shouldn't lose the rest of the
+                // table: give up on the enclosing block only.
+                Err(error) if !context.in_method => {
+                    log_at!(Level::Warn, "AML: skipping the rest of {}: {}", context.scope, error);
+                    stream.pos = stream.end;
+                    return Ok(Flow::Normal);
+                }
+                Err(error) => return Err(error),
+            };
+            if !matches!(flow, Flow::Normal) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Add an object to the namespace, remembering it if a method is creating it.
+    fn define(&mut self, context: &mut Context, name: &NameString, value: AmlValue) -> Result<AmlName, AmlError> {
+        let path = name.resolve(&context.scope).ok_or(AmlError::InvalidName)?;
+        if context.in_method && !self.namespace.contains(&path) {
+            context.created.push(path.clone());
+        }
+        self.namespace.insert(path.clone(), value);
+        Ok(path)
+    }
+
+    /// Define a scope-like object and run the terms inside it.
+    fn define_block(
+        &mut self,
+        context: &mut Context,
+        stream: &mut Stream,
+        end: usize,
+        name: &NameString,
+        value: AmlValue,
+    ) -> Result<(), AmlError> {
+        let path = self.define(context, name, value)?;
+        self.block(context, stream, end, path)
+    }
+
+    fn block(&mut self, context: &mut Context, stream: &mut Stream, end: usize, scope: AmlName) -> Result<(), AmlError> {
+        let outer = core::mem::replace(&mut context.scope, scope);
+        let result = self.term_list(context, &mut stream.until(end));
+        context.scope = outer;
+        stream.pos = end;
+        result.map(|_| ())
+    }
+
+    fn term(&mut self, context: &mut Context, stream: &mut Stream) -> Result<Flow, AmlError> {
+        let op = stream.peek()?;
+        match op {
+            NAME_OP => {
+                stream.pos += 1;
+                let name = stream.name_string()?;
+                let value = self.data_ref_object(context, stream)?;
+                self.define(context, &name, value)?;
+            }
+            ALIAS_OP => {
+                stream.pos += 1;
+                let source = stream.name_string()?;
+                let alias = stream.name_string()?;
+                let target = self
+                    .namespace
+                    .search(&source, &context.scope)
+                    .or_else(|| source.resolve(&context.scope))
+                    .ok_or(AmlError::InvalidName)?;
+                self.define(context, &alias, AmlValue::Alias(target))?;
+            }
+            SCOPE_OP => {
+                stream.pos += 1;
+                let end = stream.pkg_length()?;
+                let name = stream.name_string()?;
+                let path = match self.namespace.search(&name, &context.scope) {
+                    Some(path) => path,
+                    None => self.define(context, &name, AmlValue::Scope)?,
+                };
+                self.block(context, stream, end, path)?;
+            }
+            METHOD_OP => {
+                stream.pos += 1;
+                let end = stream.pkg_length()?;
+                let name = stream.name_string()?;
+                let flags = stream.byte()?;
+                let method = Method {
+                    code: &stream.data[stream.pos..end],
+                    arg_count: (flags & 0x7) as usize,
+                };
+                self.define(context, &name, AmlValue::Method(method))?;
+                stream.pos = end;
+            }
+            EXTERNAL_OP => {
+                // Only a hint for disassemblers.
+                stream.pos += 1;
+                stream.name_string()?;
+                stream.bytes(2)?;
+            }
+            EXT_OP_PREFIX => return self.extended_term(context, stream),
+            IF_OP => {
+                stream.pos += 1;
+                let end = stream.pkg_length()?;
+                let predicate = self.term_arg(context, stream)?.as_integer()? != 0;
+                let mut body = stream.until(end);
+                stream.pos = end;
+                let mut flow = Flow::Normal;
+                if predicate {
+                    flow = self.term_list(context, &mut body)?;
+                }
+                if stream.peek_at(0) == Some(ELSE_OP) {
+                    stream.pos += 1;
+                    let end = stream.pkg_length()?;
+                    let mut body = stream.until(end);
+                    stream.pos = end;
+                    if !predicate {
+                        flow = self.term_list(context, &mut body)?;
+                    }
+                }
+                return Ok(flow);
+            }
+            ELSE_OP => {
+                // Only reached for an Else without its If, skip it.
+                stream.pos += 1;
+                stream.pos = stream.pkg_length()?;
+            }
+            WHILE_OP => {
+                stream.pos += 1;
+                let end = stream.pkg_length()?;
+                let start = stream.pos;
+                let mut iterations = 0;
+                loop {
+                    let mut body = stream.until(end);
+                    body.pos = start;
+                    if self.term_arg(context, &mut body)?.as_integer()? == 0 {
+                        break;
+                    }
+                    iterations += 1;
+                    if iterations > MAX_LOOP_ITERATIONS {
+                        return Err(AmlError::LoopTimeout);
+                    }
+                    match self.term_list(context, &mut body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+                stream.pos = end;
+            }
+            RETURN_OP => {
+                stream.pos += 1;
+                let value = self.term_arg(context, stream)?;
+                return Ok(Flow::Return(value));
+            }
+            BREAK_OP => {
+                stream.pos += 1;
+                return Ok(Flow::Break);
+            }
+            CONTINUE_OP => {
+                stream.pos += 1;
+                return Ok(Flow::Continue);
+            }
+            NOOP_OP | BREAKPOINT_OP => stream.pos += 1,
+            NOTIFY_OP => {
+                stream.pos += 1;
+                let object = self.super_name(context, stream)?;
+                let value = self.operand(context, stream)?;
+                log_at!(Level::Debug, "AML: Notify({:?}, 0x{:x})", object, value);
+            }
+            CREATE_BIT_FIELD_OP | CREATE_BYTE_FIELD_OP | CREATE_WORD_FIELD_OP | CREATE_DWORD_FIELD_OP
+            | CREATE_QWORD_FIELD_OP => {
+                stream.pos += 1;
+                let source = self.operand_reference(context, stream)?;
+                let index = self.operand(context, stream)? as usize;
+                let (bit_offset, bit_length) = match op {
+                    CREATE_BIT_FIELD_OP => (index, 1),
+                    CREATE_BYTE_FIELD_OP => (index * 8, 8),
+                    CREATE_WORD_FIELD_OP => (index * 8, 16),
+                    CREATE_DWORD_FIELD_OP => (index * 8, 32),
+                    _ => (index * 8, 64),
+                };
+                let name = stream.name_string()?;
+                let field = AmlValue::BufferField {
+                    source,
+                    bit_offset,
+                    bit_length,
+                };
+                self.define(context, &name, field)?;
+            }
+            _ => {
+                // An expression used as a statement, usually a method call.
+                self.term_arg(context, stream)?;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn extended_term(&mut self, context: &mut Context, stream: &mut Stream) -> Result<Flow, AmlError> {
+        let op = stream.peek_at(1).ok_or(AmlError::UnexpectedEnd)?;
+        match op {
+            MUTEX_OP => {
+                stream.pos += 2;
+                let name = stream.name_string()?;
+                // The sync level only orders acquires, which never wait here.
+                stream.byte()?;
+                self.define(context, &name, AmlValue::Mutex)?;
+            }
+            EVENT_OP => {
+                stream.pos += 2;
+                let name = stream.name_string()?;
+                self.define(context, &name, AmlValue::Event)?;
+            }
+            OP_REGION_OP => {
+                stream.pos += 2;
+                let name = stream.name_string()?;
+                let space = match stream.byte()? {
+                    0 => AddressSpace::SystemMemory,
+                    1 => AddressSpace::SystemIo,
+                    2 => AddressSpace::PciConfig,
+                    other => AddressSpace::Other(other),
+                };
+                let offset = self.operand(context, stream)?;
+                let length = self.operand(context, stream)?;
+                let region = OpRegion { space, offset, length };
+                self.define(context, &name, AmlValue::OpRegion(region))?;
+            }
+            FIELD_OP => {
+                stream.pos += 2;
+                let end = stream.pkg_length()?;
+                let region = self.existing_name(context, stream)?;
+                let flags = stream.byte()?;
+                self.field_list(context, stream, end, FieldKind::Normal { region }, flags)?;
+            }
+            INDEX_FIELD_OP => {
+                stream.pos += 2;
+                let end = stream.pkg_length()?;
+                let index = self.existing_name(context, stream)?;
+                let data = self.existing_name(context, stream)?;
+                let flags = stream.byte()?;
+                self.field_list(context, stream, end, FieldKind::Index { index, data }, flags)?;
+            }
+            BANK_FIELD_OP => {
+                stream.pos += 2;
+                let end = stream.pkg_length()?;
+                let region = self.existing_name(context, stream)?;
+                let bank = self.existing_name(context, stream)?;
+                let value = self.operand(context, stream)?;
+                let flags = stream.byte()?;
+                self.field_list(context, stream, end, FieldKind::Bank { region, bank, value }, flags)?;
+            }
+            DEVICE_OP | THERMAL_ZONE_OP => {
+                stream.pos += 2;
+                let end = stream.pkg_length()?;
+                let name = stream.name_string()?;
+                let value = if op == DEVICE_OP {
+                    AmlValue::Device
+                } else {
+                    AmlValue::ThermalZone
+                };
+                self.define_block(context, stream, end, &name, value)?;
+            }
+            PROCESSOR_OP => {
+                stream.pos += 2;
+                let end = stream.pkg_length()?;
+                let name = stream.name_string()?;
+                // The processor id and P_BLK are superseded by the MADT and FADT.
+                stream.bytes(6)?;
+                self.define_block(context, stream, end, &name, AmlValue::Processor)?;
+            }
+            POWER_RES_OP => {
+                stream.pos += 2;
+                let end = stream.pkg_length()?;
+                let name = stream.name_string()?;
+                // System level and resource order only matter to power management.
+                stream.bytes(3)?;
+                self.define_block(context, stream, end, &name, AmlValue::PowerResource)?;
+            }
+            CREATE_FIELD_OP => {
+                stream.pos += 2;
+                let source = self.operand_reference(context, stream)?;
+                let bit_offset = self.operand(context, stream)? as usize;
+                let bit_length = self.operand(context, stream)? as usize;
+                let name = stream.name_string()?;
+                let field = AmlValue::BufferField {
+                    source,
+                    bit_offset,
+                    bit_length,
+                };
+                self.define(context, &name, field)?;
+            }
+            DATA_REGION_OP => return Err(AmlError::Unsupported("DataTableRegion")),
+            LOAD_OP | LOAD_TABLE_OP | UNLOAD_OP => return Err(AmlError::Unsupported("dynamic table loading")),
+            STALL_OP => {
+                stream.pos += 2;
+                let microseconds = self.operand(context, stream)?;
+                region::stall(microseconds);
+            }
+            SLEEP_OP => {
+                stream.pos += 2;
+                let milliseconds = self.operand(context, stream)?;
+                region::sleep(milliseconds);
+            }
+            // There is no concurrency in the interpreter, synchronisation objects
+            // are no-ops.
+            SIGNAL_OP | RESET_OP | RELEASE_OP => {
+                stream.pos += 2;
+                self.super_name(context, stream)?;
+            }
+            FATAL_OP => {
+                stream.pos += 2;
+                let fatal_type = stream.byte()?;
+                let code = stream.dword()?;
+                let argument = self.operand(context, stream)?;
+                return Err(AmlError::Fatal {
+                    fatal_type,
+                    code,
+                    argument,
+                });
+            }
+            _ => {
+                self.term_arg(context, stream)?;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Look up a name that has to exist already, e.g. the region of a field.
+    fn existing_name(&mut self, context: &Context, stream: &mut Stream) -> Result<AmlName, AmlError> {
+        let name = stream.name_string()?;
+        self.namespace
+            .search(&name, &context.scope)
+            .ok_or_else(|| AmlError::NotFound(name.resolve(&context.scope).unwrap_or_else(AmlName::root)))
+    }
+
+    fn field_list(
+        &mut self,
+        context: &mut Context,
+        stream: &mut Stream,
+        end: usize,
+        kind: FieldKind,
+        mut flags: u8,
+    ) -> Result<(), AmlError> {
+        let mut fields = stream.until(end);
+        stream.pos = end;
+        let mut bit_offset = 0;
+        while !fields.at_end() {
+            match fields.peek()? {
+                // ReservedField, the PkgLength is the number of bits to skip.
+                0x00 => {
+                    fields.pos += 1;
+                    bit_offset += fields.pkg_length_value()?;
+                }
+                // AccessField: changes the access type of the fields that follow.
+                0x01 => {
+                    fields.pos += 1;
+                    let access_type = fields.byte()?;
+                    fields.byte()?;
+                    flags = (flags & 0xf0) | (access_type & 0x0f);
+                }
+                // ConnectField, only meaningful for serial bus and GPIO regions.
+                0x02 => {
+                    fields.pos += 1;
+                    if fields.peek()? == BUFFER_OP {
+                        self.term_arg(context, &mut fields)?;
+                    } else {
+                        fields.name_string()?;
+                    }
+                }
+                // ExtendedAccessField
+                0x03 => {
+                    fields.pos += 1;
+                    let access_type = fields.byte()?;
+                    fields.bytes(2)?;
+                    flags = (flags & 0xf0) | (access_type & 0x0f);
+                }
+                _ => {
+                    let segment = fields.name_seg()?;
+                    let bit_length = fields.pkg_length_value()?;
+                    let field = FieldUnit {
+                        kind: kind.clone(),
+                        bit_offset,
+                        bit_length,
+                        flags,
+                    };
+                    let name = NameString {
+                        root: false,
+                        parents: 0,
+                        segments: alloc::vec![segment],
+                    };
+                    self.define(context, &name, AmlValue::Field(field))?;
+                    bit_offset += bit_length;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// DataRefObject: a constant, buffer, package or a reference to a named object.
+    fn data_ref_object(&mut self, context: &mut Context, stream: &mut Stream) -> Result<AmlValue, AmlError> {
+        if is_name_lead(stream.peek()?) {
+            return Ok(AmlValue::Reference(Reference::Name(self.name_reference(context, stream)?)));
+        }
+        self.term_arg(context, stream)
+    }
+
+    /// A name used as a reference, e.g. inside a package. It doesn't have to exist yet.
+    fn name_reference(&mut self, context: &Context, stream: &mut Stream) -> Result<AmlName, AmlError> {
+        let name = stream.name_string()?;
+        self.namespace
+            .search(&name, &context.scope)
+            .or_else(|| name.resolve(&context.scope))
+            .ok_or(AmlError::InvalidName)
+    }
+
+    fn operand(&mut self, context: &mut Context, stream: &mut Stream) -> Result<u64, AmlError> {
+        self.term_arg(context, stream)?.as_integer()
+    }
+
+    /// Target or SuperName: where a result gets stored.
+    fn super_name(&mut self, context: &mut Context, stream: &mut Stream) -> Result<Reference, AmlError> {
+        let op = stream.peek()?;
+        match op {
+            ZERO_OP => {
+                stream.pos += 1;
+                Ok(Reference::Null)
+            }
+            LOCAL0_OP..=LOCAL7_OP => {
+                stream.pos += 1;
+                Ok(Reference::Local((op - LOCAL0_OP) as usize))
+            }
+            ARG0_OP..=ARG6_OP => {
+                stream.pos += 1;
+                Ok(Reference::Arg((op - ARG0_OP) as usize))
+            }
+            EXT_OP_PREFIX if stream.peek_at(1) == Some(DEBUG_OP) => {
+                stream.pos += 2;
+                Ok(Reference::Debug)
+            }
+            _ if is_name_lead(op) => Ok(Reference::Name(self.existing_name(context, stream)?)),
+            // RefOf, DerefOf, Index or a method returning a reference.
+            _ => match self.term_arg(context, stream)? {
+                AmlValue::Reference(reference) => Ok(reference),
+                _ => Err(AmlError::InvalidTarget),
+            },
+        }
+    }
+
+    /// An operand that is needed as a reference rather than a value, like the source
+    /// of Index or CreateField, so stores through it reach the original object.
+    fn operand_reference(&mut self, context: &mut Context, stream: &mut Stream) -> Result<Reference, AmlError> {
+        let op = stream.peek()?;
+        if is_name_lead(op) {
+            let start = stream.pos;
+            let name = stream.name_string()?;
+            if let Some(path) = self.namespace.search(&name, &context.scope) {
+                if !matches!(self.namespace.get(&path), Some(AmlValue::Method(_) | AmlValue::NativeMethod { .. })) {
+                    return Ok(Reference::Name(path));
+                }
+            }
+            stream.pos = start;
+        } else if matches!(op, LOCAL0_OP..=LOCAL7_OP | ARG0_OP..=ARG6_OP) {
+            return self.super_name(context, stream);
+        }
+        match self.term_arg(context, stream)? {
+            AmlValue::Reference(reference) => Ok(reference),
+            value => Ok(Reference::Value(Box::new(value))),
+        }
+    }
+
+    /// TermArg: evaluate an expression.
+    fn term_arg(&mut self, context: &mut Context, stream: &mut Stream) -> Result<AmlValue, AmlError> {
+        let op = stream.peek()?;
+        if is_name_lead(op) {
+            return self.name_term(context, stream);
+        }
+        stream.pos += 1;
+        match op {
+            ZERO_OP => Ok(AmlValue::Integer(0)),
+            ONE_OP => Ok(AmlValue::Integer(1)),
+            ONES_OP => Ok(AmlValue::Integer(self.ones)),
+            BYTE_PREFIX => Ok(AmlValue::Integer(stream.byte()? as u64)),
+            WORD_PREFIX => Ok(AmlValue::Integer(stream.word()? as u64)),
+            DWORD_PREFIX => Ok(AmlValue::Integer(stream.dword()? as u64)),
+            QWORD_PREFIX => Ok(AmlValue::Integer(stream.qword()?)),
+            STRING_PREFIX => {
+                let mut string = String::new();
+                loop {
+                    match stream.byte()? {
+                        0 => break,
+                        c => string.push(c as char),
+                    }
+                }
+                Ok(AmlValue::String(string))
+            }
+            BUFFER_OP => {
+                let end = stream.pkg_length()?;
+                let size = self.operand(context, stream)? as usize;
+                let initializer = stream.data.get(stream.pos..end).ok_or(AmlError::UnexpectedEnd)?;
+                stream.pos = end;
+                let mut bytes = initializer.to_vec();
+                bytes.resize(size.max(initializer.len()), 0);
+                Ok(AmlValue::Buffer(bytes))
+            }
+            PACKAGE_OP | VAR_PACKAGE_OP => {
+                let end = stream.pkg_length()?;
+                let count = if op == PACKAGE_OP {
+                    stream.byte()? as usize
+                } else {
+                    self.operand(context, stream)? as usize
+                };
+                let mut elements_stream = stream.until(end);
+                stream.pos = end;
+                let mut elements = Vec::new();
+                while !elements_stream.at_end() {
+                    elements.push(self.data_ref_object(context, &mut elements_stream)?);
+                }
+                elements.resize(count, AmlValue::Uninitialized);
+                Ok(AmlValue::Package(elements))
+            }
+            LOCAL0_OP..=LOCAL7_OP => Ok(context.locals[(op - LOCAL0_OP) as usize].clone()),
+            ARG0_OP..=ARG6_OP => match context.args[(op - ARG0_OP) as usize].clone() {
+                // An argument holding a reference stands for the referenced object.
+                AmlValue::Reference(reference) => self.read(context, &reference),
+                value => Ok(value),
+            },
+            STORE_OP => {
+                let value = self.term_arg(context, stream)?;
+                let target = self.super_name(context, stream)?;
+                self.store(context, &target, value.clone())?;
+                Ok(value)
+            }
+            COPY_OBJECT_OP => {
+                let value = self.term_arg(context, stream)?;
+                let target = self.super_name(context, stream)?;
+                self.copy_object(context, &target, value.clone())?;
+                Ok(value)
+            }
+            ADD_OP => self.binary(context, stream, |a, b| Ok(a.wrapping_add(b))),
+            SUBTRACT_OP => self.binary(context, stream, |a, b| Ok(a.wrapping_sub(b))),
+            MULTIPLY_OP => self.binary(context, stream, |a, b| Ok(a.wrapping_mul(b))),
+            SHIFT_LEFT_OP => self.binary(context, stream, |a, b| Ok(a.checked_shl(b as u32).unwrap_or(0))),
+            SHIFT_RIGHT_OP => self.binary(context, stream, |a, b| Ok(a.checked_shr(b as u32).unwrap_or(0))),
+            AND_OP => self.binary(context, stream, |a, b| Ok(a & b)),
+            NAND_OP => self.binary(context, stream, |a, b| Ok(!(a & b))),
+            OR_OP => self.binary(context, stream, |a, b| Ok(a | b)),
+            NOR_OP => self.binary(context, stream, |a, b| Ok(!(a | b))),
+            XOR_OP => self.binary(context, stream, |a, b| Ok(a ^ b)),
+            MOD_OP => self.binary(context, stream, |a, b| a.checked_rem(b).ok_or(AmlError::DivideByZero)),
+            DIVIDE_OP => {
+                let dividend = self.operand(context, stream)?;
+                let divisor = self.operand(context, stream)?;
+                let remainder_target = self.super_name(context, stream)?;
+                let quotient_target = self.super_name(context, stream)?;
+                if divisor == 0 {
+                    return Err(AmlError::DivideByZero);
+                }
+                let quotient = AmlValue::Integer(dividend / divisor);
+                self.store(context, &remainder_target, AmlValue::Integer(dividend % divisor))?;
+                self.store(context, &quotient_target, quotient.clone())?;
+                Ok(quotient)
+            }
+            NOT_OP | FIND_SET_LEFT_BIT_OP | FIND_SET_RIGHT_BIT_OP => {
+                let value = self.operand(context, stream)? & self.ones;
+                let result = match op {
+                    NOT_OP => !value,
+                    // Bit numbers are one based, zero means no bit is set.
+                    FIND_SET_LEFT_BIT_OP if value != 0 => 64 - value.leading_zeros() as u64,
+                    FIND_SET_RIGHT_BIT_OP if value != 0 => value.trailing_zeros() as u64 + 1,
+                    _ => 0,
+                };
+                self.store_result(context, stream, AmlValue::Integer(result & self.ones))
+            }
+            INCREMENT_OP | DECREMENT_OP => {
+                let target = self.super_name(context, stream)?;
+                let value = self.read(context, &target)?.as_integer()?;
+                let value = if op == INCREMENT_OP {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+                let value = AmlValue::Integer(value & self.ones);
+                self.store(context, &target, value.clone())?;
+                Ok(value)
+            }
+            LAND_OP | LOR_OP => {
+                let a = self.operand(context, stream)? != 0;
+                let b = self.operand(context, stream)? != 0;
+                Ok(self.boolean(if op == LAND_OP { a && b } else { a || b }))
+            }
+            LNOT_OP => match stream.peek()? {
+                // LNotEqual, LLessEqual and LGreaterEqual are encoded as LNot
+                // followed by the opposite comparison.
+                LEQUAL_OP | LGREATER_OP | LLESS_OP => {
+                    let compare = stream.byte()?;
+                    let ordering = self.compare(context, stream)?;
+                    Ok(self.boolean(!Self::comparison_holds(compare, ordering)))
+                }
+                _ => {
+                    let value = self.operand(context, stream)?;
+                    Ok(self.boolean(value == 0))
+                }
+            },
+            LEQUAL_OP | LGREATER_OP | LLESS_OP => {
+                let ordering = self.compare(context, stream)?;
+                Ok(self.boolean(Self::comparison_holds(op, ordering)))
+            }
+            CONCAT_OP => {
+                let a = self.term_arg(context, stream)?;
+                let b = self.term_arg(context, stream)?;
+                let result = match a {
+                    AmlValue::String(a) => AmlValue::String(a + &b.as_string()?),
+                    AmlValue::Integer(a) => {
+                        let width = if self.ones == u64::MAX { 8 } else { 4 };
+                        let mut bytes = a.to_le_bytes()[..width].to_vec();
+                        bytes.extend_from_slice(&b.as_integer()?.to_le_bytes()[..width]);
+                        AmlValue::Buffer(bytes)
+                    }
+                    a => {
+                        let mut bytes = a.as_buffer()?;
+                        bytes.extend_from_slice(&b.as_buffer()?);
+                        AmlValue::Buffer(bytes)
+                    }
+                };
+                self.store_result(context, stream, result)
+            }
+            CONCAT_RES_OP => {
+                // Join two resource templates, dropping the end tag of the first.
+                let mut a = self.term_arg(context, stream)?.as_buffer()?;
+                let b = self.term_arg(context, stream)?.as_buffer()?;
+                if a.len() >= 2 && a[a.len() - 2] == 0x79 {
+                    a.truncate(a.len() - 2);
+                }
+                a.extend_from_slice(&b);
+                self.store_result(context, stream, AmlValue::Buffer(a))
+            }
+            REF_OF_OP => {
+                let reference = self.super_name(context, stream)?;
+                Ok(AmlValue::Reference(self.detach(context, reference)?))
+            }
+            DEREF_OF_OP => match self.term_arg(context, stream)? {
+                AmlValue::Reference(reference) => self.read(context, &reference),
+                AmlValue::String(path) => {
+                    let path = AmlName::parse(&path).ok_or(AmlError::InvalidName)?;
+                    self.read(context, &Reference::Name(path))
+                }
+                _ => Err(AmlError::TypeMismatch),
+            },
+            INDEX_OP => {
+                let source = self.operand_reference(context, stream)?;
+                let index = self.operand(context, stream)? as usize;
+                let reference = Reference::Index(Box::new(source), index);
+                let target = self.super_name(context, stream)?;
+                let value = AmlValue::Reference(reference);
+                self.store(context, &target, value.clone())?;
+                Ok(value)
+            }
+            SIZE_OF_OP => {
+                let object = self.super_name(context, stream)?;
+                let size = match self.read(context, &object)? {
+                    AmlValue::Buffer(bytes) => bytes.len(),
+                    AmlValue::String(string) => string.len(),
+                    AmlValue::Package(elements) => elements.len(),
+                    _ => return Err(AmlError::TypeMismatch),
+                };
+                Ok(AmlValue::Integer(size as u64))
+            }
+            OBJECT_TYPE_OP => {
+                let object = self.super_name(context, stream)?;
+                let object_type = match &object {
+                    Reference::Name(path) => self.namespace.get(path).map(|o| o.object_type()).unwrap_or(0),
+                    Reference::Debug => 16,
+                    reference => self.read(context, reference)?.object_type(),
+                };
+                Ok(AmlValue::Integer(object_type))
+            }
+            MATCH_OP => {
+                let package = match self.term_arg(context, stream)? {
+                    AmlValue::Package(elements) => elements,
+                    _ => return Err(AmlError::TypeMismatch),
+                };
+                let first_op = stream.byte()?;
+                let first = self.term_arg(context, stream)?;
+                let second_op = stream.byte()?;
+                let second = self.term_arg(context, stream)?;
+                let start = self.operand(context, stream)? as usize;
+                let found = package.iter().enumerate().skip(start).find(|(_, element)| {
+                    Self::match_holds(first_op, element, &first) && Self::match_holds(second_op, element, &second)
+                });
+                Ok(AmlValue::Integer(found.map(|(i, _)| i as u64).unwrap_or(self.ones)))
+            }
+            TO_BUFFER_OP => {
+                let value = self.term_arg(context, stream)?.as_buffer()?;
+                self.store_result(context, stream, AmlValue::Buffer(value))
+            }
+            TO_INTEGER_OP => {
+                let value = match self.term_arg(context, stream)? {
+                    // Explicit conversion accepts decimal as well as 0x prefixed hex.
+                    AmlValue::String(string) => match string.strip_prefix("0x").or(string.strip_prefix("0X")) {
+                        Some(hex) => u64::from_str_radix(hex, 16).map_err(|_| AmlError::TypeMismatch)?,
+                        None => string.parse().map_err(|_| AmlError::TypeMismatch)?,
+                    },
+                    value => value.as_integer()?,
+                };
+                self.store_result(context, stream, AmlValue::Integer(value & self.ones))
+            }
+            TO_DECIMAL_STRING_OP | TO_HEX_STRING_OP => {
+                let value = self.term_arg(context, stream)?;
+                let mut string = String::new();
+                match (&value, op) {
+                    (AmlValue::String(s), _) => string.push_str(s),
+                    (AmlValue::Integer(v), TO_DECIMAL_STRING_OP) => {
+                        let _ = write!(string, "{}", v);
+                    }
+                    (AmlValue::Integer(v), _) => {
+                        let _ = write!(string, "0x{:X}", v);
+                    }
+                    (AmlValue::Buffer(bytes), _) => {
+                        for (i, byte) in bytes.iter().enumerate() {
+                            let separator = if i == 0 { "" } else { "," };
+                            let _ = match op {
+                                TO_DECIMAL_STRING_OP => write!(string, "{}{}", separator, byte),
+                                _ => write!(string, "{}0x{:02X}", separator, byte),
+                            };
+                        }
+                    }
+                    _ => return Err(AmlError::TypeMismatch),
+                }
+                self.store_result(context, stream, AmlValue::String(string))
+            }
+            TO_STRING_OP => {
+                let bytes = self.term_arg(context, stream)?.as_buffer()?;
+                let limit = self.operand(context, stream)? as usize;
+                let string = bytes
+                    .iter()
+                    .take(limit)
+                    .take_while(|byte| **byte != 0)
+                    .map(|byte| *byte as char)
+                    .collect();
+                self.store_result(context, stream, AmlValue::String(string))
+            }
+            MID_OP => {
+                let source = self.term_arg(context, stream)?;
+                let index = self.operand(context, stream)? as usize;
+                let length = self.operand(context, stream)? as usize;
+                let result = match source {
+                    AmlValue::String(string) => {
+                        AmlValue::String(string.chars().skip(index).take(length).collect())
+                    }
+                    source => AmlValue::Buffer(source.as_buffer()?.into_iter().skip(index).take(length).collect()),
+                };
+                self.store_result(context, stream, result)
+            }
+            EXT_OP_PREFIX => self.extended_term_arg(context, stream),
+            _ => Err(AmlError::UnknownOpcode(op as u16)),
+        }
+    }
+
+    fn extended_term_arg(&mut self, context: &mut Context, stream: &mut Stream) -> Result<AmlValue, AmlError> {
+        let op = stream.byte()?;
+        match op {
+            REVISION_OP => Ok(AmlValue::Integer(INTERPRETER_REVISION)),
+            TIMER_OP => Ok(AmlValue::Integer(region::timer())),
+            COND_REF_OF_OP => {
+                // Like RefOf, but the object not existing isn't an error.
+                let name = match stream.peek()? {
+                    lead if is_name_lead(lead) => {
+                        let name = stream.name_string()?;
+                        match self.namespace.search(&name, &context.scope) {
+                            Some(path) => Reference::Name(path),
+                            None => {
+                                self.super_name(context, stream)?;
+                                return Ok(self.boolean(false));
+                            }
+                        }
+                    }
+                    _ => self.super_name(context, stream)?,
+                };
+                let exists = match &name {
+                    Reference::Local(n) => !matches!(context.locals[*n], AmlValue::Uninitialized),
+                    Reference::Arg(n) => !matches!(context.args[*n], AmlValue::Uninitialized),
+                    _ => true,
+                };
+                let target = self.super_name(context, stream)?;
+                if exists {
+                    let reference = AmlValue::Reference(self.detach(context, name)?);
+                    self.store(context, &target, reference)?;
+                }
+                Ok(self.boolean(exists))
+            }
+            ACQUIRE_OP => {
+                self.super_name(context, stream)?;
+                stream.word()?;
+                // Acquired, there is nothing to wait for.
+                Ok(AmlValue::Integer(0))
+            }
+            WAIT_OP => {
+                self.super_name(context, stream)?;
+                self.operand(context, stream)?;
+                Ok(AmlValue::Integer(0))
+            }
+            FROM_BCD_OP => {
+                let mut bcd = self.operand(context, stream)?;
+                let mut value = 0;
+                let mut scale = 1;
+                while bcd != 0 {
+                    value += (bcd & 0xf) * scale;
+                    scale *= 10;
+                    bcd >>= 4;
+                }
+                self.store_result(context, stream, AmlValue::Integer(value))
+            }
+            TO_BCD_OP => {
+                let mut value = self.operand(context, stream)?;
+                let mut bcd = 0;
+                let mut shift = 0;
+                while value != 0 && shift < 64 {
+                    bcd |= (value % 10) << shift;
+                    value /= 10;
+                    shift += 4;
+                }
+                self.store_result(context, stream, AmlValue::Integer(bcd))
+            }
+            DEBUG_OP => Ok(AmlValue::Reference(Reference::Debug)),
+            _ => Err(AmlError::UnknownOpcode(0x5b00 | op as u16)),
+        }
+    }
+
+    /// A NameString in an expression: call the method it names or read the object.
+    fn name_term(&mut self, context: &mut Context, stream: &mut Stream) -> Result<AmlValue, AmlError> {
+        let name = stream.name_string()?;
+        let path = self
+            .namespace
+            .search(&name, &context.scope)
+            .ok_or_else(|| AmlError::NotFound(name.resolve(&context.scope).unwrap_or_else(AmlName::root)))?;
+        match self.namespace.get(&path).cloned() {
+            Some(AmlValue::Method(method)) => {
+                let args = self.call_args(context, stream, method.arg_count)?;
+                let path = self.namespace.resolve_alias(&path);
+                self.invoke(&path, method, args)
+            }
+            Some(AmlValue::NativeMethod { arg_count, function }) => {
+                let args = self.call_args(context, stream, arg_count)?;
+                function(&args)
+            }
+            _ => self.read(context, &Reference::Name(path)),
+        }
+    }
+
+    fn call_args(&mut self, context: &mut Context, stream: &mut Stream, count: usize) -> Result<Vec<AmlValue>, AmlError> {
+        (0..count).map(|_| self.term_arg(context, stream)).collect()
+    }
+
+    /// Locals and arguments belong to the running method, a reference to one that
+    /// outlives it (returned, or passed to another method) holds its value instead.
+    fn detach(&mut self, context: &mut Context, reference: Reference) -> Result<Reference, AmlError> {
+        match reference {
+            Reference::Local(_) | Reference::Arg(_) => Ok(Reference::Value(Box::new(self.read(context, &reference)?))),
+            reference => Ok(reference),
+        }
+    }
+
+    fn boolean(&self, value: bool) -> AmlValue {
+        AmlValue::Integer(if value { self.ones } else { 0 })
+    }
+
+    fn binary(
+        &mut self,
+        context: &mut Context,
+        stream: &mut Stream,
+        operation: fn(u64, u64) -> Result<u64, AmlError>,
+    ) -> Result<AmlValue, AmlError> {
+        let a = self.operand(context, stream)?;
+        let b = self.operand(context, stream)?;
+        let result = AmlValue::Integer(operation(a, b)? & self.ones);
+        self.store_result(context, stream, result)
+    }
+
+    /// Store the result of an operator to its optional target and return it.
+    fn store_result(&mut self, context: &mut Context, stream: &mut Stream, value: AmlValue) -> Result<AmlValue, AmlError> {
+        let target = self.super_name(context, stream)?;
+        self.store(context, &target, value.clone())?;
+        Ok(value)
+    }
+
+    fn compare(&mut self, context: &mut Context, stream: &mut Stream) -> Result<Ordering, AmlError> {
+        let a = self.term_arg(context, stream)?;
+        let b = self.term_arg(context, stream)?;
+        Self::compare_values(&a, &b)
+    }
+
+    fn compare_values(a: &AmlValue, b: &AmlValue) -> Result<Ordering, AmlError> {
+        match a {
+            AmlValue::String(a) => Ok(a.as_str().cmp(b.as_string()?.as_str())),
+            AmlValue::Buffer(a) => Ok(a.as_slice().cmp(b.as_buffer()?.as_slice())),
+            a => Ok(a.as_integer()?.cmp(&b.as_integer()?)),
+        }
+    }
+
+    fn comparison_holds(op: u8, ordering: Ordering) -> bool {
+        match op {
+            LEQUAL_OP => ordering == Ordering::Equal,
+            LGREATER_OP => ordering == Ordering::Greater,
+            _ => ordering == Ordering::Less,
+        }
+    }
+
+    /// Match operator predicates: MTR, MEQ, MLE, MLT, MGE, MGT.
+    fn match_holds(op: u8, element: &AmlValue, operand: &AmlValue) -> bool {
+        if op == 0 {
+            return true;
+        }
+        match Self::compare_values(element, operand) {
+            Ok(ordering) => match op {
+                1 => ordering == Ordering::Equal,
+                2 => ordering != Ordering::Greater,
+                3 => ordering == Ordering::Less,
+                4 => ordering != Ordering::Less,
+                5 => ordering == Ordering::Greater,
+                _ => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    pub(super) fn read_name(&mut self, path: &AmlName) -> Result<AmlValue, AmlError> {
+        let mut context = Context::new(path.parent().unwrap_or_else(AmlName::root), false);
+        self.read(&mut context, &Reference::Name(path.clone()))
+    }
+
+    pub(super) fn write_name(&mut self, path: &AmlName, value: AmlValue) -> Result<(), AmlError> {
+        let mut context = Context::new(path.parent().unwrap_or_else(AmlName::root), false);
+        self.store(&mut context, &Reference::Name(path.clone()), value)
+    }
+
+    fn read(&mut self, context: &mut Context, reference: &Reference) -> Result<AmlValue, AmlError> {
+        match reference {
+            Reference::Null | Reference::Debug => Ok(AmlValue::Uninitialized),
+            Reference::Local(n) => Ok(context.locals[*n].clone()),
+            Reference::Arg(n) => match context.args[*n].clone() {
+                AmlValue::Reference(reference) => self.read(context, &reference),
+                value => Ok(value),
+            },
+            Reference::Value(value) => Ok((**value).clone()),
+            Reference::Index(base, index) => {
+                let container = match self.read(context, base)? {
+                    AmlValue::Reference(reference) => self.read(context, &reference)?,
+                    container => container,
+                };
+                match container {
+                    AmlValue::Package(mut elements) if *index < elements.len() => Ok(elements.swap_remove(*index)),
+                    AmlValue::Buffer(bytes) if *index < bytes.len() => Ok(AmlValue::Integer(bytes[*index] as u64)),
+                    AmlValue::String(string) if *index < string.len() => {
+                        Ok(AmlValue::Integer(string.as_bytes()[*index] as u64))
+                    }
+                    _ => Err(AmlError::IndexOutOfBounds),
+                }
+            }
+            Reference::Name(path) => {
+                let path = self.namespace.resolve_alias(path);
+                match self.namespace.get(&path).cloned() {
+                    None => Err(AmlError::NotFound(path)),
+                    Some(AmlValue::Field(field)) => region::read_field(self, &field),
+                    Some(AmlValue::BufferField {
+                        source,
+                        bit_offset,
+                        bit_length,
+                    }) => {
+                        let bytes = self.read(context, &source)?.as_buffer()?;
+                        Ok(region::extract_bits(&bytes, bit_offset, bit_length))
+                    }
+                    Some(AmlValue::Method(method)) if method.arg_count == 0 => self.invoke(&path, method, Vec::new()),
+                    // Devices and other objects without a value stand for themselves.
+                    Some(
+                        AmlValue::Device
+                        | AmlValue::Processor
+                        | AmlValue::PowerResource
+                        | AmlValue::ThermalZone
+                        | AmlValue::Mutex
+                        | AmlValue::Event
+                        | AmlValue::OpRegion(_)
+                        | AmlValue::Scope,
+                    ) => Ok(AmlValue::Reference(Reference::Name(path))),
+                    Some(value) => Ok(value),
+                }
+            }
+        }
+    }
+
+    fn store(&mut self, context: &mut Context, target: &Reference, value: AmlValue) -> Result<(), AmlError> {
+        match target {
+            Reference::Null | Reference::Value(_) => Ok(()),
+            Reference::Debug => {
+                log_at!(Level::Debug, "AML debug: {:?}", value);
+                Ok(())
+            }
+            Reference::Local(n) => {
+                context.locals[*n] = value;
+                Ok(())
+            }
+            Reference::Arg(n) => match context.args[*n].clone() {
+                AmlValue::Reference(reference) => self.store(context, &reference, value),
+                _ => {
+                    context.args[*n] = value;
+                    Ok(())
+                }
+            },
+            Reference::Index(base, index) => {
+                let mut container = self.read(context, base)?;
+                if let AmlValue::Reference(reference) = container {
+                    let element = Reference::Index(Box::new(reference), *index);
+                    return self.store(context, &element, value);
+                }
+                match &mut container {
+                    AmlValue::Package(elements) if *index < elements.len() => elements[*index] = value,
+                    AmlValue::Buffer(bytes) if *index < bytes.len() => bytes[*index] = value.as_integer()? as u8,
+                    _ => return Err(AmlError::IndexOutOfBounds),
+                }
+                self.copy_object(context, base, container)
+            }
+            Reference::Name(path) => {
+                let path = self.namespace.resolve_alias(path);
+                match self.namespace.get(&path).cloned() {
+                    Some(AmlValue::Field(field)) => region::write_field(self, &field, value),
+                    Some(AmlValue::BufferField {
+                        source,
+                        bit_offset,
+                        bit_length,
+                    }) => {
+                        let mut bytes = self.read(context, &source)?.as_buffer()?;
+                        region::insert_bits(&mut bytes, bit_offset, bit_length, &value.as_buffer()?);
+                        self.copy_object(context, &source, AmlValue::Buffer(bytes))
+                    }
+                    Some(existing @ (AmlValue::Integer(_) | AmlValue::String(_) | AmlValue::Buffer(_))) => {
+                        let value = value.convert_like(&existing, self.ones)?;
+                        self.namespace.insert(path, value);
+                        Ok(())
+                    }
+                    Some(AmlValue::Method(_) | AmlValue::NativeMethod { .. } | AmlValue::Device) => {
+                        Err(AmlError::InvalidTarget)
+                    }
+                    _ => {
+                        self.namespace.insert(path, value);
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Store without converting to the type of the target.
+    fn copy_object(&mut self, context: &mut Context, target: &Reference, value: AmlValue) -> Result<(), AmlError> {
+        match target {
+            Reference::Name(path) => {
+                let path = self.namespace.resolve_alias(path);
+                match self.namespace.get(&path) {
+                    Some(AmlValue::Field(_) | AmlValue::BufferField { .. }) => self.store(context, target, value),
+                    _ => {
+                        self.namespace.insert(path, value);
+                        Ok(())
+                    }
+                }
+            }
+            Reference::Local(n) => {
+                context.locals[*n] = value;
+                Ok(())
+            }
+            target => self.store(context, target, value),
+        }
+    }
+}
@@ -0,0 +1,337 @@
+mod interpreter;
+mod name;
+mod namespace;
+mod region;
+pub mod resource;
+mod value;
+
+use super::{dsdt, find_tables, Sdt, SDT_HEADER_SIZE};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use interpreter::Interpreter;
+use namespace::Namespace;
+use spin::Mutex;
+
+pub use name::AmlName;
+pub use resource::Resource;
+pub use value::{AmlValue, Reference};
+
+#[derive(Clone, Debug)]
+pub enum AmlError {
+    UnexpectedEnd,
+    UnknownOpcode(u16),
+    InvalidName,
+    NotFound(AmlName),
+    TypeMismatch,
+    InvalidTarget,
+    IndexOutOfBounds,
+    DivideByZero,
+    UnsupportedRegion(u8),
+    Unsupported(&'static str),
+    NestingTooDeep,
+    LoopTimeout,
+    /// The firmware executed a Fatal operator.
+    Fatal {
+        fatal_type: u8,
+        code: u32,
+        argument: u64,
+    },
+    /// The namespace isn't loaded, or is in use further up the stack.
+    Unavailable,
+}
+
+impl fmt::Display for AmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmlError::UnexpectedEnd => write!(f, "unexpected end of code"),
+            AmlError::UnknownOpcode(op) => write!(f, "unknown opcode 0x{:x}", op),
+            AmlError::InvalidName => write!(f, "invalid name"),
+            AmlError::NotFound(name) => write!(f, "{} not found", name),
+            AmlError::TypeMismatch => write!(f, "type mismatch"),
+            AmlError::InvalidTarget => write!(f, "invalid target"),
+            AmlError::IndexOutOfBounds => write!(f, "index out of bounds"),
+            AmlError::DivideByZero => write!(f, "divide by zero"),
+            AmlError::UnsupportedRegion(space) => write!(f, "unsupported region space {}", space),
+            AmlError::Unsupported(what) => write!(f, "{} unsupported", what),
+            AmlError::NestingTooDeep => write!(f, "nesting too deep"),
+            AmlError::LoopTimeout => write!(f, "loop timed out"),
+            AmlError::Fatal {
+                fatal_type,
+                code,
+                argument,
+            } => write!(f, "fatal error type {} code 0x{:x} argument 0x{:x}", fatal_type, code, argument),
+            AmlError::Unavailable => write!(f, "namespace unavailable"),
+        }
+    }
+}
+
+/// `_STA` bits: the device is present, and functioning.
+const STA_PRESENT: u64 = 1 << 0;
+const STA_FUNCTIONING: u64 = 1 << 3;
+/// `_STA` of a device without one.
+const STA_DEFAULT: u64 = 0xf;
+
+/// The ACPI namespace, built by running the AML (ACPI Machine Language) in the DSDT
+/// and SSDTs: devices, their configuration objects and the methods that control
+/// them. Methods are interpreted on demand through `evaluate`.
+static NAMESPACE: Mutex<Option<Namespace>> = Mutex::new(None);
+
+/// Load the DSDT and SSDTs into the namespace and initialise the devices.
+pub fn init() {
+    let dsdt = match dsdt() {
+        Some(dsdt) => dsdt,
+        None => {
+            log!("no DSDT, AML not loaded");
+            return;
+        }
+    };
+    let mut namespace = Namespace::new();
+    let mut interpreter = Interpreter::new(&mut namespace, dsdt.revision());
+    let tables: Vec<Sdt> = core::iter::once(dsdt).chain(find_tables("SSDT")).collect();
+    for table in &tables {
+        if let Err(error) = interpreter.load_table(&table.data()[SDT_HEADER_SIZE..]) {
+            log_at!(crate::logging::Level::Warn, "{} failed to load: {}", table.signature(), error);
+        }
+    }
+    initialize_devices(&mut interpreter);
+
+    let devices = namespace.iter().filter(|(_, object)| matches!(object, AmlValue::Device)).count();
+    log!("namespace loaded from {} tables: {} objects, {} devices", tables.len(), namespace.len(), devices);
+    *NAMESPACE.lock() = Some(namespace);
+
+    for bridge in find_devices("PNP0A03") {
+        if device_status(&bridge) & STA_PRESENT == 0 {
+            continue;
+        }
+        log!("PCI host bridge {}", bridge);
+        for resource in current_resources(&bridge).unwrap_or_default() {
+            log!("  {}", resource);
+        }
+    }
+}
+
+/// Run `\_SB._INI` and then `_INI` of every present device, parents first. The
+/// children of a device that is neither present nor functioning are skipped.
+fn initialize_devices(interpreter: &mut Interpreter) {
+    let system_bus = AmlName::parse("\\_SB").unwrap();
+    run_ini(interpreter, &system_bus);
+
+    let devices: Vec<AmlName> = interpreter
+        .namespace
+        .iter()
+        .filter(|(_, object)| matches!(object, AmlValue::Device))
+        .map(|(path, _)| path.clone())
+        .collect();
+    let mut skipped: Vec<&AmlName> = Vec::new();
+    for device in &devices {
+        if skipped.iter().any(|parent| parent.is_ancestor_of(device)) {
+            continue;
+        }
+        let status = status(interpreter, device);
+        if status & STA_PRESENT != 0 {
+            run_ini(interpreter, device);
+        } else if status & STA_FUNCTIONING == 0 {
+            skipped.push(device);
+        }
+    }
+}
+
+fn run_ini(interpreter: &mut Interpreter, scope: &AmlName) {
+    let ini = scope.child(b"_INI");
+    if interpreter.namespace.contains(&ini) {
+        if let Err(error) = interpreter.evaluate(&ini, Vec::new()) {
+            log_at!(crate::logging::Level::Warn, "{} failed: {}", ini, error);
+        }
+    }
+}
+
+fn status(interpreter: &mut Interpreter, device: &AmlName) -> u64 {
+    let sta = device.child(b"_STA");
+    if !interpreter.namespace.contains(&sta) {
+        return STA_DEFAULT;
+    }
+    match interpreter.evaluate(&sta, Vec::new()).and_then(|value| value.as_integer()) {
+        Ok(status) => status,
+        Err(error) => {
+            log_at!(crate::logging::Level::Warn, "{} failed: {}", sta, error);
+            STA_DEFAULT
+        }
+    }
+}
+
+/// Run `f` with an interpreter on the loaded namespace. The lock is only tried: a
+/// single CPU finding it taken means it is re-entering from a panic mid evaluation.
+fn with_interpreter<T>(f: impl FnOnce(&mut Interpreter) -> Result<T, AmlError>) -> Result<T, AmlError> {
+    let mut guard = NAMESPACE.try_lock().ok_or(AmlError::Unavailable)?;
+    let namespace = guard.as_mut().ok_or(AmlError::Unavailable)?;
+    let revision = dsdt().map(|dsdt| dsdt.revision()).unwrap_or(2);
+    f(&mut Interpreter::new(namespace, revision))
+}
+
+/// Evaluate an object by absolute path, e.g. `\_SB.PCI0._PRT`. Methods are run
+/// with `args`, other objects are read.
+pub fn evaluate(path: &str, args: Vec<AmlValue>) -> Result<AmlValue, AmlError> {
+    let path = AmlName::parse(path).ok_or(AmlError::InvalidName)?;
+    evaluate_name(&path, args)
+}
+
+pub fn evaluate_name(path: &AmlName, args: Vec<AmlValue>) -> Result<AmlValue, AmlError> {
+    with_interpreter(|interpreter| interpreter.evaluate(path, args))
+}
+
+pub fn exists(path: &AmlName) -> bool {
+    with_interpreter(|interpreter| Ok(interpreter.namespace.contains(path))).unwrap_or(false)
+}
+
+/// All devices in the namespace, parents before their children.
+pub fn devices() -> Vec<AmlName> {
+    with_interpreter(|interpreter| {
+        Ok(interpreter
+            .namespace
+            .iter()
+            .filter(|(_, object)| matches!(object, AmlValue::Device))
+            .map(|(path, _)| path.clone())
+            .collect())
+    })
+    .unwrap_or_default()
+}
+
+/// `_STA` of a device, all bits set if it has none.
+pub fn device_status(device: &AmlName) -> u64 {
+    with_interpreter(|interpreter| Ok(status(interpreter, device))).unwrap_or(0)
+}
+
+/// Turn a `_HID` or `_CID` value into a string: compressed EISA ids such as
+/// PNP0A03 are stored as integers.
+fn hardware_id(value: &AmlValue) -> Option<String> {
+    match value {
+        AmlValue::String(id) => Some(id.clone()),
+        AmlValue::Integer(id) => {
+            let id = (*id as u32).swap_bytes();
+            let letter = |shift: u32| (((id >> shift) & 0x1f) as u8 + 0x40) as char;
+            let mut string = String::new();
+            let _ = write!(string, "{}{}{}{:04X}", letter(26), letter(21), letter(16), id & 0xffff);
+            Some(string)
+        }
+        _ => None,
+    }
+}
+
+/// The `_HID` and `_CID` ids of a device.
+pub fn device_ids(device: &AmlName) -> Vec<String> {
+    let mut ids = Vec::new();
+    if let Ok(hid) = evaluate_name(&device.child(b"_HID"), Vec::new()) {
+        ids.extend(hardware_id(&hid));
+    }
+    match evaluate_name(&device.child(b"_CID"), Vec::new()) {
+        Ok(AmlValue::Package(cids)) => ids.extend(cids.iter().filter_map(hardware_id)),
+        Ok(cid) => ids.extend(hardware_id(&cid)),
+        Err(_) => {}
+    }
+    ids
+}
+
+/// Devices with `id` as their `_HID` or one of their `_CID`s, e.g. "PNP0A08".
+pub fn find_devices(id: &str) -> Vec<AmlName> {
+    devices()
+        .into_iter()
+        .filter(|device| device_ids(device).iter().any(|device_id| device_id == id))
+        .collect()
+}
+
+/// A device's current resource settings, from `_CRS`.
+pub fn current_resources(device: &AmlName) -> Result<Vec<Resource>, AmlError> {
+    match evaluate_name(&device.child(b"_CRS"), Vec::new())? {
+        AmlValue::Buffer(buffer) => Ok(resource::parse(&buffer)),
+        _ => Err(AmlError::TypeMismatch),
+    }
+}
+
+/// Where a PCI interrupt pin is routed, from a `_PRT` entry.
+#[derive(Clone, Debug)]
+pub enum PciInterruptSource {
+    /// Hardwired to a global system interrupt.
+    Gsi(u32),
+    /// Through a PCI interrupt link device; `index` selects one of its resources.
+    Link { device: AmlName, index: u32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct PciRoute {
+    pub device: u8,
+    /// 0 to 3 for INTA# to INTD#.
+    pub pin: u8,
+    pub source: PciInterruptSource,
+}
+
+/// The interrupt routing table of a PCI bridge, from `_PRT`.
+pub fn pci_routing(bridge: &AmlName) -> Result<Vec<PciRoute>, AmlError> {
+    let entries = match evaluate_name(&bridge.child(b"_PRT"), Vec::new())? {
+        AmlValue::Package(entries) => entries,
+        _ => return Err(AmlError::TypeMismatch),
+    };
+    let mut routes = Vec::new();
+    for entry in entries {
+        let fields = match entry {
+            AmlValue::Package(fields) if fields.len() >= 4 => fields,
+            _ => return Err(AmlError::TypeMismatch),
+        };
+        let index = fields[3].as_integer()? as u32;
+        let source = match &fields[2] {
+            AmlValue::Reference(Reference::Name(device)) => PciInterruptSource::Link {
+                device: device.clone(),
+                index,
+            },
+            AmlValue::String(path) => PciInterruptSource::Link {
+                device: AmlName::parse(path).ok_or(AmlError::InvalidName)?,
+                index,
+            },
+            _ => PciInterruptSource::Gsi(index),
+        };
+        routes.push(PciRoute {
+            // The address is the device in the high word, the function is always 0xFFFF.
+            device: (fields[0].as_integer()? >> 16) as u8,
+            pin: fields[1].as_integer()? as u8,
+            source,
+        });
+    }
+    Ok(routes)
+}
+
+/// The interrupt a PCI interrupt link device is currently set to.
+pub fn link_interrupt(link: &AmlName, index: u32) -> Option<u32> {
+    let interrupts: Vec<u32> = current_resources(link)
+        .ok()?
+        .into_iter()
+        .filter_map(|resource| match resource {
+            Resource::Irq { irqs, .. } => irqs.first().map(|irq| *irq as u32),
+            Resource::ExtendedIrq { interrupts, .. } => interrupts.first().copied(),
+            _ => None,
+        })
+        .collect();
+    interrupts.get(index as usize).or(interrupts.first()).copied()
+}
+
+/// Every GSI a PCI interrupt pin is routed to, going by the `_PRT` of each bridge
+/// that has one. Pins behind an interrupt link device count with its current
+/// setting. Evaluate after `\_PIC` has selected the IOAPIC model.
+pub fn pci_gsis() -> Vec<u32> {
+    let mut gsis = Vec::new();
+    for bridge in devices().iter().filter(|device| exists(&device.child(b"_PRT"))) {
+        for route in pci_routing(bridge).unwrap_or_default() {
+            let gsi = match route.source {
+                PciInterruptSource::Gsi(gsi) => Some(gsi),
+                PciInterruptSource::Link { device, index } => link_interrupt(&device, index),
+            };
+            if let Some(gsi) = gsi {
+                let pin = (b'A' + route.pin) as char;
+                log_at!(crate::logging::Level::Debug, "{} device {} INT{}# -> GSI {}", bridge, route.device, pin, gsi);
+            }
+            if let Some(gsi) = gsi.filter(|gsi| !gsis.contains(gsi)) {
+                gsis.push(gsi);
+            }
+        }
+    }
+    gsis
+}
@@ -0,0 +1,103 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+/// An absolute path in the ACPI namespace, one four character segment per level.
+/// The root scope `\` is the empty path.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AmlName(Vec<[u8; 4]>);
+
+impl AmlName {
+    pub fn root() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Parse an absolute path such as `\_SB.PCI0._PRT`. Segments shorter than four
+    /// characters are padded with '_', as ASL does.
+    pub fn parse(path: &str) -> Option<Self> {
+        let path = path.strip_prefix('\\')?;
+        if path.is_empty() {
+            return Some(Self::root());
+        }
+        let mut segments = Vec::new();
+        for part in path.split('.') {
+            if part.is_empty() || part.len() > 4 || !part.bytes().all(is_name_char) {
+                return None;
+            }
+            let mut segment = [b'_'; 4];
+            segment[..part.len()].copy_from_slice(part.as_bytes());
+            segments.push(segment);
+        }
+        Some(Self(segments))
+    }
+
+    pub fn child(&self, segment: &[u8; 4]) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(*segment);
+        Self(segments)
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        let (_, parent) = self.0.split_last()?;
+        Some(Self(parent.to_vec()))
+    }
+
+    /// True if `other` lives somewhere below this scope.
+    pub fn is_ancestor_of(&self, other: &AmlName) -> bool {
+        other.0.len() > self.0.len() && other.0.starts_with(&self.0)
+    }
+}
+
+impl fmt::Display for AmlName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\\")?;
+        for (i, segment) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(".")?;
+            }
+            f.write_str(core::str::from_utf8(segment).unwrap_or("????"))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for AmlName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+pub fn is_lead_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c == b'_'
+}
+
+pub fn is_name_char(c: u8) -> bool {
+    is_lead_name_char(c) || c.is_ascii_digit()
+}
+
+/// A NameString as it appears in AML: optionally anchored at the root or a number
+/// of scopes up from the current one.
+#[derive(Clone, Debug)]
+pub struct NameString {
+    pub root: bool,
+    pub parents: usize,
+    pub segments: Vec<[u8; 4]>,
+}
+
+impl NameString {
+    /// The path this name refers to from `scope`, without the search rules.
+    pub fn resolve(&self, scope: &AmlName) -> Option<AmlName> {
+        if self.root {
+            return Some(AmlName(self.segments.clone()));
+        }
+        let depth = scope.0.len().checked_sub(self.parents)?;
+        let mut segments = scope.0[..depth].to_vec();
+        segments.extend_from_slice(&self.segments);
+        Some(AmlName(segments))
+    }
+
+    /// A bare single segment name is searched for in the current scope and then in
+    /// each parent scope up to the root.
+    pub fn uses_search_rules(&self) -> bool {
+        !self.root && self.parents == 0 && self.segments.len() == 1
+    }
+}
@@ -0,0 +1,115 @@
+use super::name::{AmlName, NameString};
+use super::value::AmlValue;
+use super::AmlError;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+
+/// Operating systems `\_OSI` answers true for. Firmware tends to test for Windows
+/// versions before enabling features, so claim the common ones like Linux does.
+const SUPPORTED_INTERFACES: &[&str] = &[
+    "Windows 2000",
+    "Windows 2001",
+    "Windows 2001 SP1",
+    "Windows 2001.1",
+    "Windows 2006",
+    "Windows 2009",
+    "Windows 2012",
+    "Windows 2015",
+    "Module Device",
+    "Processor Device",
+    "3.0 Thermal Model",
+    "Extended Address Space Descriptor",
+];
+
+fn osi(args: &[AmlValue]) -> Result<AmlValue, AmlError> {
+    let interface = args.first().ok_or(AmlError::TypeMismatch)?.as_string()?;
+    let supported = SUPPORTED_INTERFACES.contains(&interface.as_str());
+    Ok(AmlValue::Integer(if supported { u64::MAX } else { 0 }))
+}
+
+/// Every named object, kept in path order so a scope is followed by its children.
+pub struct Namespace {
+    objects: BTreeMap<AmlName, AmlValue>,
+}
+
+impl Namespace {
+    /// A namespace holding the predefined scopes and objects.
+    pub fn new() -> Self {
+        let mut objects = BTreeMap::new();
+        objects.insert(AmlName::root(), AmlValue::Scope);
+        for scope in ["\\_GPE", "\\_PR", "\\_SB", "\\_SI", "\\_TZ"] {
+            objects.insert(AmlName::parse(scope).unwrap(), AmlValue::Scope);
+        }
+        objects.insert(
+            AmlName::parse("\\_OS").unwrap(),
+            AmlValue::String(String::from("Microsoft Windows NT")),
+        );
+        objects.insert(AmlName::parse("\\_REV").unwrap(), AmlValue::Integer(2));
+        objects.insert(
+            AmlName::parse("\\_OSI").unwrap(),
+            AmlValue::NativeMethod {
+                arg_count: 1,
+                function: osi,
+            },
+        );
+        objects.insert(AmlName::parse("\\_GL").unwrap(), AmlValue::Mutex);
+        Self { objects }
+    }
+
+    /// Follow aliases to the object a name stands for.
+    pub fn resolve_alias(&self, name: &AmlName) -> AmlName {
+        let mut name = name.clone();
+        // Bounded, aliases of aliases pointing back at themselves are possible.
+        for _ in 0..8 {
+            match self.objects.get(&name) {
+                Some(AmlValue::Alias(target)) => name = target.clone(),
+                _ => break,
+            }
+        }
+        name
+    }
+
+    pub fn get(&self, name: &AmlName) -> Option<&AmlValue> {
+        self.objects.get(&self.resolve_alias(name))
+    }
+
+    pub fn contains(&self, name: &AmlName) -> bool {
+        self.objects.contains_key(name)
+    }
+
+    /// Add or replace an object.
+    pub fn insert(&mut self, name: AmlName, value: AmlValue) {
+        let name = self.resolve_alias(&name);
+        self.objects.insert(name, value);
+    }
+
+    /// Remove an object and everything defined below it.
+    pub fn remove(&mut self, name: &AmlName) {
+        self.objects.retain(|path, _| path != name && !name.is_ancestor_of(path));
+    }
+
+    /// Find the object a NameString refers to from `scope`, applying the search
+    /// rules for bare names.
+    pub fn search(&self, name: &NameString, scope: &AmlName) -> Option<AmlName> {
+        if !name.uses_search_rules() {
+            return name.resolve(scope).filter(|path| self.contains(path));
+        }
+        let mut scope = Some(scope.clone());
+        while let Some(current) = scope {
+            let path = current.child(&name.segments[0]);
+            if self.contains(&path) {
+                return Some(path);
+            }
+            scope = current.parent();
+        }
+        None
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&AmlName, &AmlValue)> {
+        self.objects.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+}
@@ -0,0 +1,280 @@
+use super::interpreter::Interpreter;
+use super::name::AmlName;
+use super::value::{AmlValue, FieldKind, FieldUnit};
+use super::AmlError;
+use crate::acpi::register::{pci_config_read, pci_config_write};
+use crate::acpi::{AddressSpace, GenericAddress};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Field update rules, what to write to the bits of an access unit that aren't
+/// part of the field.
+const UPDATE_PRESERVE: u8 = 0;
+const UPDATE_WRITE_AS_ONES: u8 = 1;
+
+/// Width in bits of one access to the field's region.
+fn access_width(field: &FieldUnit) -> usize {
+    match field.flags & 0xf {
+        2 => 16,
+        3 => 32,
+        4 => 64,
+        // Byte, buffer and "any" access: bytes always work.
+        _ => 8,
+    }
+}
+
+pub fn extract_bits(bytes: &[u8], bit_offset: usize, bit_length: usize) -> AmlValue {
+    let mut result = vec![0u8; (bit_length + 7) / 8];
+    for bit in 0..bit_length {
+        let source = bit_offset + bit;
+        if bytes.get(source / 8).map_or(false, |byte| byte & (1 << (source % 8)) != 0) {
+            result[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+    if bit_length <= 64 {
+        AmlValue::Buffer(result).as_integer().map_or(AmlValue::Integer(0), AmlValue::Integer)
+    } else {
+        AmlValue::Buffer(result)
+    }
+}
+
+pub fn insert_bits(bytes: &mut [u8], bit_offset: usize, bit_length: usize, data: &[u8]) {
+    for bit in 0..bit_length {
+        let target = bit_offset + bit;
+        if target / 8 >= bytes.len() {
+            break;
+        }
+        let set = data.get(bit / 8).map_or(false, |byte| byte & (1 << (bit % 8)) != 0);
+        if set {
+            bytes[target / 8] |= 1 << (target % 8);
+        } else {
+            bytes[target / 8] &= !(1 << (target % 8));
+        }
+    }
+}
+
+pub fn read_field(interpreter: &mut Interpreter, field: &FieldUnit) -> Result<AmlValue, AmlError> {
+    let width = access_width(field);
+    let first_unit = field.bit_offset / width;
+    let last_unit = (field.bit_offset + field.bit_length + width - 1) / width;
+    let mut bytes = Vec::new();
+    for unit in first_unit..last_unit {
+        let value = read_unit(interpreter, &field.kind, unit * width / 8, width)?;
+        bytes.extend_from_slice(&value.to_le_bytes()[..width / 8]);
+    }
+    Ok(extract_bits(&bytes, field.bit_offset - first_unit * width, field.bit_length))
+}
+
+pub fn write_field(interpreter: &mut Interpreter, field: &FieldUnit, value: AmlValue) -> Result<(), AmlError> {
+    let data = value.as_buffer()?;
+    let width = access_width(field);
+    let field_end = field.bit_offset + field.bit_length;
+    let first_unit = field.bit_offset / width;
+    let last_unit = (field_end + width - 1) / width;
+    for unit in first_unit..last_unit {
+        let unit_start = unit * width;
+        let start = field.bit_offset.max(unit_start);
+        let end = field_end.min(unit_start + width);
+
+        let mut raw = if end - start == width {
+            0
+        } else {
+            match (field.flags >> 5) & 0x3 {
+                UPDATE_PRESERVE => read_unit(interpreter, &field.kind, unit_start / 8, width)?,
+                UPDATE_WRITE_AS_ONES => u64::MAX,
+                _ => 0,
+            }
+        }
+        .to_le_bytes();
+
+        // The part of the value that lands in this unit.
+        let bits = extract_bits(&data, start - field.bit_offset, end - start).as_buffer()?;
+        insert_bits(&mut raw[..width / 8], start - unit_start, end - start, &bits);
+        write_unit(interpreter, &field.kind, unit_start / 8, width, u64::from_le_bytes(raw))?;
+    }
+    Ok(())
+}
+
+fn read_unit(interpreter: &mut Interpreter, kind: &FieldKind, offset: usize, width: usize) -> Result<u64, AmlError> {
+    match kind {
+        FieldKind::Normal { region } => access_region(interpreter, region, offset, width, None),
+        FieldKind::Bank { region, bank, value } => {
+            interpreter.write_name(bank, AmlValue::Integer(*value))?;
+            access_region(interpreter, region, offset, width, None)
+        }
+        FieldKind::Index { index, data } => {
+            interpreter.write_name(index, AmlValue::Integer(offset as u64))?;
+            interpreter.read_name(data)?.as_integer()
+        }
+    }
+}
+
+fn write_unit(interpreter: &mut Interpreter, kind: &FieldKind, offset: usize, width: usize, value: u64) -> Result<(), AmlError> {
+    match kind {
+        FieldKind::Normal { region } => access_region(interpreter, region, offset, width, Some(value)).map(|_| ()),
+        FieldKind::Bank { region, bank, value: bank_value } => {
+            interpreter.write_name(bank, AmlValue::Integer(*bank_value))?;
+            access_region(interpreter, region, offset, width, Some(value)).map(|_| ())
+        }
+        FieldKind::Index { index, data } => {
+            interpreter.write_name(index, AmlValue::Integer(offset as u64))?;
+            interpreter.write_name(data, AmlValue::Integer(value))
+        }
+    }
+}
+
+/// Read, or write if `value` is given, `width` bits at `offset` into an operation region.
+fn access_region(
+    interpreter: &mut Interpreter,
+    path: &AmlName,
+    offset: usize,
+    width: usize,
+    value: Option<u64>,
+) -> Result<u64, AmlError> {
+    let region = match interpreter.namespace.get(path) {
+        Some(AmlValue::OpRegion(region)) => *region,
+        _ => return Err(AmlError::TypeMismatch),
+    };
+    if (offset + width / 8) as u64 > region.length {
+        return Err(AmlError::IndexOutOfBounds);
+    }
+    let address = region.offset + offset as u64;
+    match region.space {
+        AddressSpace::SystemMemory | AddressSpace::SystemIo => {
+            Ok(access_register(region.space, address, width, value))
+        }
+        AddressSpace::PciConfig => {
+            let (segment, bus, device, function) = pci_location(interpreter, path)?;
+            pci_config(segment, bus, device, function, address, width, value)
+        }
+        AddressSpace::Other(space) => Err(AmlError::UnsupportedRegion(space)),
+    }
+}
+
+fn access_register(space: AddressSpace, address: u64, width: usize, value: Option<u64>) -> u64 {
+    let register = GenericAddress {
+        address_space: space,
+        bit_width: width as u8,
+        bit_offset: 0,
+        access_size: match width {
+            8 => 1,
+            16 => 2,
+            32 => 3,
+            _ => 4,
+        },
+        address,
+    };
+    match value {
+        Some(value) => {
+            register.write(value);
+            0
+        }
+        None => register.read(),
+    }
+}
+
+/// Evaluate an optional integer object such as `_ADR` or `_BBN`.
+fn optional_integer(interpreter: &mut Interpreter, path: AmlName) -> Result<Option<u64>, AmlError> {
+    if !interpreter.namespace.contains(&path) {
+        return Ok(None);
+    }
+    Ok(Some(interpreter.evaluate(&path, Vec::new())?.as_integer()?))
+}
+
+/// The PCI function a configuration space region belongs to: `_ADR` of the device
+/// it is declared in, and `_BBN`/`_SEG` of the host bridge above that. Bridges
+/// in between aren't followed, the bus is the host bridge's.
+fn pci_location(interpreter: &mut Interpreter, region: &AmlName) -> Result<(u16, u8, u8, u8), AmlError> {
+    let mut address = None;
+    let mut bus = None;
+    let mut segment = None;
+    let mut scope = region.parent();
+    while let Some(current) = scope {
+        if address.is_none() {
+            address = optional_integer(interpreter, current.child(b"_ADR"))?;
+        }
+        if bus.is_none() {
+            bus = optional_integer(interpreter, current.child(b"_BBN"))?;
+        }
+        if segment.is_none() {
+            segment = optional_integer(interpreter, current.child(b"_SEG"))?;
+        }
+        scope = current.parent();
+    }
+    let address = address.unwrap_or(0);
+    Ok((
+        segment.unwrap_or(0) as u16,
+        bus.unwrap_or(0) as u8,
+        (address >> 16) as u8,
+        address as u8,
+    ))
+}
+
+fn pci_config(
+    segment: u16,
+    bus: u8,
+    device: u8,
+    function: u8,
+    offset: u64,
+    width: usize,
+    value: Option<u64>,
+) -> Result<u64, AmlError> {
+    if segment == 0 && offset + (width / 8) as u64 <= 256 {
+        return Ok(match value {
+            Some(value) => {
+                pci_config_write(bus, device, function, offset as u8, width as u8, value);
+                0
+            }
+            None => pci_config_read(bus, device, function, offset as u8, width as u8),
+        });
+    }
+    // Extended configuration space needs the memory mapped ECAM window.
+    let address = crate::acpi::mcfg()
+        .and_then(|mcfg| {
+            mcfg.windows()
+                .filter(|window| window.segment == segment)
+                .find_map(|window| window.config_address(bus, device, function))
+        })
+        .ok_or(AmlError::Unsupported("PCI configuration space without ECAM"))?;
+    Ok(access_register(AddressSpace::SystemMemory, address + offset, width, value))
+}
+
+/// Busy wait for the Stall operator, on PIT channel 2.
+pub fn stall(microseconds: u64) {
+    crate::arch::pit::delay_us(microseconds);
+}
+
+/// Busy wait for the Sleep operator. Nothing else can run meanwhile, so it spins
+/// like Stall.
+pub fn sleep(milliseconds: u64) {
+    crate::arch::pit::delay_ms(milliseconds);
+}
+
+/// ACPI PM timer frequency in Hz.
+const PM_TIMER_FREQUENCY: u128 = 3_579_545;
+
+/// Last PM timer reading, extended to 64 bits.
+static PM_TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Monotonic time in 100ns units, for the Timer operator. Based on the ACPI PM
+/// timer, which wraps after a few seconds: readings are extended to 64 bits, so
+/// it has to be read at least that often to stay monotonic.
+pub fn timer() -> u64 {
+    let fadt = match crate::acpi::fadt() {
+        Some(fadt) => fadt,
+        None => return 0,
+    };
+    let block = match fadt.pm_timer_block() {
+        Some(block) => block,
+        None => return 0,
+    };
+    let mask: u64 = if fadt.pm_timer_is_32bit() { u32::MAX as u64 } else { 0xff_ffff };
+    let last = PM_TIMER_TICKS.load(Ordering::Relaxed);
+    let mut ticks = (last & !mask) | (block.read() & mask);
+    if ticks < last {
+        ticks += mask + 1;
+    }
+    PM_TIMER_TICKS.store(ticks, Ordering::Relaxed);
+    (ticks as u128 * 10_000_000 / PM_TIMER_FREQUENCY) as u64
+}
@@ -0,0 +1,258 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A decoded resource descriptor from a `_CRS` buffer.
+#[derive(Clone, Debug)]
+pub enum Resource {
+    Irq {
+        irqs: Vec<u8>,
+        edge_triggered: bool,
+        active_low: bool,
+        shared: bool,
+    },
+    Dma {
+        channels: Vec<u8>,
+    },
+    Io {
+        minimum: u16,
+        maximum: u16,
+        alignment: u8,
+        length: u8,
+    },
+    FixedIo {
+        base: u16,
+        length: u8,
+    },
+    Memory32 {
+        minimum: u32,
+        maximum: u32,
+        alignment: u32,
+        length: u32,
+        writeable: bool,
+    },
+    FixedMemory32 {
+        base: u32,
+        length: u32,
+        writeable: bool,
+    },
+    /// Word, DWord and QWord address space descriptors. `resource_type` is 0 for
+    /// memory, 1 for IO and 2 for a bus number range.
+    AddressSpace {
+        resource_type: u8,
+        minimum: u64,
+        maximum: u64,
+        translation: u64,
+        length: u64,
+    },
+    ExtendedIrq {
+        interrupts: Vec<u32>,
+        edge_triggered: bool,
+        active_low: bool,
+        shared: bool,
+    },
+    Other {
+        tag: u8,
+    },
+}
+
+fn interrupt_mode(f: &mut fmt::Formatter, edge_triggered: bool, active_low: bool, shared: bool) -> fmt::Result {
+    let trigger = if edge_triggered { "edge" } else { "level" };
+    let polarity = if active_low { "low" } else { "high" };
+    write!(f, " {} active {}{}", trigger, polarity, if shared { " shared" } else { "" })
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::Irq {
+                irqs,
+                edge_triggered,
+                active_low,
+                shared,
+            } => {
+                write!(f, "IRQ {:?}", irqs)?;
+                interrupt_mode(f, *edge_triggered, *active_low, *shared)
+            }
+            Resource::Dma { channels } => write!(f, "DMA {:?}", channels),
+            Resource::Io {
+                minimum,
+                maximum,
+                alignment,
+                length,
+            } => write!(f, "IO 0x{:x}-0x{:x} align 0x{:x} length 0x{:x}", minimum, maximum, alignment, length),
+            Resource::FixedIo { base, length } => write!(f, "IO 0x{:x} length 0x{:x}", base, length),
+            Resource::Memory32 {
+                minimum,
+                maximum,
+                alignment,
+                length,
+                writeable,
+            } => write!(
+                f,
+                "memory 0x{:x}-0x{:x} align 0x{:x} length 0x{:x}{}",
+                minimum,
+                maximum,
+                alignment,
+                length,
+                if *writeable { "" } else { " read-only" }
+            ),
+            Resource::FixedMemory32 { base, length, writeable } => write!(
+                f,
+                "memory 0x{:x} length 0x{:x}{}",
+                base,
+                length,
+                if *writeable { "" } else { " read-only" }
+            ),
+            Resource::AddressSpace {
+                resource_type,
+                minimum,
+                maximum,
+                translation,
+                length,
+            } => {
+                let space = match resource_type {
+                    0 => "memory",
+                    1 => "IO",
+                    2 => "bus",
+                    _ => "other",
+                };
+                write!(f, "{} window 0x{:x}-0x{:x} length 0x{:x}", space, minimum, maximum, length)?;
+                if *translation != 0 {
+                    write!(f, " translation 0x{:x}", translation)?;
+                }
+                Ok(())
+            }
+            Resource::ExtendedIrq {
+                interrupts,
+                edge_triggered,
+                active_low,
+                shared,
+            } => {
+                write!(f, "interrupts {:?}", interrupts)?;
+                interrupt_mode(f, *edge_triggered, *active_low, *shared)
+            }
+            Resource::Other { tag } => write!(f, "descriptor 0x{:02x}", tag),
+        }
+    }
+}
+
+const SMALL_IRQ: u8 = 0x04;
+const SMALL_DMA: u8 = 0x05;
+const SMALL_IO: u8 = 0x08;
+const SMALL_FIXED_IO: u8 = 0x09;
+const SMALL_END_TAG: u8 = 0x0F;
+const LARGE_MEMORY32: u8 = 0x05;
+const LARGE_FIXED_MEMORY32: u8 = 0x06;
+const LARGE_DWORD_ADDRESS_SPACE: u8 = 0x07;
+const LARGE_WORD_ADDRESS_SPACE: u8 = 0x08;
+const LARGE_EXTENDED_IRQ: u8 = 0x09;
+const LARGE_QWORD_ADDRESS_SPACE: u8 = 0x0A;
+
+fn read(bytes: &[u8], offset: usize, size: usize) -> u64 {
+    let mut value = [0u8; 8];
+    if let Some(field) = bytes.get(offset..offset + size) {
+        value[..size].copy_from_slice(field);
+    }
+    u64::from_le_bytes(value)
+}
+
+fn bits_set(mask: u64, count: u8) -> Vec<u8> {
+    (0..count).filter(|bit| mask & (1 << bit) != 0).collect()
+}
+
+/// Decode a resource template, up to its end tag.
+pub fn parse(buffer: &[u8]) -> Vec<Resource> {
+    let mut resources = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let tag = buffer[offset];
+        if tag & 0x80 == 0 {
+            // Small descriptor: type in bits 3-6, length in bits 0-2.
+            let length = (tag & 0x7) as usize;
+            let body = match buffer.get(offset + 1..offset + 1 + length) {
+                Some(body) => body,
+                None => break,
+            };
+            offset += 1 + length;
+            let resource = match (tag >> 3) & 0xf {
+                SMALL_END_TAG => break,
+                SMALL_IRQ => {
+                    // Without the optional flags byte the IRQ is edge triggered, active high.
+                    let flags = if length >= 3 { body[2] } else { 0x01 };
+                    Resource::Irq {
+                        irqs: bits_set(read(body, 0, 2), 16),
+                        edge_triggered: flags & 0x01 != 0,
+                        active_low: flags & 0x08 != 0,
+                        shared: flags & 0x10 != 0,
+                    }
+                }
+                SMALL_DMA => Resource::Dma {
+                    channels: bits_set(read(body, 0, 1), 8),
+                },
+                SMALL_IO => Resource::Io {
+                    minimum: read(body, 1, 2) as u16,
+                    maximum: read(body, 3, 2) as u16,
+                    alignment: read(body, 5, 1) as u8,
+                    length: read(body, 6, 1) as u8,
+                },
+                SMALL_FIXED_IO => Resource::FixedIo {
+                    base: read(body, 0, 2) as u16 & 0x3ff,
+                    length: read(body, 2, 1) as u8,
+                },
+                other => Resource::Other { tag: other },
+            };
+            resources.push(resource);
+        } else {
+            // Large descriptor: type in bits 0-6, then a 16-bit length.
+            let length = read(buffer, offset + 1, 2) as usize;
+            let body = match buffer.get(offset + 3..offset + 3 + length) {
+                Some(body) => body,
+                None => break,
+            };
+            offset += 3 + length;
+            let resource = match tag & 0x7f {
+                LARGE_MEMORY32 => Resource::Memory32 {
+                    writeable: read(body, 0, 1) & 1 != 0,
+                    minimum: read(body, 1, 4) as u32,
+                    maximum: read(body, 5, 4) as u32,
+                    alignment: read(body, 9, 4) as u32,
+                    length: read(body, 13, 4) as u32,
+                },
+                LARGE_FIXED_MEMORY32 => Resource::FixedMemory32 {
+                    writeable: read(body, 0, 1) & 1 != 0,
+                    base: read(body, 1, 4) as u32,
+                    length: read(body, 5, 4) as u32,
+                },
+                kind @ (LARGE_WORD_ADDRESS_SPACE | LARGE_DWORD_ADDRESS_SPACE | LARGE_QWORD_ADDRESS_SPACE) => {
+                    // Granularity, minimum, maximum, translation and length follow the
+                    // three flag bytes, each the descriptor's word size.
+                    let size = match kind {
+                        LARGE_WORD_ADDRESS_SPACE => 2,
+                        LARGE_DWORD_ADDRESS_SPACE => 4,
+                        _ => 8,
+                    };
+                    Resource::AddressSpace {
+                        resource_type: read(body, 0, 1) as u8,
+                        minimum: read(body, 3 + size, size),
+                        maximum: read(body, 3 + 2 * size, size),
+                        translation: read(body, 3 + 3 * size, size),
+                        length: read(body, 3 + 4 * size, size),
+                    }
+                }
+                LARGE_EXTENDED_IRQ => {
+                    let flags = read(body, 0, 1);
+                    let count = read(body, 1, 1) as usize;
+                    Resource::ExtendedIrq {
+                        interrupts: (0..count).map(|i| read(body, 2 + 4 * i, 4) as u32).collect(),
+                        edge_triggered: flags & 0x02 != 0,
+                        active_low: flags & 0x04 != 0,
+                        shared: flags & 0x08 != 0,
+                    }
+                }
+                other => Resource::Other { tag: 0x80 | other },
+            };
+            resources.push(resource);
+        }
+    }
+    resources
+}
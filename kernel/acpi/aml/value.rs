@@ -0,0 +1,179 @@
+use super::name::AmlName;
+use super::AmlError;
+use crate::acpi::AddressSpace;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// An object in the namespace, or a value computed while running AML.
+#[derive(Clone, Debug)]
+pub enum AmlValue {
+    Uninitialized,
+    Integer(u64),
+    String(String),
+    Buffer(Vec<u8>),
+    Package(Vec<AmlValue>),
+    Reference(Reference),
+    Method(Method),
+    /// A method implemented by the kernel, such as `\_OSI`.
+    NativeMethod {
+        arg_count: usize,
+        function: fn(&[AmlValue]) -> Result<AmlValue, AmlError>,
+    },
+    OpRegion(OpRegion),
+    Field(FieldUnit),
+    BufferField {
+        source: Reference,
+        bit_offset: usize,
+        bit_length: usize,
+    },
+    Device,
+    Processor,
+    PowerResource,
+    ThermalZone,
+    Scope,
+    Mutex,
+    Event,
+    Alias(AmlName),
+}
+
+/// Where a value can be read from or stored to: a local or argument of the running
+/// method, a named object or an element inside one of those.
+#[derive(Clone, Debug)]
+pub enum Reference {
+    Null,
+    Local(usize),
+    Arg(usize),
+    Name(AmlName),
+    Debug,
+    Index(Box<Reference>, usize),
+    /// A temporary, stores to it are dropped.
+    Value(Box<AmlValue>),
+}
+
+/// The serialize flag isn't kept: methods only run with the namespace locked.
+#[derive(Clone, Copy, Debug)]
+pub struct Method {
+    pub code: &'static [u8],
+    pub arg_count: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OpRegion {
+    pub space: AddressSpace,
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Clone, Debug)]
+pub enum FieldKind {
+    Normal { region: AmlName },
+    /// Accessed by writing the byte offset to `index` and then using `data`.
+    Index { index: AmlName, data: AmlName },
+    /// `bank` is set to `value` before `region` is accessed.
+    Bank { region: AmlName, bank: AmlName, value: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldUnit {
+    pub kind: FieldKind,
+    pub bit_offset: usize,
+    pub bit_length: usize,
+    /// Access type in bits 0-3, lock rule in bit 4, update rule in bits 5-6.
+    pub flags: u8,
+}
+
+impl AmlValue {
+    /// The object type number used by the ObjectType operator.
+    pub fn object_type(&self) -> u64 {
+        match self {
+            AmlValue::Uninitialized | AmlValue::Scope | AmlValue::Alias(_) => 0,
+            AmlValue::Integer(_) => 1,
+            AmlValue::String(_) => 2,
+            AmlValue::Buffer(_) => 3,
+            AmlValue::Package(_) => 4,
+            AmlValue::Field(_) => 5,
+            AmlValue::Device => 6,
+            AmlValue::Event => 7,
+            AmlValue::Method(_) | AmlValue::NativeMethod { .. } => 8,
+            AmlValue::Mutex => 9,
+            AmlValue::OpRegion(_) => 10,
+            AmlValue::PowerResource => 11,
+            AmlValue::Processor => 12,
+            AmlValue::ThermalZone => 13,
+            AmlValue::BufferField { .. } => 14,
+            AmlValue::Reference(_) => 16,
+        }
+    }
+
+    pub fn as_integer(&self) -> Result<u64, AmlError> {
+        match self {
+            AmlValue::Integer(value) => Ok(*value),
+            AmlValue::Buffer(bytes) => {
+                let mut value = [0u8; 8];
+                let length = bytes.len().min(8);
+                value[..length].copy_from_slice(&bytes[..length]);
+                Ok(u64::from_le_bytes(value))
+            }
+            // Implicit conversion reads hex digits up to the first other character.
+            AmlValue::String(string) => {
+                let digits = string.trim_start_matches("0x").trim_start_matches("0X");
+                Ok(digits
+                    .chars()
+                    .map_while(|c| c.to_digit(16))
+                    .fold(0u64, |value, digit| value.wrapping_shl(4) | digit as u64))
+            }
+            _ => Err(AmlError::TypeMismatch),
+        }
+    }
+
+    pub fn as_buffer(&self) -> Result<Vec<u8>, AmlError> {
+        match self {
+            AmlValue::Buffer(bytes) => Ok(bytes.clone()),
+            AmlValue::Integer(value) => Ok(value.to_le_bytes().to_vec()),
+            AmlValue::String(string) => {
+                let mut bytes = string.as_bytes().to_vec();
+                bytes.push(0);
+                Ok(bytes)
+            }
+            _ => Err(AmlError::TypeMismatch),
+        }
+    }
+
+    pub fn as_string(&self) -> Result<String, AmlError> {
+        match self {
+            AmlValue::String(string) => Ok(string.clone()),
+            AmlValue::Integer(value) => {
+                let mut string = String::new();
+                let _ = write!(string, "{:016X}", value);
+                Ok(string)
+            }
+            AmlValue::Buffer(bytes) => {
+                let mut string = String::new();
+                for (i, byte) in bytes.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { " " };
+                    let _ = write!(string, "{}{:02X}", separator, byte);
+                }
+                Ok(string)
+            }
+            _ => Err(AmlError::TypeMismatch),
+        }
+    }
+
+    /// Convert to the same type as `like`, used when storing into an existing
+    /// named Integer, String or Buffer.
+    pub fn convert_like(&self, like: &AmlValue, ones: u64) -> Result<AmlValue, AmlError> {
+        Ok(match like {
+            AmlValue::Integer(_) => AmlValue::Integer(self.as_integer()? & ones),
+            AmlValue::String(_) => AmlValue::String(self.as_string()?),
+            // A buffer keeps its size, the new contents are truncated or zero padded.
+            AmlValue::Buffer(old) => {
+                let mut bytes = self.as_buffer()?;
+                bytes.resize(old.len(), 0);
+                AmlValue::Buffer(bytes)
+            }
+            _ => self.clone(),
+        })
+    }
+}
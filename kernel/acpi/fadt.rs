@@ -0,0 +1,87 @@
+use super::{GenericAddress, Sdt};
+
+/// Fixed ACPI Description Table, signature "FACP". Holds the fixed hardware
+/// registers: PM1 event/control blocks used for sleep states, the PM timer and the
+/// reset register. ACPI 2.0 added 64-bit (X_) versions of the register fields,
+/// those win over the legacy 32-bit port fields when present.
+#[derive(Clone, Copy)]
+pub struct Fadt(Sdt);
+
+/// FADT flags bit: the reset register is supported.
+const RESET_REG_SUP: u32 = 1 << 10;
+/// FADT flags bit: the PM timer is 32 bits wide instead of 24.
+const TMR_VAL_EXT: u32 = 1 << 8;
+
+impl Fadt {
+    pub fn new(sdt: Sdt) -> Self {
+        Self(sdt)
+    }
+
+    /// Physical address of the DSDT.
+    pub fn dsdt_address(&self) -> Option<u64> {
+        match self.0.read_u64(140) {
+            Some(address) if address != 0 => Some(address),
+            _ => self.0.read_u32(40).filter(|a| *a != 0).map(|a| a as u64),
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.0.read_u32(112).unwrap_or(0)
+    }
+
+    /// The interrupt the SCI is wired to, as an ISA IRQ number.
+    pub fn sci_interrupt(&self) -> u16 {
+        self.0.read_u16(46).unwrap_or(0)
+    }
+
+    /// Port to write `acpi_enable()` to in order to switch the firmware into ACPI
+    /// mode. Zero means the system is always in ACPI mode.
+    pub fn smi_command_port(&self) -> u32 {
+        self.0.read_u32(48).unwrap_or(0)
+    }
+
+    pub fn acpi_enable(&self) -> u8 {
+        self.0.read_u8(52).unwrap_or(0)
+    }
+
+    fn register(&self, extended_offset: usize, legacy_offset: usize, length_offset: usize) -> Option<GenericAddress> {
+        self.0.read_address(extended_offset).or_else(|| {
+            GenericAddress::io_port(self.0.read_u32(legacy_offset)?, self.0.read_u8(length_offset)?)
+        })
+    }
+
+    pub fn pm1a_control_block(&self) -> Option<GenericAddress> {
+        self.register(172, 64, 89)
+    }
+
+    pub fn pm1b_control_block(&self) -> Option<GenericAddress> {
+        self.register(184, 68, 89)
+    }
+
+    pub fn pm_timer_block(&self) -> Option<GenericAddress> {
+        self.register(208, 76, 91)
+    }
+
+    pub fn pm_timer_is_32bit(&self) -> bool {
+        self.flags() & TMR_VAL_EXT != 0
+    }
+
+    /// The reset register and the value to write to it, if the firmware supports it.
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        if self.flags() & RESET_REG_SUP == 0 {
+            return None;
+        }
+        Some((self.0.read_address(116)?, self.0.read_u8(128)?))
+    }
+
+    /// RTC CMOS index of the century register, 0 if there is none.
+    pub fn century_register(&self) -> u8 {
+        self.0.read_u8(108).unwrap_or(0)
+    }
+
+    /// IA-PC boot architecture flags. Bit 0: legacy devices, bit 1: 8042 keyboard
+    /// controller, bit 4: MSIs must not be enabled, bit 5: no CMOS RTC.
+    pub fn boot_architecture_flags(&self) -> u16 {
+        self.0.read_u16(109).unwrap_or(0)
+    }
+}
@@ -0,0 +1,42 @@
+use super::{GenericAddress, Sdt};
+
+/// High Precision Event Timer description table, signature "HPET".
+#[derive(Clone, Copy)]
+pub struct Hpet(Sdt);
+
+impl Hpet {
+    pub fn new(sdt: Sdt) -> Self {
+        Self(sdt)
+    }
+
+    /// Copy of the HPET's general capabilities register, bits 0-31.
+    pub fn event_timer_block_id(&self) -> u32 {
+        self.0.read_u32(36).unwrap_or(0)
+    }
+
+    /// Where the HPET registers are mapped, always system memory in practice.
+    pub fn base_address(&self) -> Option<GenericAddress> {
+        self.0.read_address(40)
+    }
+
+    pub fn hpet_number(&self) -> u8 {
+        self.0.read_u8(52).unwrap_or(0)
+    }
+
+    /// Minimum number of main counter ticks for a periodic timer without lost interrupts.
+    pub fn minimum_tick(&self) -> u16 {
+        self.0.read_u16(53).unwrap_or(0)
+    }
+
+    pub fn comparator_count(&self) -> u8 {
+        ((self.event_timer_block_id() >> 8) & 0x1f) as u8 + 1
+    }
+
+    pub fn counter_is_64bit(&self) -> bool {
+        self.event_timer_block_id() & (1 << 13) != 0
+    }
+
+    pub fn pci_vendor_id(&self) -> u16 {
+        (self.event_timer_block_id() >> 16) as u16
+    }
+}
@@ -0,0 +1,146 @@
+use super::Sdt;
+
+/// Multiple APIC Description Table, signature "APIC". Lists the interrupt
+/// controllers: one local APIC per CPU, the IOAPICs and how legacy IRQs map onto
+/// global system interrupts (GSIs).
+#[derive(Clone, Copy)]
+pub struct Madt(Sdt);
+
+#[derive(Clone, Copy, Debug)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_id: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    /// An ISA IRQ that isn't identity mapped to a GSI, or has non-default polarity
+    /// or trigger mode. `flags` bits 0-1 are the polarity, bits 2-3 the trigger mode.
+    InterruptOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    /// Which LINT pin of a CPU's local APIC the NMI is wired to. A processor id of
+    /// 0xff means all processors.
+    LocalApicNmi {
+        processor_id: u8,
+        flags: u16,
+        lint: u8,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        flags: u32,
+        processor_uid: u32,
+    },
+    Other {
+        entry_type: u8,
+    },
+}
+
+/// Local APIC flags: the CPU is enabled, or can be brought online later.
+const LAPIC_ENABLED: u32 = 1 << 0;
+const LAPIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+impl MadtEntry {
+    /// True for local (x2)APIC entries of processors that are, or can be, online.
+    pub fn is_usable_processor(&self) -> bool {
+        match self {
+            MadtEntry::LocalApic { flags, .. } | MadtEntry::LocalX2Apic { flags, .. } => {
+                flags & (LAPIC_ENABLED | LAPIC_ONLINE_CAPABLE) != 0
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Madt {
+    pub fn new(sdt: Sdt) -> Self {
+        Self(sdt)
+    }
+
+    /// Physical address of the local APIC, taking a 64-bit override entry into account.
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or(self.0.read_u32(36).unwrap_or(0) as u64)
+    }
+
+    /// True if the system also has dual 8259 PICs, which must be masked when the
+    /// APICs are used.
+    pub fn has_legacy_pics(&self) -> bool {
+        self.0.read_u32(40).unwrap_or(0) & 1 != 0
+    }
+
+    pub fn entries(&self) -> MadtIter {
+        MadtIter {
+            madt: self.0,
+            offset: 44,
+        }
+    }
+}
+
+pub struct MadtIter {
+    madt: Sdt,
+    offset: usize,
+}
+
+impl Iterator for MadtIter {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry_type = self.madt.read_u8(self.offset)?;
+        let length = self.madt.read_u8(self.offset + 1)? as usize;
+        if length < 2 || self.offset + length > self.madt.length() {
+            return None;
+        }
+        let base = self.offset;
+        self.offset += length;
+
+        let madt = &self.madt;
+        let entry = match entry_type {
+            0 => MadtEntry::LocalApic {
+                processor_id: madt.read_u8(base + 2)?,
+                apic_id: madt.read_u8(base + 3)?,
+                flags: madt.read_u32(base + 4)?,
+            },
+            1 => MadtEntry::IoApic {
+                id: madt.read_u8(base + 2)?,
+                address: madt.read_u32(base + 4)?,
+                gsi_base: madt.read_u32(base + 8)?,
+            },
+            2 => MadtEntry::InterruptOverride {
+                bus: madt.read_u8(base + 2)?,
+                source: madt.read_u8(base + 3)?,
+                gsi: madt.read_u32(base + 4)?,
+                flags: madt.read_u16(base + 8)?,
+            },
+            4 => MadtEntry::LocalApicNmi {
+                processor_id: madt.read_u8(base + 2)?,
+                flags: madt.read_u16(base + 3)?,
+                lint: madt.read_u8(base + 5)?,
+            },
+            5 => MadtEntry::LocalApicAddressOverride {
+                address: madt.read_u64(base + 4)?,
+            },
+            9 => MadtEntry::LocalX2Apic {
+                x2apic_id: madt.read_u32(base + 4)?,
+                flags: madt.read_u32(base + 8)?,
+                processor_uid: madt.read_u32(base + 12)?,
+            },
+            entry_type => MadtEntry::Other { entry_type },
+        };
+        Some(entry)
+    }
+}
@@ -0,0 +1,50 @@
+use super::Sdt;
+
+/// PCI Express memory mapped configuration table, signature "MCFG". Lists the
+/// ECAM windows through which PCI configuration space can be accessed.
+#[derive(Clone, Copy)]
+pub struct Mcfg(Sdt);
+
+/// One ECAM window, covering buses `start_bus..=end_bus` of a PCI segment group.
+#[derive(Clone, Copy, Debug)]
+pub struct EcamWindow {
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl EcamWindow {
+    /// Physical address of the 4 KiB configuration space of a PCI function.
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<u64> {
+        if bus < self.start_bus || bus > self.end_bus || device >= 32 || function >= 8 {
+            return None;
+        }
+        let offset = ((bus - self.start_bus) as u64) << 20 | (device as u64) << 15 | (function as u64) << 12;
+        Some(self.base_address + offset)
+    }
+}
+
+// Entries start after the header and 8 reserved bytes.
+const ENTRIES_OFFSET: usize = 44;
+const ENTRY_SIZE: usize = 16;
+
+impl Mcfg {
+    pub fn new(sdt: Sdt) -> Self {
+        Self(sdt)
+    }
+
+    pub fn windows(&self) -> impl Iterator<Item = EcamWindow> {
+        let mcfg = self.0;
+        (ENTRIES_OFFSET..mcfg.length())
+            .step_by(ENTRY_SIZE)
+            .filter_map(move |offset| {
+                Some(EcamWindow {
+                    base_address: mcfg.read_u64(offset)?,
+                    segment: mcfg.read_u16(offset + 8)?,
+                    start_bus: mcfg.read_u8(offset + 10)?,
+                    end_bus: mcfg.read_u8(offset + 11)?,
+                })
+            })
+    }
+}
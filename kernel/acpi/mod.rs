@@ -0,0 +1,418 @@
+pub mod aml;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+mod register;
+mod sleep;
+
+use crate::arch::memory::{free_physical_range, map_physical_region, unmap_physical_region, PAGE_SIZE};
+use crate::boot_info::BootInfo;
+use crate::memory::addr::PhysicalAddress;
+use crate::multiboot::MMapEntryType;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use fadt::Fadt;
+use hpet::Hpet;
+use madt::Madt;
+use mcfg::Mcfg;
+use spin::Mutex;
+
+pub use sleep::sleep_type;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const SDT_HEADER_SIZE: usize = 36;
+/// Real mode pointer to the Extended BIOS Data Area, in the BIOS Data Area.
+const EBDA_POINTER: usize = 0x40E;
+const BIOS_AREA_START: usize = 0xE0000;
+const BIOS_AREA_END: usize = 0x100000;
+
+/// Copies of the ACPI tables. They're copied to the heap so ACPI reclaimable memory
+/// can be handed back to the frame allocator once they've been found.
+struct AcpiTables {
+    tables: Vec<Sdt>,
+}
+
+static TABLES: Mutex<Option<AcpiTables>> = Mutex::new(None);
+
+/// A System Description Table: the common 36 byte header followed by the table body.
+#[derive(Clone, Copy)]
+pub struct Sdt {
+    data: &'static [u8],
+}
+
+impl Sdt {
+    pub fn signature(&self) -> &'static str {
+        core::str::from_utf8(&self.data[0..4]).unwrap_or("????")
+    }
+
+    pub fn length(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.data[8]
+    }
+
+    pub fn oem_id(&self) -> &'static str {
+        core::str::from_utf8(&self.data[10..16])
+            .unwrap_or("")
+            .trim_end_matches(|c| c == ' ' || c == '\0')
+    }
+
+    /// The whole table, header included.
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+
+    fn read_u8(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    fn read_u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_u64(&self, offset: usize) -> Option<u64> {
+        let bytes = self.data.get(offset..offset + 8)?;
+        let mut value = [0u8; 8];
+        value.copy_from_slice(bytes);
+        Some(u64::from_le_bytes(value))
+    }
+
+    fn read_address(&self, offset: usize) -> Option<GenericAddress> {
+        GenericAddress::parse(self.data.get(offset..offset + 12)?)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    Other(u8),
+}
+
+/// ACPI Generic Address Structure, describes where a register lives.
+#[derive(Clone, Copy, Debug)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let mut address = [0u8; 8];
+        address.copy_from_slice(&bytes[4..12]);
+        let address = u64::from_le_bytes(address);
+        if address == 0 {
+            return None;
+        }
+
+        Some(Self {
+            address_space: match bytes[0] {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfig,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[1],
+            bit_offset: bytes[2],
+            access_size: bytes[3],
+            address,
+        })
+    }
+
+    /// An IO port block given by one of the legacy 32-bit FADT fields.
+    fn io_port(port: u32, length: u8) -> Option<Self> {
+        if port == 0 {
+            return None;
+        }
+        Some(Self {
+            address_space: AddressSpace::SystemIo,
+            bit_width: length * 8,
+            bit_offset: 0,
+            access_size: 0,
+            address: port as u64,
+        })
+    }
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// Copy `length` bytes of physical memory.
+fn copy_physical(start: usize, length: usize) -> Option<Vec<u8>> {
+    let address = map_physical_region(PhysicalAddress::new(start), length).ok()?;
+    let bytes = unsafe { core::slice::from_raw_parts(address.0 as *const u8, length) };
+    let copy = bytes.to_vec();
+    unmap_physical_region(address, PhysicalAddress::new(start), length);
+    Some(copy)
+}
+
+/// Copy the table at physical address `start` to the heap, checking its checksum.
+fn copy_table(start: usize) -> Option<Sdt> {
+    let header = copy_physical(start, SDT_HEADER_SIZE)?;
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if length < SDT_HEADER_SIZE {
+        log!("warning: ACPI table at 0x{:x} has a bad length", start);
+        return None;
+    }
+
+    let table = copy_physical(start, length)?;
+    if !checksum_ok(&table) {
+        log!(
+            "warning: ACPI table {} at 0x{:x} failed its checksum, ignoring it",
+            core::str::from_utf8(&table[0..4]).unwrap_or("????"),
+            start
+        );
+        return None;
+    }
+    Some(Sdt {
+        data: Box::leak(table.into_boxed_slice()),
+    })
+}
+
+/// Look for the RSDP on a 16 byte boundary in a physical memory range.
+fn scan_for_rsdp(start: usize, length: usize) -> Option<usize> {
+    let area = copy_physical(start, length)?;
+    (0..length.saturating_sub(20))
+        .step_by(16)
+        .find(|offset| area[*offset..].starts_with(RSDP_SIGNATURE) && checksum_ok(&area[*offset..*offset + 20]))
+        .map(|offset| start + offset)
+}
+
+/// Find the RSDP. The boot loader may have passed a copy along (Multiboot2),
+/// otherwise search the first KiB of the EBDA and then the BIOS ROM area.
+fn find_rsdp(boot_info: &BootInfo) -> Option<usize> {
+    if let Some(rsdp) = boot_info.rsdp {
+        return Some(rsdp);
+    }
+
+    let ebda_pointer = copy_physical(EBDA_POINTER, 2)?;
+    let ebda = (u16::from_le_bytes([ebda_pointer[0], ebda_pointer[1]]) as usize) << 4;
+    if ebda != 0 {
+        if let Some(rsdp) = scan_for_rsdp(ebda, 1024) {
+            return Some(rsdp);
+        }
+    }
+    scan_for_rsdp(BIOS_AREA_START, BIOS_AREA_END - BIOS_AREA_START)
+}
+
+/// Find and copy the ACPI tables, then release ACPI reclaimable memory. Needs the heap.
+pub fn init(boot_info: &BootInfo) {
+    let rsdp_address = match find_rsdp(boot_info) {
+        Some(address) => address,
+        None => {
+            log!("no ACPI RSDP found");
+            return;
+        }
+    };
+
+    // ACPI 1.0 RSDPs are 20 bytes, 2.0+ add the length, XSDT address and an extended
+    // checksum over all 36 bytes.
+    let rsdp = match copy_physical(rsdp_address, 36) {
+        Some(rsdp) => rsdp,
+        None => return,
+    };
+    let revision = rsdp[15];
+    if !checksum_ok(&rsdp[..20]) || (revision >= 2 && !checksum_ok(&rsdp[..36])) {
+        log!("warning: ACPI RSDP at 0x{:x} failed its checksum", rsdp_address);
+        return;
+    }
+
+    let rsdt_address = u32::from_le_bytes([rsdp[16], rsdp[17], rsdp[18], rsdp[19]]) as usize;
+    let mut xsdt_address = [0u8; 8];
+    xsdt_address.copy_from_slice(&rsdp[24..32]);
+    let xsdt_address = u64::from_le_bytes(xsdt_address) as usize;
+
+    // Prefer the XSDT, its entries are 64-bit.
+    let (root, entry_size) = if revision >= 2 && xsdt_address != 0 {
+        (copy_table(xsdt_address), 8)
+    } else {
+        (copy_table(rsdt_address), 4)
+    };
+    let root = match root {
+        Some(root) => root,
+        None => return,
+    };
+
+    let mut tables = Vec::new();
+    for offset in (SDT_HEADER_SIZE..root.length()).step_by(entry_size) {
+        let address = match entry_size {
+            8 => root.read_u64(offset).map(|a| a as usize),
+            _ => root.read_u32(offset).map(|a| a as usize),
+        };
+        if let Some(table) = address.and_then(copy_table) {
+            tables.push(table);
+        }
+    }
+
+    // The DSDT isn't listed in the root table, only the FADT points to it.
+    let dsdt_address = tables
+        .iter()
+        .find(|table| table.signature() == "FACP")
+        .and_then(|table| Fadt::new(*table).dsdt_address());
+    if let Some(dsdt) = dsdt_address.and_then(|address| copy_table(address as usize)) {
+        tables.push(dsdt);
+    }
+
+    log!(
+        "ACPI revision {} ({}), {} tables from the {}",
+        revision,
+        root.oem_id(),
+        tables.len(),
+        if entry_size == 8 { "XSDT" } else { "RSDT" }
+    );
+    for table in tables.iter() {
+        log!("  {} rev {} length {} ({})", table.signature(), table.revision(), table.length(), table.oem_id());
+    }
+
+    *TABLES.lock() = Some(AcpiTables { tables });
+    log_platform();
+    release_reclaimable_memory(boot_info);
+    aml::init();
+}
+
+fn log_platform() {
+    if let Some(madt) = madt() {
+        let cpus = madt
+            .entries()
+            .filter(|entry| entry.is_usable_processor())
+            .count();
+        log!(
+            "MADT: {} usable CPUs, local APIC at 0x{:x}{}",
+            cpus,
+            madt.local_apic_address(),
+            if madt.has_legacy_pics() { ", dual 8259 PICs" } else { "" }
+        );
+        for entry in madt.entries() {
+            let usable = if entry.is_usable_processor() { "" } else { " (disabled)" };
+            match entry {
+                madt::MadtEntry::LocalApic { processor_id, apic_id, .. } => {
+                    log!("MADT: CPU {} local APIC id {}{}", processor_id, apic_id, usable)
+                }
+                madt::MadtEntry::LocalX2Apic { processor_uid, x2apic_id, .. } => {
+                    log!("MADT: CPU {} x2APIC id {}{}", processor_uid, x2apic_id, usable)
+                }
+                madt::MadtEntry::LocalApicNmi { processor_id: 0xff, flags, lint } => {
+                    log!("MADT: NMI on LINT{} of all CPUs (flags 0x{:x})", lint, flags)
+                }
+                madt::MadtEntry::LocalApicNmi { processor_id, flags, lint } => {
+                    log!("MADT: NMI on LINT{} of CPU {} (flags 0x{:x})", lint, processor_id, flags)
+                }
+                madt::MadtEntry::Other { entry_type } => log!("MADT: skipping entry type {}", entry_type),
+                madt::MadtEntry::IoApic { id, address, gsi_base } => {
+                    log!("MADT: IOAPIC {} at 0x{:x}, GSI base {}", id, address, gsi_base)
+                }
+                madt::MadtEntry::InterruptOverride { source, gsi, flags, .. } => {
+                    log!("MADT: IRQ {} -> GSI {} (flags 0x{:x})", source, gsi, flags)
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(fadt) = fadt() {
+        log!(
+            "FADT: SCI on IRQ {}, boot architecture flags 0x{:x}, century register {}",
+            fadt.sci_interrupt(),
+            fadt.boot_architecture_flags(),
+            fadt.century_register()
+        );
+    }
+    if let Some(hpet) = hpet() {
+        if let Some(base) = hpet.base_address() {
+            log!(
+                "HPET {} at 0x{:x}, {} comparators, {}-bit counter, minimum tick {}, vendor 0x{:04x}",
+                hpet.hpet_number(),
+                base.address,
+                hpet.comparator_count(),
+                if hpet.counter_is_64bit() { 64 } else { 32 },
+                hpet.minimum_tick(),
+                hpet.pci_vendor_id()
+            );
+        }
+    }
+    if let Some(mcfg) = mcfg() {
+        for window in mcfg.windows() {
+            log!(
+                "MCFG: segment {} buses {}-{} at 0x{:x}",
+                window.segment,
+                window.start_bus,
+                window.end_bus,
+                window.base_address
+            );
+        }
+    }
+}
+
+/// Everything we need from ACPI reclaimable memory has been copied, give it back.
+fn release_reclaimable_memory(boot_info: &BootInfo) {
+    let mut frames = 0;
+    for region in boot_info.memory_map() {
+        if let MMapEntryType::ACPI = region.entry_type() {
+            frames += free_physical_range(
+                PhysicalAddress::new(region.base_addr() as usize),
+                region.length() as usize,
+            );
+        }
+    }
+    if frames != 0 {
+        log!("released {} KiB of ACPI reclaimable memory", frames * PAGE_SIZE / 1024);
+    }
+}
+
+/// Find a table by its signature, e.g. "APIC" for the MADT.
+pub fn find_table(signature: &str) -> Option<Sdt> {
+    TABLES
+        .lock()
+        .as_ref()?
+        .tables
+        .iter()
+        .find(|table| table.signature() == signature)
+        .copied()
+}
+
+/// All tables with a given signature, there can be several SSDTs.
+pub fn find_tables(signature: &str) -> Vec<Sdt> {
+    match TABLES.lock().as_ref() {
+        Some(tables) => tables
+            .tables
+            .iter()
+            .filter(|table| table.signature() == signature)
+            .copied()
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+pub fn madt() -> Option<Madt> {
+    find_table("APIC").map(Madt::new)
+}
+
+pub fn fadt() -> Option<Fadt> {
+    find_table("FACP").map(Fadt::new)
+}
+
+pub fn hpet() -> Option<Hpet> {
+    find_table("HPET").map(Hpet::new)
+}
+
+pub fn mcfg() -> Option<Mcfg> {
+    find_table("MCFG").map(Mcfg::new)
+}
+
+/// The Differentiated System Description Table, the main AML block.
+pub fn dsdt() -> Option<Sdt> {
+    find_table("DSDT")
+}
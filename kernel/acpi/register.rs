@@ -0,0 +1,196 @@
+use super::{AddressSpace, GenericAddress};
+use crate::arch::interrupt::without_interrupts;
+use crate::arch::memory::{map_mmio_region, PAGE_SIZE};
+use crate::arch::x86_io::{inb, inl, inw, outb, outl, outw};
+use crate::memory::addr::PhysicalAddress;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+/// Pages of memory mapped registers mapped so far, as (physical, virtual) page
+/// addresses. A register is accessed again and again, e.g. the PM timer, so its
+/// page stays mapped once used.
+static REGISTER_PAGES: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+/// Virtual address of the memory mapped register at `address`. Registers are
+/// naturally aligned, so none of them straddles a page.
+fn map_register(address: u64) -> Option<*mut u8> {
+    let address = address as usize;
+    let page = address & !(PAGE_SIZE - 1);
+    let offset = address - page;
+    without_interrupts(|| {
+        let mut pages = REGISTER_PAGES.lock();
+        if let Some((_, virtual_page)) = pages.iter().find(|(physical_page, _)| *physical_page == page) {
+            return Some((virtual_page + offset) as *mut u8);
+        }
+        let virtual_page = map_mmio_region(PhysicalAddress::new(page), PAGE_SIZE).ok()?.0;
+        pages.push((page, virtual_page));
+        Some((virtual_page + offset) as *mut u8)
+    })
+}
+
+/// Select a configuration register through the legacy 0xCF8/0xCFC mechanism, which
+/// reaches the first 256 bytes of every function on segment 0.
+fn select_pci_config(bus: u8, device: u8, function: u8, offset: u8) {
+    let address = (1 << 31)
+        | (bus as u32) << 16
+        | ((device & 0x1f) as u32) << 11
+        | ((function & 0x7) as u32) << 8
+        | (offset & 0xfc) as u32;
+    unsafe { outl(PCI_CONFIG_ADDRESS, address) };
+}
+
+/// Read `width` bits of a function's PCI configuration space.
+pub(super) fn pci_config_read(bus: u8, device: u8, function: u8, offset: u8, width: u8) -> u64 {
+    select_pci_config(bus, device, function, offset);
+    let port = PCI_CONFIG_DATA + (offset & 0x3) as u16;
+    unsafe {
+        match width {
+            8 => inb(port) as u64,
+            16 => inw(port) as u64,
+            _ => inl(PCI_CONFIG_DATA) as u64,
+        }
+    }
+}
+
+/// Write `width` bits of a function's PCI configuration space.
+pub(super) fn pci_config_write(bus: u8, device: u8, function: u8, offset: u8, width: u8, value: u64) {
+    select_pci_config(bus, device, function, offset);
+    let port = PCI_CONFIG_DATA + (offset & 0x3) as u16;
+    unsafe {
+        match width {
+            8 => outb(port, value as u8),
+            16 => outw(port, value as u16),
+            _ => outl(PCI_CONFIG_DATA, value as u32),
+        }
+    }
+}
+
+impl GenericAddress {
+    /// Access width in bits. The access size field wins, older tables only fill in
+    /// the register bit width.
+    fn access_width(&self) -> u8 {
+        match self.access_size {
+            1 => 8,
+            2 => 16,
+            3 => 32,
+            4 => 64,
+            _ => match self.bit_width {
+                0..=8 => 8,
+                9..=16 => 16,
+                17..=32 => 32,
+                _ => 64,
+            },
+        }
+    }
+
+    /// For the PCI configuration space the address encodes the function on bus 0:
+    /// device in bits 32-47, function in bits 16-31 and the register offset below.
+    fn pci_function(&self) -> (u8, u8, u8) {
+        ((self.address >> 32) as u8, (self.address >> 16) as u8, self.address as u8)
+    }
+
+    /// The bits of an access that hold the register, given by the bit offset and
+    /// width. All of them when the table leaves the width out.
+    fn field_mask(&self) -> u64 {
+        let width = self.access_width() as u32;
+        let bits = match self.bit_width {
+            0 => width,
+            bits => (bits as u32).min(width),
+        };
+        let mask = u64::MAX >> (64 - bits);
+        let access_mask = u64::MAX >> (64 - width);
+        mask.checked_shl(self.bit_offset as u32).unwrap_or(0) & access_mask
+    }
+
+    pub fn read(&self) -> u64 {
+        (self.read_access() & self.field_mask()) >> self.bit_offset.min(63)
+    }
+
+    /// Write `value` to the register. Registers that only cover part of an access
+    /// are read first, so the bits around them are kept.
+    pub fn write(&self, value: u64) {
+        let mask = self.field_mask();
+        let access_mask = u64::MAX >> (64 - self.access_width() as u32);
+        let value = value.checked_shl(self.bit_offset as u32).unwrap_or(0) & mask;
+        if mask == access_mask {
+            self.write_access(value);
+        } else {
+            self.write_access(self.read_access() & !mask | value);
+        }
+    }
+
+    /// Read a whole access, ignoring the bit offset and width.
+    fn read_access(&self) -> u64 {
+        let width = self.access_width();
+        match self.address_space {
+            AddressSpace::SystemIo => {
+                let port = self.address as u16;
+                unsafe {
+                    match width {
+                        8 => inb(port) as u64,
+                        16 => inw(port) as u64,
+                        _ => inl(port) as u64,
+                    }
+                }
+            }
+            AddressSpace::SystemMemory => {
+                let address = match map_register(self.address) {
+                    Some(address) => address,
+                    None => return 0,
+                };
+                unsafe {
+                    match width {
+                        8 => core::ptr::read_volatile(address) as u64,
+                        16 => core::ptr::read_volatile(address as *const u16) as u64,
+                        32 => core::ptr::read_volatile(address as *const u32) as u64,
+                        _ => core::ptr::read_volatile(address as *const u64),
+                    }
+                }
+            }
+            AddressSpace::PciConfig => {
+                let (device, function, offset) = self.pci_function();
+                pci_config_read(0, device, function, offset, width)
+            }
+            AddressSpace::Other(_) => 0,
+        }
+    }
+
+    /// Write a whole access, ignoring the bit offset and width.
+    fn write_access(&self, value: u64) {
+        let width = self.access_width();
+        match self.address_space {
+            AddressSpace::SystemIo => {
+                let port = self.address as u16;
+                unsafe {
+                    match width {
+                        8 => outb(port, value as u8),
+                        16 => outw(port, value as u16),
+                        _ => outl(port, value as u32),
+                    }
+                }
+            }
+            AddressSpace::SystemMemory => {
+                let address = match map_register(self.address) {
+                    Some(address) => address,
+                    None => return,
+                };
+                unsafe {
+                    match width {
+                        8 => core::ptr::write_volatile(address, value as u8),
+                        16 => core::ptr::write_volatile(address as *mut u16, value as u16),
+                        32 => core::ptr::write_volatile(address as *mut u32, value as u32),
+                        _ => core::ptr::write_volatile(address as *mut u64, value),
+                    }
+                }
+            }
+            AddressSpace::PciConfig => {
+                let (device, function, offset) = self.pci_function();
+                pci_config_write(0, device, function, offset, width, value);
+            }
+            AddressSpace::Other(_) => {}
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use super::aml::{self, AmlName, AmlValue};
+use super::{find_table, Sdt, SDT_HEADER_SIZE};
+use alloc::vec::Vec;
+
+// Just enough AML to find the sleep type values of a `\_Sx` package, e.g.
+//
+//     Name (_S5, Package (0x04) { 0x05, 0x05, Zero, Zero })
+//
+// which encodes as NameOp "_S5_" PackageOp PkgLength NumElements followed by the
+// elements, SLP_TYPa first and SLP_TYPb second.
+
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const BYTE_PREFIX: u8 = 0x0A;
+const WORD_PREFIX: u8 = 0x0B;
+const ROOT_CHAR: u8 = b'\\';
+
+/// Decode an integer element of the package, returns the value and its size.
+fn integer(aml: &[u8]) -> Option<(u8, usize)> {
+    match *aml.first()? {
+        ZERO_OP => Some((0, 1)),
+        ONE_OP => Some((1, 1)),
+        BYTE_PREFIX => Some((*aml.get(1)?, 2)),
+        WORD_PREFIX => Some((*aml.get(1)?, 3)),
+        _ => None,
+    }
+}
+
+fn scan(table: &Sdt, name: &[u8; 4]) -> Option<(u8, u8)> {
+    let aml = table.data().get(SDT_HEADER_SIZE..)?;
+    for position in 1..aml.len().saturating_sub(4) {
+        if &aml[position..position + 4] != name {
+            continue;
+        }
+        // Must be the name being defined: NameOp, optionally with a root prefix.
+        let named = aml[position - 1] == NAME_OP
+            || (aml[position - 1] == ROOT_CHAR && position >= 2 && aml[position - 2] == NAME_OP);
+        if !named {
+            continue;
+        }
+
+        let mut cursor = position + 4;
+        if *aml.get(cursor)? != PACKAGE_OP {
+            continue;
+        }
+        cursor += 1;
+        // PkgLength: bits 6-7 of the lead byte count the bytes that follow it.
+        cursor += 1 + (*aml.get(cursor)? >> 6) as usize;
+        // NumElements
+        cursor += 1;
+
+        let (slp_typ_a, size) = integer(aml.get(cursor..)?)?;
+        let (slp_typ_b, _) = integer(aml.get(cursor + size..)?).unwrap_or((0, 0));
+        return Some((slp_typ_a, slp_typ_b));
+    }
+    None
+}
+
+/// The SLP_TYPa and SLP_TYPb values for sleep state `state` (e.g. 5 for soft-off),
+/// from the `\_Sx` object in the DSDT or one of the SSDTs. The namespace is
+/// asked first, scanning the tables still works if it isn't available.
+pub fn sleep_type(state: u8) -> Option<(u8, u8)> {
+    let name = [b'_', b'S', b'0' + state, b'_'];
+    let path = AmlName::root().child(&name);
+    if let Ok(AmlValue::Package(elements)) = aml::evaluate_name(&path, Vec::new()) {
+        let slp_typ = |i: usize| elements.get(i).and_then(|e| e.as_integer().ok()).unwrap_or(0) as u8;
+        return Some((slp_typ(0), slp_typ(1)));
+    }
+
+    let mut tables: Vec<Sdt> = find_table("DSDT").into_iter().collect();
+    tables.extend(super::find_tables("SSDT"));
+    tables.iter().find_map(|table| scan(table, &name))
+}
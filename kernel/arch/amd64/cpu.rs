@@ -0,0 +1,438 @@
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    let mut result = CpuidResult::default();
+    unsafe {
+        // rbx is reserved by LLVM, save it around the instruction.
+        asm!(
+            "mov {0:r}, rbx",
+            "cpuid",
+            "xchg {0:r}, rbx",
+            out(reg) result.ebx,
+            inout("eax") leaf => result.eax,
+            inout("ecx") subleaf => result.ecx,
+            lateout("edx") result.edx,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Vendor {
+    Intel,
+    Amd,
+    Other,
+}
+
+/// CPU features the kernel cares about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Feature {
+    Tsc,
+    Msr,
+    Pae,
+    Apic,
+    Pge,
+    Pat,
+    Sse2,
+    Pcid,
+    X2Apic,
+    TscDeadline,
+    Xsave,
+    Osxsave,
+    Avx,
+    Rdrand,
+    Hypervisor,
+    FsGsBase,
+    Smep,
+    Invpcid,
+    Rdseed,
+    Smap,
+    Umip,
+    La57,
+    Nx,
+    HugePages1G,
+    Rdtscp,
+    LongMode,
+    InvariantTsc,
+}
+
+#[derive(Clone, Copy)]
+enum Register {
+    Ecx,
+    Edx,
+    Ebx,
+}
+
+/// Where each feature's bit lives: leaf, register and bit number.
+const FEATURE_BITS: &[(Feature, &str, u32, Register, u32)] = &[
+    (Feature::Tsc, "tsc", 0x1, Register::Edx, 4),
+    (Feature::Msr, "msr", 0x1, Register::Edx, 5),
+    (Feature::Pae, "pae", 0x1, Register::Edx, 6),
+    (Feature::Apic, "apic", 0x1, Register::Edx, 9),
+    (Feature::Pge, "pge", 0x1, Register::Edx, 13),
+    (Feature::Pat, "pat", 0x1, Register::Edx, 16),
+    (Feature::Sse2, "sse2", 0x1, Register::Edx, 26),
+    (Feature::Pcid, "pcid", 0x1, Register::Ecx, 17),
+    (Feature::X2Apic, "x2apic", 0x1, Register::Ecx, 21),
+    (Feature::TscDeadline, "tsc_deadline", 0x1, Register::Ecx, 24),
+    (Feature::Xsave, "xsave", 0x1, Register::Ecx, 26),
+    (Feature::Osxsave, "osxsave", 0x1, Register::Ecx, 27),
+    (Feature::Avx, "avx", 0x1, Register::Ecx, 28),
+    (Feature::Rdrand, "rdrand", 0x1, Register::Ecx, 30),
+    (Feature::Hypervisor, "hypervisor", 0x1, Register::Ecx, 31),
+    (Feature::FsGsBase, "fsgsbase", 0x7, Register::Ebx, 0),
+    (Feature::Smep, "smep", 0x7, Register::Ebx, 7),
+    (Feature::Invpcid, "invpcid", 0x7, Register::Ebx, 10),
+    (Feature::Rdseed, "rdseed", 0x7, Register::Ebx, 18),
+    (Feature::Smap, "smap", 0x7, Register::Ebx, 20),
+    (Feature::Umip, "umip", 0x7, Register::Ecx, 2),
+    (Feature::La57, "la57", 0x7, Register::Ecx, 16),
+    (Feature::Nx, "nx", 0x8000_0001, Register::Edx, 20),
+    (Feature::HugePages1G, "pdpe1gb", 0x8000_0001, Register::Edx, 26),
+    (Feature::Rdtscp, "rdtscp", 0x8000_0001, Register::Edx, 27),
+    (Feature::LongMode, "lm", 0x8000_0001, Register::Edx, 29),
+    (Feature::InvariantTsc, "invariant_tsc", 0x8000_0007, Register::Edx, 8),
+];
+
+/// A set of `Feature`s.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Features(u64);
+
+impl Features {
+    pub fn has(&self, feature: Feature) -> bool {
+        self.0 & (1 << feature as u64) != 0
+    }
+
+    fn insert(&mut self, feature: Feature) {
+        self.0 |= 1 << feature as u64;
+    }
+
+    /// Names of the features present, for logging.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        FEATURE_BITS
+            .iter()
+            .filter(move |(feature, ..)| self.has(*feature))
+            .map(|(_, name, ..)| *name)
+    }
+}
+
+/// The names of the features, each preceded by a space. Formats straight into the
+/// log, `init` runs before there is a heap.
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for name in self.names() {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Cache {
+    pub level: u8,
+    pub cache_type: CacheType,
+    pub size: usize,
+    pub line_size: usize,
+    pub ways: usize,
+    /// Logical processors sharing this cache.
+    pub shared_by: u32,
+}
+
+const MAX_CACHES: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Hypervisor {
+    pub signature: [u8; 12],
+    pub max_leaf: u32,
+}
+
+impl Hypervisor {
+    pub fn name(&self) -> &str {
+        match &self.signature {
+            b"KVMKVMKVM\0\0\0" => "KVM",
+            b"TCGTCGTCGTCG" => "QEMU TCG",
+            b"VMwareVMware" => "VMware",
+            b"Microsoft Hv" => "Hyper-V",
+            b"XenVMMXenVMM" => "Xen",
+            b"VBoxVBoxVBox" => "VirtualBox",
+            b" lrpepyh  vr" => "Parallels",
+            signature => core::str::from_utf8(signature).unwrap_or("unknown"),
+        }
+    }
+}
+
+/// Everything learned from CPUID on the boot CPU.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuInfo {
+    pub vendor: Vendor,
+    pub vendor_string: [u8; 12],
+    pub brand: [u8; 48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    pub max_leaf: u32,
+    pub max_extended_leaf: u32,
+    pub features: Features,
+    pub physical_address_bits: u8,
+    pub virtual_address_bits: u8,
+    pub initial_apic_id: u32,
+    pub caches: [Option<Cache>; MAX_CACHES],
+    pub hypervisor: Option<Hypervisor>,
+    /// TSC frequency reported by leaf 0x15 or the hypervisor, if any.
+    pub tsc_frequency: Option<u64>,
+}
+
+impl CpuInfo {
+    pub fn vendor_string(&self) -> &str {
+        core::str::from_utf8(&self.vendor_string).unwrap_or("unknown")
+    }
+
+    pub fn brand(&self) -> &str {
+        let length = self.brand.iter().position(|c| *c == 0).unwrap_or(self.brand.len());
+        core::str::from_utf8(&self.brand[..length]).unwrap_or("").trim()
+    }
+
+    pub fn caches(&self) -> impl Iterator<Item = &Cache> {
+        self.caches.iter().flatten()
+    }
+}
+
+static CPU_INFO: Mutex<Option<CpuInfo>> = Mutex::new(None);
+/// The feature set again, for checks that shouldn't take a lock.
+static FEATURES: AtomicU64 = AtomicU64::new(0);
+
+fn detect_features(max_leaf: u32, max_extended_leaf: u32) -> Features {
+    let mut features = Features::default();
+    for (feature, _, leaf, register, bit) in FEATURE_BITS {
+        let available = if *leaf >= 0x8000_0000 {
+            *leaf <= max_extended_leaf
+        } else {
+            *leaf <= max_leaf
+        };
+        if !available {
+            continue;
+        }
+        let result = cpuid(*leaf, 0);
+        let value = match register {
+            Register::Ebx => result.ebx,
+            Register::Ecx => result.ecx,
+            Register::Edx => result.edx,
+        };
+        if value & (1 << bit) != 0 {
+            features.insert(*feature);
+        }
+    }
+    features
+}
+
+/// Deterministic cache parameters, leaf 4 on Intel and 0x8000001D on AMD.
+fn detect_caches(leaf: u32) -> [Option<Cache>; MAX_CACHES] {
+    let mut caches = [None; MAX_CACHES];
+    for (index, slot) in caches.iter_mut().enumerate() {
+        let result = cpuid(leaf, index as u32);
+        let cache_type = match result.eax & 0x1f {
+            1 => CacheType::Data,
+            2 => CacheType::Instruction,
+            3 => CacheType::Unified,
+            _ => break,
+        };
+        let line_size = (result.ebx & 0xfff) as usize + 1;
+        let partitions = ((result.ebx >> 12) & 0x3ff) as usize + 1;
+        let ways = (result.ebx >> 22) as usize + 1;
+        let sets = result.ecx as usize + 1;
+        *slot = Some(Cache {
+            level: ((result.eax >> 5) & 0x7) as u8,
+            cache_type,
+            size: line_size * partitions * ways * sets,
+            line_size,
+            ways,
+            shared_by: ((result.eax >> 14) & 0xfff) + 1,
+        });
+    }
+    caches
+}
+
+fn detect_hypervisor() -> Option<Hypervisor> {
+    let result = cpuid(0x4000_0000, 0);
+    let mut signature = [0u8; 12];
+    signature[0..4].copy_from_slice(&result.ebx.to_le_bytes());
+    signature[4..8].copy_from_slice(&result.ecx.to_le_bytes());
+    signature[8..12].copy_from_slice(&result.edx.to_le_bytes());
+    Some(Hypervisor {
+        signature,
+        max_leaf: result.eax,
+    })
+}
+
+fn detect_tsc_frequency(max_leaf: u32, hypervisor: Option<Hypervisor>) -> Option<u64> {
+    // Leaf 0x15: TSC/crystal ratio in ebx/eax, crystal frequency in ecx.
+    if max_leaf >= 0x15 {
+        let result = cpuid(0x15, 0);
+        if result.eax != 0 && result.ebx != 0 && result.ecx != 0 {
+            return Some(result.ecx as u64 * result.ebx as u64 / result.eax as u64);
+        }
+    }
+    // VMware and KVM report the TSC frequency in kHz in leaf 0x40000010.
+    match hypervisor {
+        Some(hypervisor) if hypervisor.max_leaf >= 0x4000_0010 => {
+            let khz = cpuid(0x4000_0010, 0).eax;
+            if khz != 0 {
+                Some(khz as u64 * 1000)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn detect() -> CpuInfo {
+    let leaf0 = cpuid(0, 0);
+    let max_leaf = leaf0.eax;
+    let mut vendor_string = [0u8; 12];
+    vendor_string[0..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+    vendor_string[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+    vendor_string[8..12].copy_from_slice(&leaf0.ecx.to_le_bytes());
+    let vendor = match &vendor_string {
+        b"GenuineIntel" => Vendor::Intel,
+        b"AuthenticAMD" => Vendor::Amd,
+        _ => Vendor::Other,
+    };
+    let max_extended_leaf = cpuid(0x8000_0000, 0).eax;
+
+    // Family, model and stepping, with the extended fields folded in.
+    let signature = cpuid(1, 0);
+    let base_family = (signature.eax >> 8) & 0xf;
+    let mut family = base_family;
+    let mut model = (signature.eax >> 4) & 0xf;
+    if base_family == 0xf {
+        family += (signature.eax >> 20) & 0xff;
+    }
+    if base_family == 0x6 || base_family == 0xf {
+        model += ((signature.eax >> 16) & 0xf) << 4;
+    }
+
+    let mut brand = [0u8; 48];
+    if max_extended_leaf >= 0x8000_0004 {
+        for (i, leaf) in (0x8000_0002..=0x8000_0004).enumerate() {
+            let result = cpuid(leaf, 0);
+            for (j, register) in [result.eax, result.ebx, result.ecx, result.edx].iter().enumerate() {
+                let offset = i * 16 + j * 4;
+                brand[offset..offset + 4].copy_from_slice(&register.to_le_bytes());
+            }
+        }
+    }
+
+    let (physical_address_bits, virtual_address_bits) = if max_extended_leaf >= 0x8000_0008 {
+        let sizes = cpuid(0x8000_0008, 0).eax;
+        (sizes as u8, (sizes >> 8) as u8)
+    } else {
+        (36, 48)
+    };
+
+    let features = detect_features(max_leaf, max_extended_leaf);
+    let caches = if vendor == Vendor::Amd {
+        // Only with the topology extensions, CPUID 0x80000001 ECX bit 22.
+        let topology_extensions = max_extended_leaf >= 0x8000_001D && cpuid(0x8000_0001, 0).ecx & (1 << 22) != 0;
+        if topology_extensions {
+            detect_caches(0x8000_001D)
+        } else {
+            [None; MAX_CACHES]
+        }
+    } else if max_leaf >= 4 {
+        detect_caches(4)
+    } else {
+        [None; MAX_CACHES]
+    };
+    let hypervisor = if features.has(Feature::Hypervisor) {
+        detect_hypervisor()
+    } else {
+        None
+    };
+
+    CpuInfo {
+        vendor,
+        vendor_string,
+        brand,
+        family,
+        model,
+        stepping: signature.eax & 0xf,
+        max_leaf,
+        max_extended_leaf,
+        features,
+        physical_address_bits,
+        virtual_address_bits,
+        initial_apic_id: signature.ebx >> 24,
+        caches,
+        hypervisor,
+        tsc_frequency: detect_tsc_frequency(max_leaf, hypervisor),
+    }
+}
+
+/// Query CPUID once on the boot CPU and log what it has.
+pub fn init() {
+    let info = detect();
+    FEATURES.store(info.features.0, Ordering::Relaxed);
+    *CPU_INFO.lock() = Some(info);
+
+    log!(
+        "{} family 0x{:x} model 0x{:x} stepping {}: {}",
+        info.vendor_string(),
+        info.family,
+        info.model,
+        info.stepping,
+        info.brand()
+    );
+    log!(
+        "address bits: {} physical, {} virtual",
+        info.physical_address_bits,
+        info.virtual_address_bits
+    );
+    log!("features:{}", info.features);
+    for cache in info.caches() {
+        log!(
+            "L{} {:?} cache: {} KiB, {}-way, {} byte lines, shared by {}",
+            cache.level,
+            cache.cache_type,
+            cache.size / 1024,
+            cache.ways,
+            cache.line_size,
+            cache.shared_by
+        );
+    }
+    if let Some(hypervisor) = info.hypervisor {
+        log!("running under {}", hypervisor.name());
+    }
+    if let Some(frequency) = info.tsc_frequency {
+        log!("TSC frequency {} kHz", frequency / 1000);
+    }
+}
+
+/// What CPUID reported on the boot CPU. Only valid after `init`.
+pub fn info() -> CpuInfo {
+    CPU_INFO.lock().expect("cpu::info used before cpu::init")
+}
+
+/// Whether the boot CPU has `feature`. Before `init` nothing is reported present.
+pub fn has_feature(feature: Feature) -> bool {
+    Features(FEATURES.load(Ordering::Relaxed)).has(feature)
+}
@@ -0,0 +1,59 @@
+// Exception fixups. Code that may fault on purpose, e.g. when reading memory it
+// can't vouch for, records the address of the instruction that may fault and
+// where to continue if it does in the .extable section. The exception handlers
+// look the faulting instruction up here and, if it is listed, resume at the
+// fixup instead of treating the fault as fatal.
+
+/// One .extable entry. Both fields are offsets from the field itself, so the
+/// table doesn't need relocating when KASLR moves the kernel.
+#[repr(C)]
+struct ExceptionTableEntry {
+    instruction: i32,
+    fixup: i32,
+}
+
+impl ExceptionTableEntry {
+    fn instruction(&self) -> u64 {
+        (&self.instruction as *const i32 as i64 + self.instruction as i64) as u64
+    }
+
+    fn fixup(&self) -> u64 {
+        (&self.fixup as *const i32 as i64 + self.fixup as i64) as u64
+    }
+}
+
+extern "C" {
+    static __extable_start: ExceptionTableEntry;
+    static __extable_end: ExceptionTableEntry;
+}
+
+fn entries() -> &'static [ExceptionTableEntry] {
+    unsafe {
+        let start = &__extable_start as *const ExceptionTableEntry;
+        let end = &__extable_end as *const ExceptionTableEntry;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Where to resume after a fault at `instruction`, if it has a fixup.
+pub fn search(instruction: u64) -> Option<u64> {
+    entries()
+        .iter()
+        .find(|entry| entry.instruction() == instruction)
+        .map(|entry| entry.fixup())
+}
+
+/// Assembler directives adding an .extable entry, for use in an `asm!` template:
+/// a fault at label `$instruction` continues at label `$fixup`.
+macro_rules! extable_entry {
+    ($instruction:literal, $fixup:literal) => {
+        concat!(
+            ".pushsection .extable, \"a\"\n",
+            ".balign 4\n",
+            ".long ", $instruction, " - .\n",
+            ".long ", $fixup, " - .\n",
+            ".popsection"
+        )
+    };
+}
+pub(crate) use extable_entry;
@@ -0,0 +1,106 @@
+use super::tss::{self, TaskStateSegment};
+use bit_field::BitField;
+use core::arch::asm;
+use core::mem::size_of;
+
+// Selectors, in the same layout start.S's early GDT used so nothing that cached
+// a selector before `init` has to change.
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const USER_CODE32_SELECTOR: u16 = 0x18 | 3;
+pub const USER_DATA_SELECTOR: u16 = 0x20 | 3;
+pub const USER_CODE_SELECTOR: u16 = 0x28 | 3;
+pub const TSS_SELECTOR: u16 = 0x38;
+
+const GDT_ENTRIES: usize = 9;
+
+// Segment descriptor bits. Base and limit are ignored in long mode except for
+// the TSS, so code and data descriptors are just these flags.
+const WRITABLE: u64 = 1 << 41;
+const EXECUTABLE: u64 = 1 << 43;
+const CODE_OR_DATA: u64 = 1 << 44;
+const USER: u64 = 3 << 45;
+const PRESENT: u64 = 1 << 47;
+const LONG_MODE: u64 = 1 << 53;
+const DEFAULT_32BIT: u64 = 1 << 54;
+
+const KERNEL_CODE: u64 = PRESENT | CODE_OR_DATA | EXECUTABLE | WRITABLE | LONG_MODE;
+const KERNEL_DATA: u64 = PRESENT | CODE_OR_DATA | WRITABLE;
+const USER_CODE32: u64 = PRESENT | USER | CODE_OR_DATA | EXECUTABLE | WRITABLE | DEFAULT_32BIT;
+const USER_DATA: u64 = PRESENT | USER | CODE_OR_DATA | WRITABLE;
+const USER_CODE: u64 = PRESENT | USER | CODE_OR_DATA | EXECUTABLE | WRITABLE | LONG_MODE;
+
+#[repr(C, packed)]
+struct DescriptorTablePointer {
+    limit: u16,
+    base: u64,
+}
+
+static mut GDT: [u64; GDT_ENTRIES] = [
+    0,
+    KERNEL_CODE,
+    KERNEL_DATA,
+    USER_CODE32,
+    USER_DATA,
+    USER_CODE,
+    USER_DATA,
+    0, // TSS, filled in by init
+    0, // TSS, upper half of base
+];
+
+/// The two descriptor slots of a 64-bit TSS.
+fn tss_descriptor(tss: &'static TaskStateSegment) -> (u64, u64) {
+    let base = tss as *const _ as u64;
+    let limit = (size_of::<TaskStateSegment>() - 1) as u64;
+
+    let mut low: u64 = 0;
+    low.set_bits(0..16, limit.get_bits(0..16));
+    low.set_bits(16..40, base.get_bits(0..24));
+    low.set_bits(40..44, 0b1001); // available 64-bit TSS
+    low.set_bit(47, true); // present
+    low.set_bits(48..52, limit.get_bits(16..20));
+    low.set_bits(56..64, base.get_bits(24..32));
+    (low, base.get_bits(32..64))
+}
+
+/// Replace the early GDT from start.S with the kernel's own, including a TSS with
+/// the ring 0 and interrupt stacks, and reload every segment register and the
+/// task register. Must run after memory::init and before the IDT is loaded.
+pub fn init() {
+    let tss = tss::init();
+    let (low, high) = tss_descriptor(tss);
+
+    unsafe {
+        GDT[TSS_SELECTOR as usize / 8] = low;
+        GDT[TSS_SELECTOR as usize / 8 + 1] = high;
+
+        let pointer = DescriptorTablePointer {
+            limit: (size_of::<[u64; GDT_ENTRIES]>() - 1) as u16,
+            base: core::ptr::addr_of!(GDT) as u64,
+        };
+        asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+
+        // CS can only be reloaded by a far transfer: return to the next
+        // instruction through the new code selector.
+        asm!(
+            "push {selector}",
+            "lea {target}, [rip + 2f]",
+            "push {target}",
+            "retfq",
+            "2:",
+            selector = in(reg) KERNEL_CODE_SELECTOR as u64,
+            target = lateout(reg) _,
+            options(preserves_flags),
+        );
+        asm!(
+            "mov ds, {0:x}",
+            "mov es, {0:x}",
+            "mov ss, {0:x}",
+            "mov fs, {0:x}",
+            "mov gs, {0:x}",
+            in(reg) KERNEL_DATA_SELECTOR,
+            options(nostack, preserves_flags),
+        );
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+    }
+}
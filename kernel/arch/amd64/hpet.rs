@@ -0,0 +1,327 @@
+// The High Precision Event Timer: a main counter running at a fixed rate,
+// given in femtoseconds per tick, and a set of comparators that raise an
+// interrupt, once or periodically, when the counter reaches them.
+use crate::acpi::{self, AddressSpace};
+use crate::arch::interrupt::{self, IoApicError, Polarity, TriggerMode};
+use crate::arch::memory::map_mmio_region;
+use crate::memory::addr::PhysicalAddress;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::mutex::Mutex;
+
+// Registers, offsets from the base.
+const CAPABILITIES: usize = 0x000;
+const CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0F0;
+/// Comparator `n`'s configuration is at `COMPARATOR_CONFIGURATION + n * COMPARATOR_STRIDE`.
+const COMPARATOR_CONFIGURATION: usize = 0x100;
+const COMPARATOR_VALUE: usize = 0x108;
+const COMPARATOR_STRIDE: usize = 0x20;
+
+// General capabilities bits.
+const CAPABILITY_64BIT_COUNTER: u64 = 1 << 13;
+
+// General configuration bits.
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+const CONFIGURATION_LEGACY_ROUTE: u64 = 1 << 1;
+
+// Comparator configuration bits.
+const COMPARATOR_LEVEL_TRIGGERED: u64 = 1 << 1;
+const COMPARATOR_INTERRUPT_ENABLE: u64 = 1 << 2;
+const COMPARATOR_PERIODIC: u64 = 1 << 3;
+const COMPARATOR_PERIODIC_CAPABLE: u64 = 1 << 4;
+/// The next comparator write sets the value rather than the period.
+const COMPARATOR_VALUE_SET: u64 = 1 << 6;
+const COMPARATOR_32BIT_MODE: u64 = 1 << 8;
+const COMPARATOR_ROUTE_SHIFT: u64 = 9;
+const COMPARATOR_ROUTE_MASK: u64 = 0x1f << COMPARATOR_ROUTE_SHIFT;
+const COMPARATOR_FSB_ENABLE: u64 = 1 << 14;
+
+/// The specification caps the period at 100 ns.
+const MAX_PERIOD_FS: u64 = 100_000_000;
+const FEMTOSECONDS_PER_MICROSECOND: u64 = 1_000_000_000;
+/// IOAPIC inputs past the ISA ones, preferred unless PCI interrupts use them.
+const FIRST_NON_ISA_GSI: u32 = 16;
+/// `PCI_GSIS` before `_PRT` has been read.
+const PCI_GSIS_UNKNOWN: u64 = u64::MAX;
+
+/// Where the registers are mapped, 0 if there is no HPET.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+static COUNTER_64BIT: AtomicBool = AtomicBool::new(false);
+/// The last value of a 32-bit main counter, with the wraps seen so far in the
+/// upper half.
+static LAST_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// The IOAPIC inputs below 32 PCI interrupts are routed to, one bit per GSI.
+/// Read from `_PRT` the first time a comparator needs an input.
+static PCI_GSIS: AtomicU64 = AtomicU64::new(PCI_GSIS_UNKNOWN);
+
+/// How the comparators are used, taken while changing one.
+struct Comparators {
+    count: u8,
+    /// GSI each comparator is routed to, once started.
+    gsis: [Option<u32>; 32],
+}
+
+static COMPARATORS: Mutex<Comparators> = Mutex::new(Comparators {
+    count: 0,
+    gsis: [None; 32],
+});
+
+#[derive(Debug)]
+pub enum HpetError {
+    NotPresent,
+    NoComparator(u8),
+    PeriodicUnsupported(u8),
+    /// Comparator interrupts go through the IOAPIC, which isn't in use.
+    NoIoApic,
+    /// None of the IOAPIC inputs the comparator can drive are free.
+    NoRoute(u8),
+    Route(IoApicError),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum EventMode {
+    /// Fire once, after this many microseconds.
+    OneShot(u64),
+    /// Fire every this many microseconds.
+    Periodic(u64),
+}
+
+fn read(offset: usize) -> u64 {
+    unsafe { ((BASE.load(Ordering::Relaxed) + offset) as *const u64).read_volatile() }
+}
+
+fn write(offset: usize, value: u64) {
+    unsafe { ((BASE.load(Ordering::Relaxed) + offset) as *mut u64).write_volatile(value) }
+}
+
+fn comparator_configuration(index: u8) -> usize {
+    COMPARATOR_CONFIGURATION + index as usize * COMPARATOR_STRIDE
+}
+
+fn comparator_value(index: u8) -> usize {
+    COMPARATOR_VALUE + index as usize * COMPARATOR_STRIDE
+}
+
+/// Map the HPET described by the ACPI HPET table, mask its comparators and
+/// start the main counter from zero. Returns false if there is none.
+pub fn init() -> bool {
+    let table = match acpi::hpet() {
+        Some(table) => table,
+        None => return false,
+    };
+    let address = match table.base_address() {
+        Some(address) if address.address_space == AddressSpace::SystemMemory => address.address,
+        _ => {
+            log_at!(crate::logging::Level::Warn, "HPET: registers not in system memory");
+            return false;
+        }
+    };
+    let base = match map_mmio_region(PhysicalAddress::new(address as usize), 0x400) {
+        Ok(base) => base.0,
+        Err(_) => {
+            log_at!(crate::logging::Level::Warn, "HPET: failed to map 0x{:x}", address);
+            return false;
+        }
+    };
+    BASE.store(base, Ordering::Relaxed);
+
+    let capabilities = read(CAPABILITIES);
+    let period = capabilities >> 32;
+    if period == 0 || period > MAX_PERIOD_FS {
+        log_at!(crate::logging::Level::Warn, "HPET: invalid period {} fs", period);
+        BASE.store(0, Ordering::Relaxed);
+        return false;
+    }
+    let count = ((capabilities >> 8) & 0x1f) as u8 + 1;
+
+    // Halt the counter while it is reset, and keep the comparators off the
+    // legacy PIT and RTC lines.
+    let configuration = read(CONFIGURATION) & !(CONFIGURATION_ENABLE | CONFIGURATION_LEGACY_ROUTE);
+    write(CONFIGURATION, configuration);
+    for index in 0..count {
+        let offset = comparator_configuration(index);
+        write(offset, read(offset) & !(COMPARATOR_INTERRUPT_ENABLE | COMPARATOR_FSB_ENABLE));
+    }
+    write(MAIN_COUNTER, 0);
+    LAST_COUNTER.store(0, Ordering::Relaxed);
+    PERIOD_FS.store(period, Ordering::Relaxed);
+    COUNTER_64BIT.store(capabilities & CAPABILITY_64BIT_COUNTER != 0, Ordering::Relaxed);
+    COMPARATORS.lock().count = count;
+    write(CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+
+    log!(
+        "HPET at 0x{:x}: {} fs period ({} kHz), {}-bit counter, {} comparators",
+        address,
+        period,
+        1_000_000_000_000 / period,
+        if capabilities & CAPABILITY_64BIT_COUNTER != 0 { 64 } else { 32 },
+        count
+    );
+    true
+}
+
+pub fn is_present() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Whether there is an HPET whose main counter is only 32 bits wide.
+pub fn has_32bit_counter() -> bool {
+    is_present() && !COUNTER_64BIT.load(Ordering::Relaxed)
+}
+
+/// Femtoseconds per main counter tick.
+pub fn period_fs() -> Option<u64> {
+    match PERIOD_FS.load(Ordering::Relaxed) {
+        0 => None,
+        period => Some(period),
+    }
+}
+
+/// Main counter ticks per second.
+pub fn frequency() -> Option<u64> {
+    period_fs().map(|period| 1_000_000_000_000_000 / period)
+}
+
+/// The main counter, counting up from `init`. A 32-bit counter is extended to
+/// 64 bits, which only works if it is read at least once per wrap, every 2^32
+/// ticks: about 43 seconds at QEMU's 100 MHz, 5 minutes at the common 14.318 MHz.
+/// `time::tick` reads it for that.
+pub fn counter() -> Option<u64> {
+    if !is_present() {
+        return None;
+    }
+    if COUNTER_64BIT.load(Ordering::Relaxed) {
+        return Some(read(MAIN_COUNTER));
+    }
+    // Read the counter only after loading `last`: a value read before could be
+    // older than a `last` stored in between, and would look like a wrap.
+    let mut last = LAST_COUNTER.load(Ordering::Relaxed);
+    loop {
+        let low = read(MAIN_COUNTER) & 0xffff_ffff;
+        let mut value = (last & !0xffff_ffff) | low;
+        if value < last {
+            value += 1 << 32;
+        }
+        match LAST_COUNTER.compare_exchange_weak(last, value, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return Some(value),
+            Err(current) => last = current,
+        }
+    }
+}
+
+/// Main counter ticks in `microseconds`.
+fn ticks(microseconds: u64, period: u64) -> u64 {
+    ((microseconds as u128 * FEMTOSECONDS_PER_MICROSECOND as u128) / period as u128).max(1) as u64
+}
+
+/// Spin for `microseconds`. Returns false, without waiting, if there is no HPET.
+pub fn delay_us(microseconds: u64) -> bool {
+    let (start, period) = match (counter(), period_fs()) {
+        (Some(start), Some(period)) => (start, period),
+        _ => return false,
+    };
+    let end = start + ticks(microseconds, period);
+    while counter().map_or(false, |now| now < end) {
+        core::hint::spin_loop();
+    }
+    true
+}
+
+pub fn comparator_count() -> u8 {
+    COMPARATORS.lock().count
+}
+
+/// PCI interrupts are level triggered and shared, a comparator can't use their
+/// IOAPIC inputs. On q35 that is all of GSIs 16 to 23.
+fn pci_gsis() -> u32 {
+    let mut gsis = PCI_GSIS.load(Ordering::Relaxed);
+    if gsis == PCI_GSIS_UNKNOWN {
+        gsis = acpi::aml::pci_gsis()
+            .iter()
+            .filter(|gsi| **gsi < 32)
+            .fold(0, |mask, gsi| mask | 1 << gsi);
+        PCI_GSIS.store(gsis, Ordering::Relaxed);
+    }
+    gsis as u32
+}
+
+/// Pick the IOAPIC input for comparator `index`, out of the ones it can drive
+/// that are masked, aren't used by PCI and no other comparator uses, preferring
+/// those past the ISA IRQs.
+fn choose_gsi(comparators: &Comparators, index: u8, route_capabilities: u32, pci_gsis: u32) -> Option<u32> {
+    let free = |gsi: &u32| {
+        route_capabilities & !pci_gsis & (1 << gsi) != 0
+            && interrupt::is_gsi_masked(*gsi).unwrap_or(false)
+            && !comparators
+                .gsis
+                .iter()
+                .enumerate()
+                .any(|(other, used)| other != index as usize && *used == Some(*gsi))
+    };
+    (FIRST_NON_ISA_GSI..32)
+        .find(|gsi| free(gsi))
+        .or_else(|| (0..FIRST_NON_ISA_GSI).find(|gsi| free(gsi)))
+}
+
+/// Have comparator `index` raise `vector` on the boot CPU, as set by `mode`.
+/// The comparator is routed through the IOAPIC, edge triggered; the handler for
+/// `vector` is the caller's to register.
+pub fn start_comparator(index: u8, vector: u8, mode: EventMode) -> Result<(), HpetError> {
+    let period = period_fs().ok_or(HpetError::NotPresent)?;
+    if !interrupt::apic_mode() {
+        return Err(HpetError::NoIoApic);
+    }
+    let pci_gsis = pci_gsis();
+    let mut comparators = COMPARATORS.lock();
+    if index >= comparators.count {
+        return Err(HpetError::NoComparator(index));
+    }
+    let offset = comparator_configuration(index);
+    let configuration = read(offset);
+    if matches!(mode, EventMode::Periodic(_)) && configuration & COMPARATOR_PERIODIC_CAPABLE == 0 {
+        return Err(HpetError::PeriodicUnsupported(index));
+    }
+
+    let gsi = match comparators.gsis[index as usize] {
+        Some(gsi) => gsi,
+        None => choose_gsi(&comparators, index, (configuration >> 32) as u32, pci_gsis)
+            .ok_or(HpetError::NoRoute(index))?,
+    };
+    interrupt::route_gsi(
+        gsi,
+        vector,
+        interrupt::local_apic_id(),
+        Polarity::ActiveHigh,
+        TriggerMode::Edge,
+    )
+    .map_err(HpetError::Route)?;
+    comparators.gsis[index as usize] = Some(gsi);
+
+    let mut configuration = configuration
+        & !(COMPARATOR_LEVEL_TRIGGERED
+            | COMPARATOR_PERIODIC
+            | COMPARATOR_32BIT_MODE
+            | COMPARATOR_ROUTE_MASK
+            | COMPARATOR_FSB_ENABLE)
+        | (gsi as u64) << COMPARATOR_ROUTE_SHIFT
+        | COMPARATOR_INTERRUPT_ENABLE;
+    match mode {
+        EventMode::OneShot(microseconds) => {
+            write(offset, configuration);
+            let now = counter().unwrap_or(0);
+            write(comparator_value(index), now + ticks(microseconds, period));
+        }
+        EventMode::Periodic(microseconds) => {
+            // With the value-set bit, the first write sets the comparator and the
+            // second the period it is advanced by after each interrupt.
+            configuration |= COMPARATOR_PERIODIC | COMPARATOR_VALUE_SET;
+            write(offset, configuration);
+            let delta = ticks(microseconds, period);
+            let now = counter().unwrap_or(0);
+            write(comparator_value(index), now + delta);
+            write(comparator_value(index), delta);
+        }
+    }
+    Ok(())
+}
@@ -1,63 +1,221 @@
-use crate::arch::memory::map_frame;
-use crate::memory::addr::{PhysicalAddress, VirtualAddress};
-use crate::memory::frame::Frame;
-use crate::memory::page::Page;
+use crate::acpi;
+use crate::arch::cpu;
+use crate::arch::memory::map_mmio_region;
+use crate::arch::{hpet, pit};
+use crate::cmdline::{FlagParam, ParamKind};
+use crate::memory::addr::PhysicalAddress;
 use bit_field::BitField;
 use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 const APIC_MSR: u32 = 0x0000_001B;
+const APIC_MSR_ENABLE: usize = 1 << 11;
+const APIC_MSR_X2APIC_ENABLE: usize = 1 << 10;
+const APIC_MSR_BOOTSTRAP_CPU: usize = 1 << 8;
+/// Spurious interrupt vector register: software enable.
+const SVR_ENABLE: u32 = 1 << 8;
+/// Local vector table entries: the interrupt is masked.
+const LVT_MASKED: u32 = 1 << 16;
+// Timer LVT entry modes, bits 17-18.
+const LVT_TIMER_ONE_SHOT: u32 = 0 << 17;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide configuration register value dividing the timer's clock by 16.
+const TIMER_DIVIDE_BY_16: u32 = 0x3;
+/// In x2APIC mode the register at MMIO offset `n` is MSR `X2APIC_MSR_BASE + n / 16`.
+const X2APIC_MSR_BASE: u32 = 0x800;
+/// How long the timer is measured against the HPET or PIT.
+const CALIBRATION_MS: u32 = 10;
 
-pub fn init_boot_apic() {
-    // Check and see if APIC is enabled
-    assert!(apic_enabled());
+// Vectors the local APIC raises itself, from the top 16 `allocate_vector`
+// leaves alone.
+pub const TIMER_VECTOR: u8 = 0xF0;
+pub const ERROR_VECTOR: u8 = 0xFE;
+/// Vector the local APIC raises for a spurious interrupt. Its low four bits
+/// must be set on older APICs.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
 
-    /*
-    Steps:
-    - reset APIC to known state
-    - enable APIC timer
-    - reset APIC timer counter
-    - wait specific amount of time measured by a different clock
-    - get number of ticks from APIC timer counter
-    - adjust it to a second
-    - divide by the quantum of your choice (results X)
-    - make APIC timer fire an interrupt every X ticks
-     */
+/// Stay in xAPIC mode even if the CPU has an x2APIC, `nox2apic`.
+static NOX2APIC: FlagParam = FlagParam::new(false);
+kernel_param!(NOX2APIC_PARAM, "nox2apic", ParamKind::Flag(&NOX2APIC));
+
+/// Where the boot CPU's local APIC registers are mapped, 0 before `init` and
+/// in x2APIC mode, where they are MSRs.
+static APIC_BASE: AtomicUsize = AtomicUsize::new(0);
+static X2APIC: AtomicBool = AtomicBool::new(false);
+/// Timer ticks per second, after the divider. 0 until `calibrate_timer`.
+static TIMER_FREQUENCY: AtomicUsize = AtomicUsize::new(0);
 
-    let apic_register_value = read_apic_msr();
-    log!("apic msr register value: 0x{:x}", apic_register_value);
+#[derive(Debug)]
+pub enum TimerError {
+    /// `calibrate_timer` hasn't succeeded.
+    NotCalibrated,
+    /// The period doesn't fit the 32-bit count.
+    OutOfRange,
+}
+
+fn base() -> *mut u8 {
+    APIC_BASE.load(Ordering::Relaxed) as *mut u8
+}
 
-    let is_bootstrap_cpu = apic_register_value.get_bit(8);
-    let is_enabled = apic_register_value.get_bit(11);
-    let apic_base_address: usize = apic_register_value.get_bits(12..52);
+/// Map and software-enable the boot CPU's local APIC, switching it to x2APIC
+/// mode if the CPU has one. Interrupts from the IOAPICs are delivered through
+/// it, and acknowledged with `eoi`.
+pub fn init() {
+    assert!(cpu::has_feature(cpu::Feature::Apic));
 
-    assert!(is_enabled);
-    assert!(is_bootstrap_cpu);
-    log!("APIC base address 0x{:x}", apic_base_address);
+    let mut apic_register_value = read_apic_msr();
+    assert!(apic_register_value & APIC_MSR_BOOTSTRAP_CPU != 0);
+    if apic_register_value & APIC_MSR_ENABLE == 0 {
+        apic_register_value |= APIC_MSR_ENABLE;
+        write_apic_msr(apic_register_value);
+    }
 
-    let apic_base_address = apic_base_address as *mut u8;
+    // Firmware may have switched to x2APIC already, and there is no going back
+    // to xAPIC without disabling the APIC.
+    let x2apic = apic_register_value & APIC_MSR_X2APIC_ENABLE != 0
+        || (cpu::has_feature(cpu::Feature::X2Apic) && !NOX2APIC.get());
+    let physical_address = if x2apic {
+        if apic_register_value & APIC_MSR_X2APIC_ENABLE == 0 {
+            write_apic_msr(apic_register_value | APIC_MSR_X2APIC_ENABLE);
+        }
+        X2APIC.store(true, Ordering::Relaxed);
+        None
+    } else {
+        // The MADT has the authoritative address, the MSR is what the CPU uses.
+        let physical_address = acpi::madt()
+            .map(|madt| madt.local_apic_address() as usize)
+            .filter(|address| *address != 0)
+            .unwrap_or(apic_register_value.get_bits(12..52) << 12);
+        let address = map_mmio_region(PhysicalAddress::new(physical_address), 0x1000)
+            .expect("Failure mapping the local APIC.");
+        APIC_BASE.store(address.0, Ordering::Relaxed);
+        Some(physical_address)
+    };
 
     /*
      * APIC registers are aligned to 16-byte offsets and must be accessed using naturally-aligned
      * DWORD size read and writes. All other accesses cause undefined behavior.
      */
-    
-    let spurious = APICRegister::read(APICRegister::SpuriousInterruptVector, apic_base_address);
-    APICRegister::write(APICRegister::SpuriousInterruptVector, apic_base_address, spurious | 1 << 8 | 32);
+    APICRegister::write(APICRegister::TPR, 0);
+    APICRegister::write(APICRegister::TimerLVTEntry, LVT_MASKED | TIMER_VECTOR as u32);
+    // Clear errors latched before the APIC was ours, then report new ones.
+    error_status();
+    APICRegister::write(APICRegister::ErrorVTE, ERROR_VECTOR as u32);
+    // LINT0 carries the 8259s' ExtINT, which are masked in favour of the IOAPICs.
+    APICRegister::write(APICRegister::LocalIntZeroVTE, LVT_MASKED);
+    let spurious = APICRegister::read(APICRegister::SpuriousInterruptVector);
+    APICRegister::write(
+        APICRegister::SpuriousInterruptVector,
+        (spurious & !0xff) | SVR_ENABLE | SPURIOUS_VECTOR as u32,
+    );
+
+    let version = APICRegister::read(APICRegister::Version) & 0xff;
+    match physical_address {
+        Some(address) => log!("local APIC {} at 0x{:x}, version 0x{:x}", id(), address, version),
+        None => log!("local APIC {} in x2APIC mode, version 0x{:x}", id(), version),
+    }
+}
 
-    APICRegister::write(APICRegister::TimerDivideConfiguration, apic_base_address, 0x3);
+/// Whether the local APIC is in x2APIC mode.
+pub fn is_x2apic() -> bool {
+    X2APIC.load(Ordering::Relaxed)
+}
+
+/// The local APIC id of the boot CPU, which IOAPIC entries are addressed to.
+/// 8 bits in xAPIC mode, 32 in x2APIC mode.
+pub fn id() -> u32 {
+    let id = APICRegister::read(APICRegister::Id);
+    if is_x2apic() {
+        id
+    } else {
+        id >> 24
+    }
+}
+
+/// Signal the end of the interrupt being serviced.
+pub fn eoi() {
+    APICRegister::write(APICRegister::EOI, 0);
+}
+
+/// Read and clear the error status register, after an interrupt on `ERROR_VECTOR`.
+pub fn error_status() -> u32 {
+    // The register latches the errors when written.
+    APICRegister::write(APICRegister::ESR, 0);
+    APICRegister::read(APICRegister::ESR)
+}
+
+/// Calibrate the timer and start it ticking `hz` times a second on
+/// `TIMER_VECTOR`. Returns false if it couldn't be calibrated, leaving the tick
+/// to the PIT.
+pub fn init_timer(hz: usize) -> bool {
+    if !calibrate_timer() {
+        return false;
+    }
+    match start_periodic(hz) {
+        Ok(()) => true,
+        Err(error) => {
+            log_at!(crate::logging::Level::Warn, "local APIC timer: {:?}", error);
+            false
+        }
+    }
+}
 
-    pit_prepare_sleep(1000);
+/// Measure the timer's frequency by counting down from the largest count while
+/// the HPET, or the PIT without one, measures `CALIBRATION_MS`.
+pub fn calibrate_timer() -> bool {
+    APICRegister::write(APICRegister::TimerDivideConfiguration, TIMER_DIVIDE_BY_16);
+    APICRegister::write(
+        APICRegister::TimerLVTEntry,
+        LVT_MASKED | LVT_TIMER_ONE_SHOT | TIMER_VECTOR as u32,
+    );
 
     let init: u32 = 0xFFFFFFFF;
-    APICRegister::write(APICRegister::TimerInitialCount, apic_base_address, init);
+    if hpet::is_present() {
+        APICRegister::write(APICRegister::TimerInitialCount, init);
+        hpet::delay_us(CALIBRATION_MS as u64 * 1000);
+    } else {
+        let delay = pit::prepare_delay(CALIBRATION_MS * 1000);
+        APICRegister::write(APICRegister::TimerInitialCount, init);
+        delay.wait();
+    }
+    let remaining = APICRegister::read(APICRegister::TimerCurrentCount);
+    APICRegister::write(APICRegister::TimerInitialCount, 0);
 
-    pit_perform_sleep();
+    // Either the timer didn't run, or it ran out before the PIT did.
+    let ticks = init - remaining;
+    if ticks == 0 || remaining == 0 {
+        log_at!(crate::logging::Level::Warn, "local APIC timer: calibration failed");
+        return false;
+    }
+    let frequency = ticks as usize * 1000 / CALIBRATION_MS as usize;
+    TIMER_FREQUENCY.store(frequency, Ordering::Relaxed);
+    log!("local APIC timer: {} kHz", frequency / 1000);
+    true
+}
 
-    let ticks_in_10_ms = init - APICRegister::read(APICRegister::TimerInitialCount, apic_base_address);
+fn timer_frequency() -> Result<usize, TimerError> {
+    match TIMER_FREQUENCY.load(Ordering::Relaxed) {
+        0 => Err(TimerError::NotCalibrated),
+        frequency => Ok(frequency),
+    }
+}
 
-    APICRegister::write(APICRegister::TimerLVTEntry, apic_base_address, (1 << 16) | 32);
+/// Arm the timer with `count` ticks in the mode in `lvt`.
+fn start_count(lvt: u32, count: usize) -> Result<(), TimerError> {
+    if count > u32::MAX as usize {
+        return Err(TimerError::OutOfRange);
+    }
+    APICRegister::write(APICRegister::TimerLVTEntry, lvt | TIMER_VECTOR as u32);
+    APICRegister::write(APICRegister::TimerDivideConfiguration, TIMER_DIVIDE_BY_16);
+    // Writing the initial count starts the countdown.
+    APICRegister::write(APICRegister::TimerInitialCount, count.max(1) as u32);
+    Ok(())
+}
 
-    unsafe { asm!("sti"); }
+/// Raise `TIMER_VECTOR` `hz` times a second.
+pub fn start_periodic(hz: usize) -> Result<(), TimerError> {
+    let frequency = timer_frequency()?;
+    start_count(LVT_TIMER_PERIODIC, frequency / hz.max(1))
 }
 
 enum APICRegister {
@@ -129,54 +287,63 @@ impl APICRegister {
         }
     }
 
-    fn read(register: APICRegister, apic_base_addr: *const u8) -> u32 {
+    /// The MSR of the register in x2APIC mode.
+    fn msr(&self) -> u32 {
+        X2APIC_MSR_BASE + (self.offset() >> 4) as u32
+    }
+
+    fn read(register: APICRegister) -> u32 {
+        if is_x2apic() {
+            return read_msr(register.msr()) as u32;
+        }
         unsafe {
-            let register: *const u32 = apic_base_addr.offset(register.offset()) as *const u32;
-            register.read()
+            let register: *const u32 = base().offset(register.offset()) as *const u32;
+            register.read_volatile()
         }
     }
 
-    fn write(register: APICRegister, apic_base_addr: *mut u8, value: u32) {
+    fn write(register: APICRegister, value: u32) {
+        if is_x2apic() {
+            return write_msr(register.msr(), value as u64);
+        }
         unsafe {
-            let register: *mut u32 = apic_base_addr.offset(register.offset()) as *mut u32;
-            register.write(value);
+            let register: *mut u32 = base().offset(register.offset()) as *mut u32;
+            register.write_volatile(value);
         }
     }
 }
 
 fn read_apic_msr() -> usize {
+    read_msr(APIC_MSR) as usize
+}
+
+fn read_msr(msr: u32) -> u64 {
     let reg_high: u32;
     let reg_low: u32;
     unsafe {
         asm!(
-            "mov ecx, {msr}",
             "rdmsr",
-            msr = const APIC_MSR,
+            in("ecx") msr,
             lateout("edx") reg_high,
             lateout("eax") reg_low,
+            options(nomem, nostack, preserves_flags),
         );
     }
-    ((reg_high as usize) << 32) | (reg_low as usize)
+    ((reg_high as u64) << 32) | (reg_low as u64)
 }
 
 fn write_apic_msr(value: usize) {
-    unsafe {
-    }
+    write_msr(APIC_MSR, value as u64);
 }
 
-fn apic_enabled() -> bool {
-    let edx: u32;
-    const APIC_CPUID_FUNCTION_NUMBER: u32 = 0x0000_0001;
+fn write_msr(msr: u32, value: u64) {
     unsafe {
-        asm!("cpuid", in("eax") APIC_CPUID_FUNCTION_NUMBER, lateout("edx") edx);
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags),
+        );
     }
-    edx.get_bit(9)
-}
-
-fn pit_prepare_sleep(_ms: u32) {
-    
-}
-
-fn pit_perform_sleep() {
-
 }
@@ -1,208 +1,217 @@
-use core::arch::asm;
-
-#[derive(Debug)]
-#[repr(C)]
-pub struct ExceptionStackFrame {
-    intruction_ptr: u64,
-    code_segment: u64,
-    cpu_flags: u64,
-    stack_pointer: u64,
-    stack_segment: u64,
+use super::trap::TrapFrame;
+use super::{apic, IrqResult};
+use crate::arch::extable;
+use crate::arch::memory::fault::{handle_page_fault, page_walk, PageFaultErrorCode};
+use crate::arch::memory::stack_allocator::guard_page_owner;
+use crate::memory::addr::VirtualAddress;
+
+pub type ExceptionHandlerFn = fn(frame: &mut TrapFrame);
+
+/// Handlers for the 32 CPU exception vectors, indexed by vector.
+pub static EXCEPTION_HANDLERS: [ExceptionHandlerFn; 32] = [
+    divide_by_zero_handler,
+    debug_handler,
+    non_maskable_handler,
+    breakpoint_handler,
+    overflow_handler,
+    bound_range_handler,
+    invalid_opcode_handler,
+    device_not_available_handler,
+    double_fault_handler,
+    reserved_handler,
+    invalid_tss_handler,
+    segment_not_present_handler,
+    stack_handler,
+    general_protection_handler,
+    page_fault_handler,
+    reserved_handler,
+    x87_floating_point_handler,
+    alignment_check_handler,
+    machine_check_handler,
+    simd_floating_point_handler,
+    reserved_handler,
+    control_protection_handler,
+    reserved_handler,
+    reserved_handler,
+    reserved_handler,
+    reserved_handler,
+    reserved_handler,
+    reserved_handler,
+    hypervisor_injection_handler,
+    vmm_communication_handler,
+    security_handler,
+    reserved_handler,
+];
+
+/// Resume at the fixup if the faulting instruction has one, see extable.rs.
+fn apply_fixup(frame: &mut TrapFrame) -> bool {
+    match extable::search(frame.rip) {
+        Some(fixup) => {
+            frame.rip = fixup;
+            true
+        }
+        None => false,
+    }
 }
 
-#[derive(Debug)]
-#[repr(C)]
-pub struct InterruptStackFrame {
-    instruction_ptr: u64,
-    // TODO this is a stub
+/// Report an exception the kernel can't recover from and stop.
+fn fatal(name: &str, frame: &TrapFrame) -> ! {
+    log!("EXCEPTION: {}\n{:?}", name, frame);
+    loop {}
 }
 
-pub extern "C" fn divide_by_zero_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: divide by zero\n{:?}", stack_frame);
-    loop {}
+fn divide_by_zero_handler(frame: &mut TrapFrame) {
+    fatal("divide by zero", frame);
 }
 
-pub extern "C" fn debug_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: debug\n{:?}", stack_frame);
-    loop {}
+fn debug_handler(frame: &mut TrapFrame) {
+    fatal("debug", frame);
 }
 
-pub extern "C" fn non_maskable_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: non maskable interrupt\n{:?}", stack_frame);
-    loop {}
+/// Runs on its own IST stack: an NMI can arrive at any instruction, including
+/// before a syscall or interrupt entry has switched away from a user stack.
+fn non_maskable_handler(frame: &mut TrapFrame) {
+    fatal("non maskable interrupt", frame);
 }
 
-pub extern "C" fn breakpoint_handler() {
+fn breakpoint_handler(_frame: &mut TrapFrame) {
     log!("EXCEPTION: breakpoint");
 }
 
-pub extern "C" fn overflow_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: overflow\n{:?}", stack_frame);
-    loop {}
+fn overflow_handler(frame: &mut TrapFrame) {
+    fatal("overflow", frame);
 }
 
-pub extern "C" fn bound_range_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: bound range\n{:?}", stack_frame);
-    loop {}
+fn bound_range_handler(frame: &mut TrapFrame) {
+    fatal("bound range", frame);
 }
 
-pub extern "C" fn invalid_opcode_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: invalid opcode\n{:?}", stack_frame);
-    loop {}
+fn invalid_opcode_handler(frame: &mut TrapFrame) {
+    fatal("invalid opcode", frame);
 }
 
-pub extern "C" fn device_not_available_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: device not available\n{:?}", stack_frame);
-    loop {}
+fn device_not_available_handler(frame: &mut TrapFrame) {
+    fatal("device not available", frame);
 }
 
-pub extern "C" fn double_fault_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
+/// Runs on its own IST stack, so a fault that couldn't be delivered because the
+/// kernel stack is unusable still gets reported instead of triple faulting.
+fn double_fault_handler(frame: &mut TrapFrame) {
+    let stack_pointer = VirtualAddress::new(frame.rsp as usize);
+    let fault_address = VirtualAddress::new(frame.cr2 as usize);
+    if let Some(owner) = guard_page_owner(stack_pointer).or_else(|| guard_page_owner(fault_address)) {
+        log!("EXCEPTION: double fault, kernel stack overflow in '{}'\n{:?}", owner, frame);
+        loop {}
+    }
     // Error code should always be zero.
-    log!("EXCEPTION: double fault\n{:?}\nerror_code: {}", stack_frame, error_code);
-    loop {}
+    fatal("double fault", frame);
 }
 
-pub extern "C" fn invalid_tss_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
-    log!("EXCEPTION: invalid tss\n{:?}\nerror_code: {}", stack_frame, error_code);
-    loop {}
+fn invalid_tss_handler(frame: &mut TrapFrame) {
+    fatal("invalid tss", frame);
 }
 
-pub extern "C" fn segment_not_present_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
-    log!("EXCEPTION: segment not present\n{:?}\nerror_code: {}", stack_frame, error_code);
-    loop {}
+fn segment_not_present_handler(frame: &mut TrapFrame) {
+    fatal("segment not present", frame);
 }
 
-pub extern "C" fn stack_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
-    log!("EXCEPTION: stack\n{:?}\nerror_code: {}", stack_frame, error_code);
-    loop {}
+fn stack_handler(frame: &mut TrapFrame) {
+    fatal("stack", frame);
 }
 
-pub extern "C" fn general_protection_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
-    log!("EXCEPTION: general protection\n{:?}\nerror_code: {}", stack_frame, error_code);
+fn general_protection_handler(frame: &mut TrapFrame) {
+    if apply_fixup(frame) {
+        return;
+    }
+    fatal("general protection", frame);
+}
+
+/// Runs on the stack of the code that faulted, so it may nest. Faults the memory
+/// code can resolve return to the faulting instruction, faults with a fixup
+/// resume there, anything else is reported with the decoded error code and the
+/// page table entries for the address. A kernel stack running into its guard
+/// page ends up in the double fault handler instead.
+fn page_fault_handler(frame: &mut TrapFrame) {
+    let fault_address = VirtualAddress::new(frame.cr2 as usize);
+    let error = PageFaultErrorCode(frame.error_code);
+    let reason = match handle_page_fault(fault_address, error) {
+        Ok(()) => return,
+        Err(reason) => reason,
+    };
+    if apply_fixup(frame) {
+        return;
+    }
+
+    if let Some(owner) = guard_page_owner(fault_address) {
+        log!(
+            "EXCEPTION: kernel stack overflow in '{}' (fault address 0x{:x})\n{:?}",
+            owner,
+            fault_address.0,
+            frame
+        );
+        loop {}
+    }
+    log!("EXCEPTION: page fault at 0x{:x}, {}: {:?}", fault_address.0, error, reason);
+    match page_walk(fault_address) {
+        Some(walk) => log!("page walk: {}", walk),
+        None => log!("page walk: page tables in use"),
+    }
+    log!("{:?}", frame);
     loop {}
 }
 
-pub extern "C" fn page_fault_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
-    log!("EXCEPTION: page fault\n{:?}\nerror_code: {}", stack_frame, error_code);
-    loop {}
+fn x87_floating_point_handler(frame: &mut TrapFrame) {
+    fatal("x87 floating point", frame);
 }
 
-pub extern "C" fn x87_floating_point_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: x87 floating point\n{:?}", stack_frame);
-    loop {}
+fn alignment_check_handler(frame: &mut TrapFrame) {
+    fatal("alignment check", frame);
 }
 
-pub extern "C" fn alignment_check_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
-    log!("EXCEPTION: alignment check\n{:?}\nerror_code: {}", stack_frame, error_code);
-    loop {}
+/// Runs on its own IST stack, like the NMI.
+fn machine_check_handler(frame: &mut TrapFrame) {
+    fatal("machine check", frame);
 }
 
-pub extern "C" fn machine_check_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: machine check\n{:?}", stack_frame);
-    loop {}
+fn simd_floating_point_handler(frame: &mut TrapFrame) {
+    fatal("simd floating point", frame);
 }
 
-pub extern "C" fn simd_floating_point_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: simd floating point\n{:?}", stack_frame);
-    loop {}
+fn control_protection_handler(frame: &mut TrapFrame) {
+    fatal("control protection", frame);
 }
 
-pub extern "C" fn control_protection_handler(stack_frame: &ExceptionStackFrame, error_code: u64) -> ! {
-    log!("EXCEPTION: control protection\n{:?}\nerror_code: {}", stack_frame, error_code);
-    loop {}
+fn hypervisor_injection_handler(frame: &mut TrapFrame) {
+    fatal("hypervisor injection", frame);
 }
 
-pub extern "C" fn hypervisor_injection_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: hypervisor injection\n{:?}", stack_frame);
-    loop {}
+fn vmm_communication_handler(frame: &mut TrapFrame) {
+    fatal("vmm communication", frame);
 }
 
-pub extern "C" fn vmm_communication_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: vmm conmmunication\n{:?}", stack_frame);
-    loop {}
+fn security_handler(frame: &mut TrapFrame) {
+    fatal("security", frame);
 }
 
-pub extern "C" fn security_handler(stack_frame: &ExceptionStackFrame) -> ! {
-    log!("EXCEPTION: security\n{:?}", stack_frame);
-    loop {}
+/// Vectors below 32 the architecture doesn't define.
+fn reserved_handler(frame: &mut TrapFrame) {
+    fatal("reserved vector", frame);
 }
 
-pub extern "C" fn timer_handler(stack_frame: &InterruptStackFrame) {
-    log!(".");
-    unsafe {
-        super::PICS.lock().notify_end_of_interrupt(32);
-    }
+pub fn timer_handler(_vector: u8, _context: usize) -> IrqResult {
+    crate::arch::time::tick();
+    IrqResult::Handled
 }
 
-pub extern "C" fn spurious_handler(stack_fram: &InterruptStackFrame) {
+/// IRQ 7 from the master PIC, or the local APIC's spurious vector, raised when
+/// an interrupt went away before it was acknowledged.
+pub fn spurious_handler(_vector: u8, _context: usize) -> IrqResult {
     log!("spurious");
+    IrqResult::Handled
 }
 
-macro_rules! handler {
-    ($name:ident) => {{
-        #[naked]
-        extern "C" fn wrapper() -> ! {
-            unsafe {
-                asm!("mov rdi, rsp; sub rsp, 8; call {x}; iretq",
-                     x = sym $name, options(noreturn))
-            }
-        }
-        wrapper
-    }};
-}
-
-macro_rules! handler_with_error_code {
-    ($name:ident) => {{
-        #[naked]
-        extern "C" fn wrapper() -> ! {
-            unsafe {
-                asm!("pop rsi; mov rdi, rsp; sub rsp, 8; call {x}; iretq",
-                     x = sym $name, options(noreturn))
-            }
-        }
-        wrapper
-    }};
-}
-
-macro_rules! isr {
-    ($name:ident) => {{
-        #[naked]
-        extern "C" fn wrapper() -> ! {
-            // sub rsp to correctly align stack ptr for extern 'C' calls
-            unsafe {
-                asm!("push rax
-                      push rcx
-                      push rdx
-                      push rbx
-                      push rsp
-                      push rbp
-                      push rsi
-                      push rdi
-                      push r8
-                      push r9
-                      push r10
-                      push r11
-                      mov rsi, [rsp + 9*8]
-                      mov rdi, rsp
-                      sub rsp, 8
-                      call {x}
-                      add rsp, 8
-                      pop r11
-                      pop r10
-                      pop r9
-                      pop r8
-                      pop rdi
-                      pop rsi
-                      pop rbp
-                      pop rsp
-                      pop rbx
-                      pop rdx
-                      pop rcx
-                      pop rax
-                      iretq
-                      ", x = sym $name, options(noreturn));
-            }
-        }
-        wrapper
-    }};
+/// The local APIC found an error, e.g. an illegal vector or a failed IPI.
+pub fn apic_error_handler(_vector: u8, _context: usize) -> IrqResult {
+    log_at!(crate::logging::Level::Warn, "local APIC error 0x{:x}", apic::error_status());
+    IrqResult::Handled
 }
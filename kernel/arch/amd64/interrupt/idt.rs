@@ -1,9 +1,8 @@
+use crate::arch::gdt::KERNEL_CODE_SELECTOR;
 use bit_field::BitField;
 use core::arch::asm;
 
-pub type InterruptHandlerFn = extern "C" fn() -> !;
-
-const IDT_SIZE: usize = 40;
+const IDT_SIZE: usize = 256;
 
 #[repr(C, packed)]
 struct DescriptorTablePointer {
@@ -18,8 +17,9 @@ impl InterruptDescriptorTable {
         Self([IDTEntry::missing(); IDT_SIZE])
     }
 
-    pub fn set_handler(&mut self, vector: usize, handler: InterruptHandlerFn) -> &mut EntryOptions {
-        self.0[vector] = IDTEntry::new(cs(), handler);
+    /// Point `vector` at an entry stub, see trap.rs.
+    pub fn set_handler(&mut self, vector: usize, address: u64) -> &mut EntryOptions {
+        self.0[vector] = IDTEntry::new(KERNEL_CODE_SELECTOR, address);
         &mut self.0[vector].options
     }
 
@@ -62,8 +62,7 @@ impl IDTEntry {
         }
     }
 
-    fn new(gdt_selector: u16, handler_addr: InterruptHandlerFn) -> Self {
-        let handler_bits = handler_addr as u64;
+    fn new(gdt_selector: u16, handler_bits: u64) -> Self {
         Self {
             isr_low: handler_bits as u16,
             gdt_selector: gdt_selector,
@@ -120,11 +119,3 @@ impl EntryOptions {
         self
     }
 }
-
-fn cs() -> u16 {
-    let segment: u16;
-    unsafe {
-        asm!(concat!("mov {0:x}, ", "cs"), out(reg) segment, options(nomem, nostack, preserves_flags));
-    }
-    segment
-}
@@ -0,0 +1,214 @@
+use crate::acpi::madt::{Madt, MadtEntry};
+use crate::arch::memory::map_mmio_region;
+use crate::memory::addr::PhysicalAddress;
+use alloc::vec::Vec;
+use spin::mutex::Mutex;
+
+// Registers are reached indirectly: write the register number to IOREGSEL, then
+// read or write IOWIN.
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const IOAPICVER: u32 = 0x01;
+/// Redirection table entry `n` is the register pair starting here + 2n.
+const IOREDTBL: u32 = 0x10;
+
+// Redirection entry bits.
+const ACTIVE_LOW: u64 = 1 << 13;
+const LEVEL_TRIGGERED: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+
+/// Number of ISA IRQs, the ones the MADT can override.
+const ISA_IRQS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+#[derive(Debug)]
+pub enum IoApicError {
+    /// No IOAPIC handles the GSI.
+    NoIoApic(u32),
+}
+
+struct IoApic {
+    /// Where the registers are mapped.
+    base: usize,
+    gsi_base: u32,
+    /// Number of redirection entries, i.e. of GSIs handled.
+    entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(register);
+            ((self.base + IOWIN) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(register);
+            ((self.base + IOWIN) as *mut u32).write_volatile(value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn read_entry(&self, index: u32) -> u64 {
+        let low = self.read(IOREDTBL + 2 * index) as u64;
+        let high = self.read(IOREDTBL + 2 * index + 1) as u64;
+        high << 32 | low
+    }
+
+    fn write_entry(&self, index: u32, entry: u64) {
+        // Mask first, so the entry never fires half written.
+        self.write(IOREDTBL + 2 * index, MASKED as u32);
+        self.write(IOREDTBL + 2 * index + 1, (entry >> 32) as u32);
+        self.write(IOREDTBL + 2 * index, entry as u32);
+    }
+}
+
+/// How an ISA IRQ is wired, from the MADT's interrupt source overrides.
+#[derive(Clone, Copy)]
+struct IsaRoute {
+    gsi: u32,
+    polarity: Polarity,
+    trigger: TriggerMode,
+}
+
+struct IoApics {
+    ioapics: Vec<IoApic>,
+    isa_routes: [IsaRoute; ISA_IRQS],
+}
+
+static IOAPICS: Mutex<Option<IoApics>> = Mutex::new(None);
+
+/// Decode the polarity and trigger bits of an MADT interrupt override, falling
+/// back to the bus defaults for ISA: active high, edge triggered.
+fn override_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0x3 {
+        0x3 => Polarity::ActiveLow,
+        _ => Polarity::ActiveHigh,
+    };
+    let trigger = match (flags >> 2) & 0x3 {
+        0x3 => TriggerMode::Level,
+        _ => TriggerMode::Edge,
+    };
+    (polarity, trigger)
+}
+
+/// Map the IOAPICs listed in the MADT and mask every input. Returns false if
+/// there are none.
+pub fn init(madt: &Madt) -> bool {
+    let mut ioapics = Vec::new();
+    let mut isa_routes = [IsaRoute {
+        gsi: 0,
+        polarity: Polarity::ActiveHigh,
+        trigger: TriggerMode::Edge,
+    }; ISA_IRQS];
+    for (irq, route) in isa_routes.iter_mut().enumerate() {
+        route.gsi = irq as u32;
+    }
+
+    for entry in madt.entries() {
+        match entry {
+            MadtEntry::IoApic { id, address, gsi_base } => {
+                let base = match map_mmio_region(PhysicalAddress::new(address as usize), 0x1000) {
+                    Ok(base) => base.0,
+                    Err(_) => {
+                        log_at!(crate::logging::Level::Warn, "IOAPIC {}: failed to map 0x{:x}", id, address);
+                        continue;
+                    }
+                };
+                let mut ioapic = IoApic {
+                    base,
+                    gsi_base,
+                    entries: 0,
+                };
+                ioapic.entries = ((ioapic.read(IOAPICVER) >> 16) & 0xff) + 1;
+                for index in 0..ioapic.entries {
+                    ioapic.write_entry(index, MASKED);
+                }
+                log!(
+                    "IOAPIC {} at 0x{:x}: GSIs {}-{}",
+                    id,
+                    address,
+                    gsi_base,
+                    gsi_base + ioapic.entries - 1
+                );
+                ioapics.push(ioapic);
+            }
+            MadtEntry::InterruptOverride { bus: 0, source, gsi, flags } if (source as usize) < ISA_IRQS => {
+                let (polarity, trigger) = override_flags(flags);
+                isa_routes[source as usize] = IsaRoute { gsi, polarity, trigger };
+            }
+            _ => {}
+        }
+    }
+
+    let found = !ioapics.is_empty();
+    *IOAPICS.lock() = Some(IoApics { ioapics, isa_routes });
+    found
+}
+
+fn with_ioapic<T>(gsi: u32, f: impl FnOnce(&IoApic, u32) -> T) -> Result<T, IoApicError> {
+    let guard = IOAPICS.lock();
+    let ioapic = guard
+        .as_ref()
+        .and_then(|state| state.ioapics.iter().find(|ioapic| ioapic.handles(gsi)))
+        .ok_or(IoApicError::NoIoApic(gsi))?;
+    Ok(f(ioapic, gsi - ioapic.gsi_base))
+}
+
+/// Deliver `gsi` as `vector` to the CPU with local APIC id `destination`. The
+/// input is left unmasked.
+pub fn route_gsi(
+    gsi: u32,
+    vector: u8,
+    destination: u32,
+    polarity: Polarity,
+    trigger: TriggerMode,
+) -> Result<(), IoApicError> {
+    // Fixed delivery, physical destination mode. The destination field is 8 bits,
+    // x2APIC ids above 255 can't be reached without interrupt remapping.
+    let mut entry = vector as u64 | (destination as u64 & 0xff) << 56;
+    if polarity == Polarity::ActiveLow {
+        entry |= ACTIVE_LOW;
+    }
+    if trigger == TriggerMode::Level {
+        entry |= LEVEL_TRIGGERED;
+    }
+    with_ioapic(gsi, |ioapic, index| ioapic.write_entry(index, entry))
+}
+
+/// The GSI, polarity and trigger mode of an ISA IRQ, after the MADT overrides.
+pub fn isa_route(irq: u8) -> (u32, Polarity, TriggerMode) {
+    let guard = IOAPICS.lock();
+    match guard.as_ref().and_then(|state| state.isa_routes.get(irq as usize)) {
+        Some(route) => (route.gsi, route.polarity, route.trigger),
+        None => (irq as u32, Polarity::ActiveHigh, TriggerMode::Edge),
+    }
+}
+
+/// Deliver ISA IRQ `irq` as `vector` to the CPU with local APIC id `destination`.
+pub fn route_isa_irq(irq: u8, vector: u8, destination: u32) -> Result<(), IoApicError> {
+    let (gsi, polarity, trigger) = isa_route(irq);
+    route_gsi(gsi, vector, destination, polarity, trigger)
+}
+
+/// Whether `gsi` is masked, i.e. free unless something routed it and masked it
+/// again.
+pub fn is_gsi_masked(gsi: u32) -> Result<bool, IoApicError> {
+    with_ioapic(gsi, |ioapic, index| ioapic.read_entry(index) & MASKED != 0)
+}
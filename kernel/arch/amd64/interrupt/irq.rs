@@ -0,0 +1,148 @@
+use super::trap::TrapFrame;
+use super::{apic, apic_mode, without_interrupts, PICS, PIC_1_OFFSET, PIC_2_OFFSET};
+use spin::mutex::Mutex;
+
+/// First vector past the CPU exceptions.
+pub const FIRST_IRQ_VECTOR: u8 = 32;
+/// Vectors handed out by `allocate_vector`: above the ISA IRQs, below the top
+/// 16 vectors, which are kept for the local APIC.
+const FIRST_DYNAMIC_VECTOR: u8 = PIC_2_OFFSET + 8;
+const LAST_DYNAMIC_VECTOR: u8 = 0xEF;
+/// Vector the master PIC raises for a spurious IRQ 7.
+const PIC_SPURIOUS_VECTOR: u8 = PIC_1_OFFSET + 7;
+/// How many handlers can share one vector.
+const MAX_SHARED_HANDLERS: usize = 4;
+
+/// Whether a handler recognised the interrupt as coming from its device. Every
+/// handler registered on a vector is called until one claims it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum IrqResult {
+    Handled,
+    NotHandled,
+}
+
+/// An interrupt handler, called with the vector and the context it was
+/// registered with.
+pub type IrqHandlerFn = fn(vector: u8, context: usize) -> IrqResult;
+
+#[derive(Debug)]
+pub enum IrqError {
+    /// The vector is a CPU exception.
+    ReservedVector,
+    /// The vector already has `MAX_SHARED_HANDLERS` handlers.
+    VectorFull,
+    NotRegistered,
+    NoFreeVector,
+}
+
+#[derive(Clone, Copy)]
+struct Registration {
+    handler: IrqHandlerFn,
+    context: usize,
+}
+
+struct IrqTable {
+    handlers: [[Option<Registration>; MAX_SHARED_HANDLERS]; 256],
+    /// Vectors handed out by `allocate_vector`, one bit each.
+    allocated: [u64; 4],
+}
+
+/// Taken with interrupts disabled, dispatch runs with them disabled too, so the
+/// lock can't be held by code the interrupt preempted.
+static IRQ_TABLE: Mutex<IrqTable> = Mutex::new(IrqTable {
+    handlers: [[None; MAX_SHARED_HANDLERS]; 256],
+    allocated: [0; 4],
+});
+
+pub(super) fn dispatch(frame: &TrapFrame) {
+    let vector = frame.vector as u8;
+    // Copied out so a handler can register or unregister handlers itself.
+    let handlers = IRQ_TABLE.lock().handlers[vector as usize];
+    let handled = handlers
+        .iter()
+        .flatten()
+        .any(|registration| (registration.handler)(vector, registration.context) == IrqResult::Handled);
+    if !handled && vector != PIC_SPURIOUS_VECTOR {
+        log_at!(crate::logging::Level::Warn, "unexpected interrupt on vector {}", vector);
+    }
+    end_of_interrupt(vector);
+}
+
+fn end_of_interrupt(vector: u8) {
+    if apic_mode() {
+        // The local APIC doesn't mark a spurious interrupt in service either.
+        if vector != apic::SPURIOUS_VECTOR {
+            apic::eoi();
+        }
+        return;
+    }
+    // A spurious IRQ 7 was never marked in service, so it gets no EOI.
+    if (PIC_1_OFFSET..PIC_2_OFFSET + 8).contains(&vector) && vector != PIC_SPURIOUS_VECTOR {
+        unsafe {
+            PICS.lock().notify_end_of_interrupt(vector);
+        }
+    }
+}
+
+/// Call `handler` with `context` whenever `vector` fires. A vector can be shared
+/// by several handlers; they are called in registration order until one of them
+/// returns `IrqResult::Handled`.
+pub fn register_irq_handler(vector: u8, handler: IrqHandlerFn, context: usize) -> Result<(), IrqError> {
+    if vector < FIRST_IRQ_VECTOR {
+        return Err(IrqError::ReservedVector);
+    }
+    without_interrupts(|| {
+        let mut table = IRQ_TABLE.lock();
+        let slot = table.handlers[vector as usize]
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(IrqError::VectorFull)?;
+        *slot = Some(Registration { handler, context });
+        Ok(())
+    })
+}
+
+/// Remove a handler registered with `register_irq_handler`. Both `handler` and
+/// `context` have to match, so one driver can't remove another's handler on a
+/// shared vector.
+pub fn unregister_irq_handler(vector: u8, handler: IrqHandlerFn, context: usize) -> Result<(), IrqError> {
+    without_interrupts(|| {
+        let mut table = IRQ_TABLE.lock();
+        let handlers = &mut table.handlers[vector as usize];
+        let index = handlers
+            .iter()
+            .position(|slot| {
+                slot.map_or(false, |registration| {
+                    registration.handler as usize == handler as usize && registration.context == context
+                })
+            })
+            .ok_or(IrqError::NotRegistered)?;
+        // Keep the remaining handlers in registration order.
+        handlers[index..].rotate_left(1);
+        handlers[MAX_SHARED_HANDLERS - 1] = None;
+        Ok(())
+    })
+}
+
+/// Reserve an unused vector for a device, e.g. for an MSI or an IOAPIC pin.
+pub fn allocate_vector() -> Result<u8, IrqError> {
+    without_interrupts(|| {
+        let mut table = IRQ_TABLE.lock();
+        let vector = (FIRST_DYNAMIC_VECTOR..=LAST_DYNAMIC_VECTOR)
+            .find(|vector| {
+                let vector = *vector as usize;
+                table.allocated[vector / 64] & (1 << (vector % 64)) == 0
+                    && table.handlers[vector].iter().all(Option::is_none)
+            })
+            .ok_or(IrqError::NoFreeVector)?;
+        table.allocated[vector as usize / 64] |= 1 << (vector % 64);
+        Ok(vector)
+    })
+}
+
+/// Give back a vector from `allocate_vector`.
+pub fn free_vector(vector: u8) {
+    without_interrupts(|| {
+        IRQ_TABLE.lock().allocated[vector as usize / 64] &= !(1 << (vector % 64));
+    })
+}
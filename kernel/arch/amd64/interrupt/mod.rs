@@ -1,61 +1,77 @@
 mod apic;
-#[macro_use]
 mod handlers;
 mod idt;
+mod ioapic;
+mod irq;
+mod trap;
 
 use core::arch::asm;
-use crate::arch::memory::page_mapper::PageMapper;
-use crate::memory::addr::VirtualAddress;
-use handlers::{
-    alignment_check_handler, bound_range_handler, breakpoint_handler, control_protection_handler,
-    debug_handler, device_not_available_handler, divide_by_zero_handler, double_fault_handler,
-    general_protection_handler, hypervisor_injection_handler, invalid_opcode_handler,
-    invalid_tss_handler, machine_check_handler, non_maskable_handler, overflow_handler,
-    page_fault_handler, security_handler, segment_not_present_handler, simd_floating_point_handler,
-    stack_handler, vmm_communication_handler, x87_floating_point_handler, timer_handler, spurious_handler,
+use handlers::{apic_error_handler, spurious_handler, timer_handler};
+pub use ioapic::{is_gsi_masked, route_gsi, IoApicError, Polarity, TriggerMode};
+pub use irq::{
+    allocate_vector, free_vector, register_irq_handler, unregister_irq_handler, IrqError, IrqHandlerFn, IrqResult,
 };
-use idt::{InterruptDescriptorTable, InterruptHandlerFn};
+use crate::acpi;
+use crate::arch::cpu;
+use crate::arch::gdt;
+use crate::arch::hpet;
+use crate::arch::x86_io::{inb, outb};
+use crate::arch::pit;
+use crate::cmdline::{FlagParam, ParamKind, UsizeParam};
+use crate::arch::registers::{read_rflags, RFLAGS_INTERRUPT_ENABLE};
+use crate::arch::tss::{DOUBLE_FAULT_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX};
+use idt::InterruptDescriptorTable;
 use pic8259::ChainedPics;
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::mutex::Mutex;
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.set_handler(0, handler!(divide_by_zero_handler));
-        idt.set_handler(1, handler!(debug_handler));
-        idt.set_handler(2, handler!(non_maskable_handler));
-        idt.set_handler(3, isr!(breakpoint_handler));
-        idt.set_handler(4, handler!(overflow_handler));
-        idt.set_handler(5, handler!(bound_range_handler));
-        idt.set_handler(6, handler!(invalid_opcode_handler));
-        idt.set_handler(7, handler!(device_not_available_handler));
-        idt.set_handler(8, handler_with_error_code!(double_fault_handler));
-        idt.set_handler(10, handler_with_error_code!(invalid_tss_handler));
-        idt.set_handler(11, handler_with_error_code!(segment_not_present_handler));
-        idt.set_handler(12, handler_with_error_code!(stack_handler));
-        idt.set_handler(13, handler_with_error_code!(general_protection_handler));
-        idt.set_handler(14, handler_with_error_code!(page_fault_handler));
-        idt.set_handler(16, handler!(x87_floating_point_handler));
-        idt.set_handler(17, handler_with_error_code!(alignment_check_handler));
-        idt.set_handler(18, handler!(machine_check_handler));
-        idt.set_handler(19, handler!(simd_floating_point_handler));
-        idt.set_handler(21, handler_with_error_code!(control_protection_handler));
-        idt.set_handler(28, handler!(hypervisor_injection_handler));
-        idt.set_handler(29, handler!(vmm_communication_handler));
-        idt.set_handler(30, handler!(security_handler));
-        idt.set_handler(32, isr!(timer_handler));
-        idt.set_handler(39, handler!(spurious_handler));
+        // Every vector enters through the same path, see trap.rs. Exceptions that
+        // can hit a broken stack, or any instruction at all, get stacks of their own.
+        // Page faults stay on the current stack: the handler can fault again, which
+        // would restart at the top of an IST stack and overwrite the outer frame. A
+        // fault on a guard page can't push its frame and becomes a double fault.
+        for vector in 0..=255u8 {
+            let options = idt.set_handler(vector as usize, trap::stub(vector));
+            match vector {
+                2 => options.set_stack_index(NMI_IST_INDEX),
+                8 => options.set_stack_index(DOUBLE_FAULT_IST_INDEX),
+                18 => options.set_stack_index(MACHINE_CHECK_IST_INDEX),
+                _ => options,
+            };
+        }
         idt
     };
 }
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+/// ISA IRQ `n` is delivered at vector `ISA_IRQ_BASE + n`, whether it comes
+/// through the PICs or an IOAPIC.
+pub const ISA_IRQ_BASE: u8 = PIC_1_OFFSET;
+
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_DATA: u16 = 0xA1;
 
 pub static PICS: spin::Mutex<ChainedPics> = Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
-pub fn init() {
+/// Timer interrupt frequency in Hz, `timer_hz=<n>`.
+static TIMER_HZ: UsizeParam = UsizeParam::new(100, 1, 10_000);
+kernel_param!(TIMER_HZ_PARAM, "timer_hz", ParamKind::Usize(&TIMER_HZ));
+
+/// Stay on the 8259 PICs even if there are IOAPICs, `noapic`.
+static NOAPIC: FlagParam = FlagParam::new(false);
+kernel_param!(NOAPIC_PARAM, "noapic", ParamKind::Flag(&NOAPIC));
 
+/// Set once interrupts are routed through the IOAPICs and the local APIC.
+static APIC_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn init() {
+    // The GDT and its TSS have to be in place before any IDT entry that switches
+    // to an IST stack can fire.
+    gdt::init();
     IDT.load();
     
     unsafe {
@@ -63,11 +79,133 @@ pub fn init() {
     }
     log!("We made it back :)");
 
+    // IRQ 0 from the PIT is the tick, unless the local APIC timer or HPET takes over.
+    register_irq_handler(ISA_IRQ_BASE, timer_handler, 0).expect("registering the timer handler");
+    register_irq_handler(PIC_1_OFFSET + 7, spurious_handler, 0).expect("registering the spurious handler");
+
+    // Remap the PICs even when they end up masked, so a stray interrupt from them
+    // can't be mistaken for a CPU exception.
     unsafe {
         PICS.lock().initialize();
+    }
+    if !NOAPIC.get() && init_apic() {
+        log!("interrupts routed through the IOAPIC");
+    } else {
+        pit::start_periodic(TIMER_HZ.get());
+        log!("interrupts routed through the 8259 PIC");
+    }
+
+    unsafe {
         asm!("sti");
     }
 
     // Page Fault time :)
     //    unsafe { *(0xdeadbeaf as *mut u64) = 42 };
 }
+
+/// Switch from the PICs to the IOAPICs described in the MADT. Returns false,
+/// leaving the PICs in charge, if there is no MADT or IOAPIC.
+fn init_apic() -> bool {
+    if !cpu::has_feature(cpu::Feature::Apic) {
+        return false;
+    }
+    let madt = match acpi::madt() {
+        Some(madt) => madt,
+        None => return false,
+    };
+    if !ioapic::init(&madt) {
+        return false;
+    }
+
+    unsafe {
+        outb(PIC_1_DATA, 0xff);
+        outb(PIC_2_DATA, 0xff);
+    }
+    apic::init();
+    register_irq_handler(apic::SPURIOUS_VECTOR, spurious_handler, 0).expect("registering the spurious handler");
+    register_irq_handler(apic::ERROR_VECTOR, apic_error_handler, 0).expect("registering the APIC error handler");
+    APIC_MODE.store(true, Ordering::Relaxed);
+
+    // Tell the firmware, so _PRT describes IOAPIC inputs rather than PIC IRQs.
+    // The HPET tick relies on it to keep off the PCI interrupt inputs.
+    match acpi::aml::evaluate("\\_PIC", alloc::vec![acpi::aml::AmlValue::Integer(1)]) {
+        Ok(_) | Err(acpi::aml::AmlError::NotFound(_)) | Err(acpi::aml::AmlError::Unavailable) => {}
+        Err(error) => log_at!(crate::logging::Level::Warn, "\\_PIC failed: {}", error),
+    }
+
+    if apic::init_timer(TIMER_HZ.get()) {
+        register_irq_handler(apic::TIMER_VECTOR, timer_handler, 0).expect("registering the timer handler");
+    } else if !start_hpet_tick(TIMER_HZ.get()) {
+        pit::start_periodic(TIMER_HZ.get());
+        enable_isa_irq(0);
+    }
+    true
+}
+
+/// Tick from HPET comparator 0 when the local APIC timer can't be used. Returns
+/// false if there is no HPET or the comparator couldn't be started.
+fn start_hpet_tick(hz: usize) -> bool {
+    if !hpet::is_present() {
+        return false;
+    }
+    let vector = match allocate_vector() {
+        Ok(vector) => vector,
+        Err(_) => return false,
+    };
+    register_irq_handler(vector, timer_handler, 0).expect("registering the timer handler");
+    let period = 1_000_000 / hz.max(1) as u64;
+    match hpet::start_comparator(0, vector, hpet::EventMode::Periodic(period)) {
+        Ok(()) => {
+            log!("timer: HPET comparator 0 on vector {}", vector);
+            true
+        }
+        Err(error) => {
+            log_at!(crate::logging::Level::Warn, "HPET timer: {:?}", error);
+            let _ = unregister_irq_handler(vector, timer_handler, 0);
+            free_vector(vector);
+            false
+        }
+    }
+}
+
+/// Whether interrupts are routed through the IOAPICs and the local APIC.
+pub fn apic_mode() -> bool {
+    APIC_MODE.load(Ordering::Relaxed)
+}
+
+/// Timer interrupts per second, whichever timer raises them.
+pub fn timer_hz() -> usize {
+    TIMER_HZ.get()
+}
+
+/// The local APIC id of the boot CPU, the destination for `route_gsi`.
+pub fn local_apic_id() -> u32 {
+    apic::id()
+}
+
+/// Let ISA IRQ `irq` through to vector `ISA_IRQ_BASE + irq` on the boot CPU.
+pub fn enable_isa_irq(irq: u8) {
+    if apic_mode() {
+        if let Err(error) = ioapic::route_isa_irq(irq, ISA_IRQ_BASE + irq, apic::id()) {
+            log_at!(crate::logging::Level::Warn, "IRQ {} not routed: {:?}", irq, error);
+        }
+    } else {
+        let port = if irq < 8 { PIC_1_DATA } else { PIC_2_DATA };
+        unsafe {
+            outb(port, inb(port) & !(1 << (irq % 8)));
+        }
+    }
+}
+
+/// Run `f` with interrupts disabled, restoring the previous state afterwards.
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = read_rflags() & RFLAGS_INTERRUPT_ENABLE != 0;
+    if enabled {
+        unsafe { asm!("cli", options(nomem, nostack)) };
+    }
+    let result = f();
+    if enabled {
+        unsafe { asm!("sti", options(nomem, nostack)) };
+    }
+    result
+}
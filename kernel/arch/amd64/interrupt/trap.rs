@@ -0,0 +1,153 @@
+use super::handlers::EXCEPTION_HANDLERS;
+use super::irq;
+use core::arch::global_asm;
+use core::fmt;
+
+/// Everything an exception or interrupt interrupted, as saved by the entry path
+/// below, lowest address first. Handlers get it mutably: whatever they leave in
+/// it is what `iretq` returns to.
+#[repr(C)]
+pub struct TrapFrame {
+    pub gs: u64,
+    pub fs: u64,
+    pub es: u64,
+    pub ds: u64,
+    /// CR2 at entry, the faulting address if this is a page fault.
+    pub cr2: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// Pushed by the CPU for some exceptions, zero for everything else.
+    pub error_code: u64,
+    // Pushed by the CPU.
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl fmt::Debug for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "vector {} error code 0x{:x}", self.vector, self.error_code)?;
+        writeln!(f, "rip {:04x}:{:016x} rsp {:04x}:{:016x} rflags {:08x}", self.cs, self.rip, self.ss, self.rsp, self.rflags)?;
+        writeln!(f, "rax {:016x} rbx {:016x} rcx {:016x} rdx {:016x}", self.rax, self.rbx, self.rcx, self.rdx)?;
+        writeln!(f, "rsi {:016x} rdi {:016x} rbp {:016x} r8  {:016x}", self.rsi, self.rdi, self.rbp, self.r8)?;
+        writeln!(f, "r9  {:016x} r10 {:016x} r11 {:016x} r12 {:016x}", self.r9, self.r10, self.r11, self.r12)?;
+        writeln!(f, "r13 {:016x} r14 {:016x} r15 {:016x} cr2 {:016x}", self.r13, self.r14, self.r15, self.cr2)?;
+        write!(f, "ds {:04x} es {:04x} fs {:04x} gs {:04x}", self.ds, self.es, self.fs, self.gs)
+    }
+}
+
+// One stub per vector, 16 bytes apart so the IDT can index them. Each pushes a
+// zero error code unless the CPU pushes one for that vector, then its vector
+// number, and joins the common path which saves the rest of the `TrapFrame`.
+// fs and gs are saved but not restored: loading a selector would clear the
+// segment base.
+global_asm!(
+    ".pushsection .text",
+    ".balign 16",
+    ".globl trap_stubs",
+    "trap_stubs:",
+    ".set trap_vector, 0",
+    ".rept 256",
+    ".balign 16",
+    ".if !(trap_vector == 8 || (trap_vector >= 10 && trap_vector <= 14) || trap_vector == 17 || trap_vector == 21 || trap_vector == 29 || trap_vector == 30)",
+    "pushq $0",
+    ".endif",
+    "pushq $trap_vector",
+    "jmp trap_common",
+    ".set trap_vector, trap_vector + 1",
+    ".endr",
+    "trap_common:",
+    "push %rax",
+    "push %rbx",
+    "push %rcx",
+    "push %rdx",
+    "push %rsi",
+    "push %rdi",
+    "push %rbp",
+    "push %r8",
+    "push %r9",
+    "push %r10",
+    "push %r11",
+    "push %r12",
+    "push %r13",
+    "push %r14",
+    "push %r15",
+    "mov %cr2, %rax",
+    "push %rax",
+    "mov %ds, %eax",
+    "push %rax",
+    "mov %es, %eax",
+    "push %rax",
+    "mov %fs, %eax",
+    "push %rax",
+    "mov %gs, %eax",
+    "push %rax",
+    "mov %rsp, %rdi",
+    "cld",
+    // Align the stack for the call, rbx survives it.
+    "mov %rsp, %rbx",
+    "and $-16, %rsp",
+    "call {trap}",
+    "mov %rbx, %rsp",
+    "add $16, %rsp",
+    "pop %rax",
+    "mov %eax, %es",
+    "pop %rax",
+    "mov %eax, %ds",
+    "add $8, %rsp",
+    "pop %r15",
+    "pop %r14",
+    "pop %r13",
+    "pop %r12",
+    "pop %r11",
+    "pop %r10",
+    "pop %r9",
+    "pop %r8",
+    "pop %rbp",
+    "pop %rdi",
+    "pop %rsi",
+    "pop %rdx",
+    "pop %rcx",
+    "pop %rbx",
+    "pop %rax",
+    // Vector and error code.
+    "add $16, %rsp",
+    "iretq",
+    ".popsection",
+    trap = sym trap,
+    options(att_syntax),
+);
+
+extern "C" {
+    static trap_stubs: [[u8; 16]; 256];
+}
+
+/// Address of the entry stub for `vector`.
+pub fn stub(vector: u8) -> u64 {
+    unsafe { core::ptr::addr_of!(trap_stubs[vector as usize]) as u64 }
+}
+
+extern "C" fn trap(frame: &mut TrapFrame) {
+    let vector = frame.vector as usize;
+    if vector < EXCEPTION_HANDLERS.len() {
+        EXCEPTION_HANDLERS[vector](frame);
+    } else {
+        irq::dispatch(frame);
+    }
+}
@@ -0,0 +1,257 @@
+use super::page_mapper::PageWalk;
+use super::page_table::{PTE_COPY_ON_WRITE, PTE_FLAGS_MASK, PTE_WRITE};
+use super::{
+    allocate_frame, allocate_zeroed_frame, copy_to_frame, map_frame, FRAME_ALLOCATOR, KERNEL_PAGE_TABLE, PAGE_SIZE,
+};
+use crate::memory::addr::VirtualAddress;
+use crate::memory::page::Page;
+use crate::memory::PagingError;
+use core::fmt;
+use spin::mutex::Mutex;
+
+/// The error code the CPU pushes with a page fault.
+#[derive(Clone, Copy)]
+pub struct PageFaultErrorCode(pub u64);
+
+impl PageFaultErrorCode {
+    /// The page was present, i.e. this is a protection violation.
+    pub fn present(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+
+    pub fn write(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    pub fn user(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    /// A reserved bit was set in one of the paging entries.
+    pub fn reserved_bit(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+
+    pub fn instruction_fetch(&self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+
+    pub fn protection_key(&self) -> bool {
+        self.0 & (1 << 5) != 0
+    }
+}
+
+impl fmt::Display for PageFaultErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = if self.instruction_fetch() {
+            "instruction fetch"
+        } else if self.write() {
+            "write"
+        } else {
+            "read"
+        };
+        write!(
+            f,
+            "{} {} {} mode",
+            if self.present() { "protection violation on" } else { "not present" },
+            access,
+            if self.user() { "user" } else { "kernel" }
+        )?;
+        if self.reserved_bit() {
+            write!(f, ", reserved bit set")?;
+        }
+        if self.protection_key() {
+            write!(f, ", protection key")?;
+        }
+        Ok(())
+    }
+}
+
+/// How faults on the not present pages of a region are resolved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultRegionKind {
+    /// Pages are mapped, zero filled, on first touch.
+    DemandZero,
+    /// A stack growing down from the end of the region. Pages between the fault
+    /// and the part already mapped are filled in; the lowest page stays unmapped
+    /// as a guard and touching it is an overflow.
+    Stack,
+}
+
+#[derive(Clone, Copy)]
+struct FaultRegion {
+    start: usize,
+    end: usize,
+    kind: FaultRegionKind,
+    owner: &'static str,
+}
+
+impl FaultRegion {
+    fn contains(&self, address: usize) -> bool {
+        (self.start..self.end).contains(&address)
+    }
+}
+
+const MAX_FAULT_REGIONS: usize = 32;
+
+static FAULT_REGIONS: Mutex<[Option<FaultRegion>; MAX_FAULT_REGIONS]> = Mutex::new([None; MAX_FAULT_REGIONS]);
+
+/// Why a page fault couldn't be resolved.
+#[derive(Debug)]
+pub enum FaultError {
+    /// No registered region covers the address.
+    NoRegion,
+    /// The page is present and the access isn't one that gets fixed up.
+    ProtectionViolation,
+    /// The guard page of a growable stack was hit.
+    StackOverflow(&'static str),
+    OutOfMemory,
+    /// The page tables or frame allocator were held by the code that faulted.
+    Busy,
+    /// A paging entry has a reserved bit set, the tables are corrupt.
+    CorruptPageTable,
+}
+
+/// Have page faults in `length` bytes from `start` resolved as `kind`. Both must
+/// be page aligned and the range can't overlap another region. Nothing is mapped
+/// up front.
+pub fn register_region(
+    start: VirtualAddress,
+    length: usize,
+    kind: FaultRegionKind,
+    owner: &'static str,
+) -> Result<(), PagingError> {
+    if start.0 % PAGE_SIZE != 0 || length % PAGE_SIZE != 0 || length == 0 {
+        return Err(PagingError::Unknown);
+    }
+    let region = FaultRegion {
+        start: start.0,
+        end: start.0 + length,
+        kind,
+        owner,
+    };
+    let mut regions = FAULT_REGIONS.lock();
+    if regions
+        .iter()
+        .flatten()
+        .any(|other| other.start < region.end && region.start < other.end)
+    {
+        return Err(PagingError::Unknown);
+    }
+    let slot = regions.iter_mut().find(|slot| slot.is_none()).ok_or(PagingError::Unknown)?;
+    *slot = Some(region);
+    Ok(())
+}
+
+/// Stop resolving faults in the region starting at `start`. Pages already mapped
+/// in it stay mapped.
+pub fn unregister_region(start: VirtualAddress) -> Result<(), PagingError> {
+    let mut regions = FAULT_REGIONS.lock();
+    let slot = regions
+        .iter_mut()
+        .find(|slot| slot.map_or(false, |region| region.start == start.0))
+        .ok_or(PagingError::Unknown)?;
+    *slot = None;
+    Ok(())
+}
+
+/// Make a mapped page read-only, to be copied into a private frame on the next
+/// write to it.
+pub fn make_copy_on_write(page: Page) -> Result<(), PagingError> {
+    KERNEL_PAGE_TABLE
+        .update_entry(page, |entry| {
+            if !entry.is_used() {
+                return Err(PagingError::Unknown);
+            }
+            entry.0 = (entry.0 & !PTE_WRITE) | PTE_COPY_ON_WRITE;
+            Ok(())
+        })
+        .unwrap_or(Err(PagingError::Unknown))
+}
+
+/// Whether resolving a fault would have to wait on a lock the faulting code holds.
+/// There is a single CPU and the handler runs with interrupts disabled, so a held
+/// lock can only belong to the code that faulted.
+fn paging_busy() -> bool {
+    KERNEL_PAGE_TABLE.inner.is_locked() || FRAME_ALLOCATOR.inner.is_locked() || FRAME_ALLOCATOR.zeroed.is_locked()
+}
+
+/// Try to resolve a page fault at `address`: copy a copy-on-write page, or map
+/// a page of a demand zero or stack region.
+pub fn handle_page_fault(address: VirtualAddress, error: PageFaultErrorCode) -> Result<(), FaultError> {
+    if error.reserved_bit() {
+        return Err(FaultError::CorruptPageTable);
+    }
+    if paging_busy() {
+        return Err(FaultError::Busy);
+    }
+    let page = Page::from_virtual_address(address);
+
+    if error.present() {
+        if error.write() && !error.instruction_fetch() && is_copy_on_write(page) {
+            return resolve_copy_on_write(page);
+        }
+        return Err(FaultError::ProtectionViolation);
+    }
+
+    let region = FAULT_REGIONS
+        .try_lock()
+        .ok_or(FaultError::Busy)?
+        .iter()
+        .flatten()
+        .find(|region| region.contains(address.0))
+        .copied()
+        .ok_or(FaultError::NoRegion)?;
+    match region.kind {
+        FaultRegionKind::DemandZero => map_zeroed(page),
+        FaultRegionKind::Stack => grow_stack(&region, page),
+    }
+}
+
+fn is_copy_on_write(page: Page) -> bool {
+    KERNEL_PAGE_TABLE
+        .update_entry(page, |entry| entry.0 & PTE_COPY_ON_WRITE != 0)
+        .unwrap_or(false)
+}
+
+/// Give the page a private, writable copy of its frame. Frames aren't reference
+/// counted yet, so the original stays with whatever else maps it.
+fn resolve_copy_on_write(page: Page) -> Result<(), FaultError> {
+    let frame = allocate_frame().ok_or(FaultError::OutOfMemory)?;
+    copy_to_frame(page.virtual_address(), frame);
+    // Keep the rest of the mapping (NX, global, caching, user) as it was.
+    KERNEL_PAGE_TABLE.update_entry(page, |entry| {
+        entry.set_frame(frame, (entry.0 & PTE_FLAGS_MASK & !PTE_COPY_ON_WRITE) | PTE_WRITE)
+    });
+    Ok(())
+}
+
+fn map_zeroed(page: Page) -> Result<(), FaultError> {
+    let frame = allocate_zeroed_frame().ok_or(FaultError::OutOfMemory)?;
+    map_frame(page, frame).map_err(|_| FaultError::OutOfMemory)
+}
+
+fn grow_stack(region: &FaultRegion, page: Page) -> Result<(), FaultError> {
+    if page.virtual_address().0 < region.start + PAGE_SIZE {
+        return Err(FaultError::StackOverflow(region.owner));
+    }
+    let mut address = page.virtual_address().0;
+    while address < region.end {
+        let page = Page::from_virtual_address(VirtualAddress::new(address));
+        if KERNEL_PAGE_TABLE.is_mapped(page) {
+            break;
+        }
+        map_zeroed(page)?;
+        address += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// The page table entries translating `address`, for fault reports. None if the
+/// page tables are in use by the code that faulted.
+pub fn page_walk(address: VirtualAddress) -> Option<PageWalk> {
+    if KERNEL_PAGE_TABLE.inner.is_locked() {
+        return None;
+    }
+    KERNEL_PAGE_TABLE.walk(Page::from_virtual_address(address))
+}
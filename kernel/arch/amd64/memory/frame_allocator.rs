@@ -6,7 +6,8 @@ use crate::memory::{
     page::Page,
     FrameAllocatorAPI,
 };
-use crate::multiboot::{MMapEntryType, MultibootInfo};
+use crate::boot_info::BootInfo;
+use crate::multiboot::MMapEntryType;
 use spin::mutex::Mutex;
 
 pub struct BootstrapFrameAllocator {
@@ -44,16 +45,19 @@ impl FrameAllocatorAPI for BootstrapFrameAllocator {
     fn deallocate_frame(&mut self, _frame: Frame) {}
 }
 
+/// One bit per frame, set while the frame is in use. `allocate_frame` only hands
+/// out frames whose bit is clear, which is what lets `reserve_frame` keep a frame
+/// (e.g. one the memtest found defective) out of circulation for good.
 pub struct FrameAllocatorInner<'a> {
     bitmap: &'a mut [u8],
-    free_frame_offset: usize,
-    free_frame_byte_offset: u8,
+    // Frame number to start the next search for a free frame from.
+    next_free: usize,
 }
 
 impl<'a> FrameAllocatorInner<'a> {
     pub fn new(
         mut bootstrap_frame_alloc: BootstrapFrameAllocator,
-        info: &MultibootInfo,
+        info: &BootInfo,
         page_mapper: &mut PageMapper,
     ) -> Self {
         let memory_sz = Self::detect_memory_size(info);
@@ -61,27 +65,28 @@ impl<'a> FrameAllocatorInner<'a> {
         let bitmap = Self::initialize_bitmap(&mut bootstrap_frame_alloc, page_mapper, memory_sz);
         Self::mark_used_frames(bitmap, info, &mut bootstrap_frame_alloc);
 
-        let (free_frame_offset, free_frame_byte_offset) =
-            Self::offsets(bootstrap_frame_alloc.free().0);
-
         Self {
             bitmap,
-            free_frame_offset,
-            free_frame_byte_offset,
+            next_free: bootstrap_frame_alloc.free().0 / PAGE_SIZE,
         }
     }
 
+    /// Returns the byte index into the bitmap and the bit within that byte for an address.
     fn offsets(addr: usize) -> (usize, u8) {
         let frame_no = addr / PAGE_SIZE;
-        let byte_offset = (frame_no % 8) as u8;
-        let offset = (frame_no - byte_offset as usize) / 8;
+        (frame_no / 8, (frame_no % 8) as u8)
+    }
 
-        (offset, byte_offset)
+    fn set_used(bitmap: &mut [u8], addr: usize) {
+        let (offset, bit) = Self::offsets(addr);
+        if offset < bitmap.len() {
+            bitmap[offset] |= 1 << bit;
+        }
     }
 
-    fn detect_memory_size(info: &MultibootInfo) -> usize {
+    fn detect_memory_size(info: &BootInfo) -> usize {
         let mut memory_sz: usize = 0;
-        for entry in info.mmap_iter() {
+        for entry in info.memory_map() {
             if (entry.length() + entry.base_addr()) as usize > memory_sz {
                 memory_sz = (entry.length() + entry.base_addr()) as usize;
             }
@@ -94,13 +99,10 @@ impl<'a> FrameAllocatorInner<'a> {
         page_mapper: &mut PageMapper,
         memory_sz: usize,
     ) -> &'static mut [u8] {
-        let bitmap_sz = (memory_sz / PAGE_SIZE) / 8;
-        let frames = bitmap_sz / PAGE_SIZE;
+        let bitmap_sz = ((memory_sz / PAGE_SIZE) + 7) / 8;
+        let frames = (bitmap_sz + PAGE_SIZE - 1) / PAGE_SIZE;
         let bitmap_start_frame = bootstrap_frame_alloc.allocate_frame().unwrap();
-        log!(
-            "Creating frame alloc bitmap, allocating {} frames.",
-            frames + 1
-        );
+        log!("Creating frame alloc bitmap, allocating {} frames.", frames);
 
         for _ in 1..frames {
             let frame = bootstrap_frame_alloc.allocate_frame().unwrap();
@@ -116,20 +118,29 @@ impl<'a> FrameAllocatorInner<'a> {
             }
         }
 
+        let page = Page::from_virtual_address(VirtualAddress::new(bitmap_start_frame.physical_address().0));
+        if !page_mapper.is_mapped(page) {
+            page_mapper
+                .map(page, bitmap_start_frame, bootstrap_frame_alloc)
+                .expect("Failure mapping page in frame allocator creation.");
+        }
+
         let ptr = bitmap_start_frame.physical_address().0 as *mut u8;
         // FIXME need to map ptr, might be id mapped right now though? just because kernel < 2MB and
         // we mapped 2 MB in start.S (at least for amd64 arch).
-        unsafe { core::slice::from_raw_parts_mut(ptr, bitmap_sz) }
+        let bitmap = unsafe { core::slice::from_raw_parts_mut(ptr, bitmap_sz) };
+        bitmap.fill(0);
+        bitmap
     }
 
     fn mark_used_frames(
         bitmap: &mut [u8],
-        info: &MultibootInfo,
+        info: &BootInfo,
         bootstrap_frame_alloc: &mut BootstrapFrameAllocator,
     ) {
         // FIXME: detect & mark regions not in the memory map as reserved.
         // We won't necessarily have all existing memory in the map.
-        for entry in info.mmap_iter() {
+        for entry in info.memory_map() {
             if let MMapEntryType::Available = entry.entry_type() {
                 continue;
             }
@@ -141,81 +152,139 @@ impl<'a> FrameAllocatorInner<'a> {
             // ranges that aren't page-aligned will have some extra space marked as
             // unavailable.
             let base_addr = base_addr - (base_addr % PAGE_SIZE);
-            let end_addr = end_addr - (end_addr % PAGE_SIZE);
+            let end_addr = (end_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
 
             log!("base_addr: 0x{:x}, end_addr: 0x{:x}", base_addr, end_addr);
 
             assert!(base_addr % PAGE_SIZE == 0);
             assert!(end_addr % PAGE_SIZE == 0);
             for addr in (base_addr..end_addr).step_by(PAGE_SIZE) {
-                let (bitmap_offset, byte_offset) = Self::offsets(addr);
-                bitmap[bitmap_offset as usize - 1] |= byte_offset;
+                Self::set_used(bitmap, addr);
+            }
+        }
+
+        // Boot modules stay where the boot loader put them, e.g. the initramfs.
+        for module in info.modules() {
+            let start = module.start() as usize & !(PAGE_SIZE - 1);
+            for frame_addr in (start..module.end() as usize).step_by(PAGE_SIZE) {
+                Self::set_used(bitmap, frame_addr);
             }
         }
 
+        // Everything below the bootstrap allocator holds the kernel image, the
+        // multiboot structures and firmware data, never hand any of it out.
+        for frame_addr in (0..bootstrap_frame_alloc.start().0).step_by(PAGE_SIZE) {
+            Self::set_used(bitmap, frame_addr);
+        }
+
         // Mark frames allocated by bootstrap frame allocator as used.
         for frame_addr in
             (bootstrap_frame_alloc.start().0..bootstrap_frame_alloc.free().0).step_by(PAGE_SIZE)
         {
-            let (bitmap_offset, byte_offset) = Self::offsets(frame_addr);
-            bitmap[bitmap_offset as usize - 1] |= byte_offset;
+            Self::set_used(bitmap, frame_addr);
+        }
+    }
+
+    /// Returns true if `frame` is tracked by the allocator and currently free.
+    pub fn is_frame_free(&self, frame: Frame) -> bool {
+        let (offset, bit) = Self::offsets(frame.physical_address().0);
+        offset < self.bitmap.len() && self.bitmap[offset] & (1 << bit) == 0
+    }
+
+    /// Take `frame` out of circulation. Returns false if it was already in use.
+    pub fn reserve_frame(&mut self, frame: Frame) -> bool {
+        if !self.is_frame_free(frame) {
+            return false;
         }
+        Self::set_used(self.bitmap, frame.physical_address().0);
+        true
     }
 }
 
 impl FrameAllocatorAPI for FrameAllocatorInner<'_> {
     fn allocate_frame(&mut self) -> Option<Frame> {
-        let frame_no = (self.free_frame_offset + self.free_frame_byte_offset as usize) * 8;
-        let mut addr = frame_no * PAGE_SIZE;
-        let frame = Frame::from_physical_address(PhysicalAddress::new(addr));
-
-        addr += PAGE_SIZE;
-        let (mut free_frame_offset, mut free_frame_byte_offset) = Self::offsets(addr);
-        if free_frame_offset >= self.bitmap.len() {
-            addr = 0;
-            let mut found_free_frame = false;
-            while free_frame_offset < self.bitmap.len() {
-                if self.bitmap[addr] & free_frame_byte_offset == 0 {
-                    found_free_frame = true;
-                    break;
-                }
-                addr += PAGE_SIZE;
-                (free_frame_offset, free_frame_byte_offset) = Self::offsets(addr);
+        let total_frames = self.bitmap.len() * 8;
+        for i in 0..total_frames {
+            let frame_no = (self.next_free + i) % total_frames;
+            let frame = Frame {
+                frame_number: frame_no,
+            };
+            if self.is_frame_free(frame) {
+                Self::set_used(self.bitmap, frame.physical_address().0);
+                self.next_free = frame_no + 1;
+                return Some(frame);
             }
+        }
 
-            if !found_free_frame {
-                panic!("OOM: No free frames");
-            }
+        panic!("OOM: No free frames");
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        let (offset, bit) = Self::offsets(frame.physical_address().0);
+        self.bitmap[offset] &= !(1 << bit);
+    }
+}
+
+/// Number of pre-zeroed frames the pool holds when full.
+pub const ZEROED_POOL_CAPACITY: usize = 64;
+/// The pool is topped back up once it drops below this many frames.
+pub const ZEROED_POOL_LOW_WATERMARK: usize = 16;
+
+/// A small stack of frames that have already been cleared, so that page table
+/// allocations don't have to zero a frame on the mapping path.
+pub struct ZeroedFramePool {
+    frames: [Option<Frame>; ZEROED_POOL_CAPACITY],
+    len: usize,
+}
+
+impl ZeroedFramePool {
+    const fn new() -> Self {
+        Self {
+            frames: [None; ZEROED_POOL_CAPACITY],
+            len: 0,
         }
+    }
 
-        // TODO figure out why we're not stepping through the free_frame_byte_offset like expected.
-        // currently it's always 1.
-        self.free_frame_offset = free_frame_offset;
-        self.free_frame_byte_offset = free_frame_byte_offset;
+    pub fn len(&self) -> usize {
+        self.len
+    }
 
-        Some(frame)
+    pub fn is_full(&self) -> bool {
+        self.len == ZEROED_POOL_CAPACITY
     }
 
-    fn deallocate_frame(&mut self, frame: Frame) {
-        let (offset, byte_offset) = Self::offsets(frame.physical_address().0);
-        self.bitmap[offset] = self.bitmap[offset] ^ byte_offset;
+    pub fn push(&mut self, frame: Frame) -> Result<(), Frame> {
+        if self.is_full() {
+            return Err(frame);
+        }
+        self.frames[self.len] = Some(frame);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<Frame> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.frames[self.len].take()
     }
 }
 
 pub struct FrameAllocator<'a> {
     pub inner: Mutex<Option<FrameAllocatorInner<'a>>>,
+    pub zeroed: Mutex<ZeroedFramePool>,
 }
 
 impl<'a> FrameAllocator<'a> {
     pub const fn new() -> Self {
         Self {
             inner: Mutex::new(None),
+            zeroed: Mutex::new(ZeroedFramePool::new()),
         }
     }
-}
 
-impl<'a> FrameAllocatorAPI for FrameAllocator<'a> {
-    fn allocate_frame(&mut self) -> Option<Frame> {
+    pub fn allocate_frame(&self) -> Option<Frame> {
         if let Some(ref mut fa) = *self.inner.lock() {
             fa.allocate_frame()
         } else {
@@ -223,9 +292,29 @@ impl<'a> FrameAllocatorAPI for FrameAllocator<'a> {
         }
     }
 
-    fn deallocate_frame(&mut self, frame: Frame) {
+    pub fn deallocate_frame(&self, frame: Frame) {
         if let Some(ref mut fa) = *self.inner.lock() {
             fa.deallocate_frame(frame);
         }
     }
+
+    pub fn allocate_zeroed_frame(&self) -> Option<Frame> {
+        self.zeroed.lock().pop()
+    }
+}
+
+// All state sits behind the locks, so the shared kernel allocator can be handed to
+// the page mapper as `&mut &FRAME_ALLOCATOR`.
+impl FrameAllocatorAPI for &FrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        FrameAllocator::allocate_frame(self)
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        FrameAllocator::deallocate_frame(self, frame)
+    }
+
+    fn allocate_zeroed_frame(&mut self) -> Option<Frame> {
+        FrameAllocator::allocate_zeroed_frame(self)
+    }
 }
@@ -0,0 +1,148 @@
+use super::PAGE_SIZE;
+use crate::arch::random::random_u64;
+use crate::cmdline::{FlagParam, ParamKind};
+use crate::memory::addr::VirtualAddress;
+use spin::mutex::Mutex;
+
+// Each dynamically placed kernel region gets its own PML4 slot (512GB). With KASLR
+// the region starts at a random 2MB-aligned offset in the first half of its slot.
+const HEAP_SLOT: usize = 506;
+const VMALLOC_SLOT: usize = 507;
+const STACK_SLOT: usize = 508;
+const SLOT_SIZE: usize = 1 << 39;
+const RANDOM_RANGE: usize = SLOT_SIZE / 2;
+const RANDOM_ALIGN: usize = 2 * 1024 * 1024;
+/// Freed vmalloc ranges remembered for reuse. Beyond that they are leaked.
+const MAX_FREE_RANGES: usize = 64;
+
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub start: VirtualAddress,
+    pub end: VirtualAddress,
+}
+
+struct KernelLayout {
+    heap: Region,
+    vmalloc: Region,
+    stacks: Region,
+    // Next free address in the vmalloc region.
+    vmalloc_next: usize,
+    // Ranges below `vmalloc_next` given back with `free_virtual_range`, as
+    // (start, end). Adjacent ranges are merged.
+    vmalloc_free: [Option<(usize, usize)>; MAX_FREE_RANGES],
+}
+
+// start.S reads the command line itself before relocating the kernel image, this
+// registration covers the Rust side and keeps the parameter from being reported as unknown.
+static NOKASLR: FlagParam = FlagParam::new(false);
+kernel_param!(NOKASLR_PARAM, "nokaslr", ParamKind::Flag(&NOKASLR));
+
+static LAYOUT: Mutex<Option<KernelLayout>> = Mutex::new(None);
+
+fn slot_region(slot: usize, randomise: bool) -> Region {
+    let base = 0xffff_0000_0000_0000 | (slot << 39);
+    let offset = if randomise {
+        (random_u64() as usize % (RANDOM_RANGE / RANDOM_ALIGN)) * RANDOM_ALIGN
+    } else {
+        0
+    };
+    Region {
+        start: VirtualAddress::new(base + offset),
+        end: VirtualAddress::new(base + SLOT_SIZE - PAGE_SIZE),
+    }
+}
+
+/// Pick the bases of the heap, vmalloc and stack regions. Must run before any of them
+/// are used, i.e. early in arch::memory::init.
+pub fn init() {
+    let randomise = !NOKASLR.get();
+    let heap = slot_region(HEAP_SLOT, randomise);
+    let vmalloc = slot_region(VMALLOC_SLOT, randomise);
+    let stacks = slot_region(STACK_SLOT, randomise);
+    log!(
+        "layout: kernel 0x{:x}, heap 0x{:x}, vmalloc 0x{:x}, stacks 0x{:x}{}",
+        crate::arch::kernel_base(),
+        heap.start.0,
+        vmalloc.start.0,
+        stacks.start.0,
+        if randomise { "" } else { " (KASLR disabled)" }
+    );
+
+    *LAYOUT.lock() = Some(KernelLayout {
+        heap,
+        vmalloc,
+        stacks,
+        vmalloc_next: vmalloc.start.0,
+        vmalloc_free: [None; MAX_FREE_RANGES],
+    });
+}
+
+fn with_layout<T>(f: impl FnOnce(&mut KernelLayout) -> T) -> T {
+    let mut layout = LAYOUT.lock();
+    f(layout
+        .as_mut()
+        .expect("Kernel address space layout used before layout::init."))
+}
+
+pub fn heap_region() -> Region {
+    with_layout(|layout| layout.heap)
+}
+
+pub fn stack_region() -> Region {
+    with_layout(|layout| layout.stacks)
+}
+
+/// Reserve `length` bytes (rounded up to whole pages) of address space in the vmalloc
+/// region. Nothing is mapped, that's left to the caller.
+pub fn allocate_virtual_range(length: usize) -> Option<VirtualAddress> {
+    let length = (length + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    with_layout(|layout| {
+        for slot in layout.vmalloc_free.iter_mut() {
+            if let Some((start, end)) = *slot {
+                if end - start >= length {
+                    *slot = if end - start == length { None } else { Some((start + length, end)) };
+                    return Some(VirtualAddress::new(start));
+                }
+            }
+        }
+
+        let start = layout.vmalloc_next;
+        if start + length > layout.vmalloc.end.0 {
+            return None;
+        }
+        layout.vmalloc_next = start + length;
+        Some(VirtualAddress::new(start))
+    })
+}
+
+/// Give back a range reserved with `allocate_virtual_range`. It has to be unmapped
+/// already.
+pub fn free_virtual_range(start: VirtualAddress, length: usize) {
+    let length = (length + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let (mut start, mut end) = (start.0, start.0 + length);
+    with_layout(|layout| {
+        // Merge with the free ranges on either side.
+        for slot in layout.vmalloc_free.iter_mut() {
+            match *slot {
+                Some((free_start, free_end)) if free_end == start => {
+                    start = free_start;
+                    *slot = None;
+                }
+                Some((free_start, free_end)) if free_start == end => {
+                    end = free_end;
+                    *slot = None;
+                }
+                _ => {}
+            }
+        }
+
+        if end == layout.vmalloc_next {
+            layout.vmalloc_next = start;
+            return;
+        }
+        match layout.vmalloc_free.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some((start, end)),
+            None => log!("vmalloc: no room to track a freed range, leaking 0x{:x}-0x{:x}", start, end),
+        }
+    })
+}
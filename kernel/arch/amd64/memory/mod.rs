@@ -1,23 +1,53 @@
+pub mod fault;
 pub mod frame_allocator;
+pub mod layout;
 pub mod page_mapper;
 pub mod page_table;
+pub mod stack_allocator;
 
 use crate::memory::addr::{PhysicalAddress, VirtualAddress};
 use crate::memory::frame::Frame;
 use crate::memory::page::Page;
-use crate::memory::FrameAllocatorAPI;
 use crate::memory::PagingError;
-use crate::multiboot::MultibootInfo;
-use frame_allocator::{BootstrapFrameAllocator, FrameAllocator, FrameAllocatorInner};
-use page_mapper::{KernelPageMapper, PageMapper};
-use spin::mutex::Mutex;
+use crate::boot_info::BootInfo;
+use frame_allocator::{
+    BootstrapFrameAllocator, FrameAllocator, FrameAllocatorInner, ZEROED_POOL_LOW_WATERMARK,
+};
+use page_mapper::{flush, KernelPageMapper, PageMapper};
+use page_table::{PageTableEntry, PTE_CACHE_DISABLE, PTE_PRESENT, PTE_WRITE, PTE_WRITE_THROUGH};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub const PAGE_SIZE: usize = 4096;
 
-static mut FRAME_ALLOCATOR: FrameAllocator = FrameAllocator::new();
-static mut KERNEL_PAGE_TABLE: KernelPageMapper = KernelPageMapper::new();
+// A single page of kernel address space (PML4 slot 509) reserved for short-lived
+// mappings of arbitrary frames, e.g. to clear them before they are handed out.
+const TEMPORARY_PAGE: VirtualAddress = VirtualAddress(0xffff_fe80_0000_0000);
+// The page after it, used only by `zero_frame`. Its page table is set up once in
+// `init`, after which the slot is filled and cleared by writing its entry directly,
+// without the page table lock or any allocation, so frames can be zeroed by code
+// that already holds either.
+const ZERO_PAGE: VirtualAddress = VirtualAddress(0xffff_fe80_0000_1000);
+// The page after that, set up the same way and used only by `copy_to_frame`, so the
+// copy-on-write fault path doesn't depend on the temporary page being free.
+const COPY_PAGE: VirtualAddress = VirtualAddress(0xffff_fe80_0000_2000);
 
-pub fn init(bootstrap_frame_alloc_start_physical: usize, multiboot_info: &MultibootInfo, multiboot_addr: usize) {
+extern "C" {
+    // Set by start.S to 5 if it enabled LA57, 4 otherwise.
+    #[link_name = "paging_levels"]
+    static PAGING_LEVELS: u32;
+}
+
+// Both keep their state behind their own locks, so they are shared as plain statics.
+static FRAME_ALLOCATOR: FrameAllocator = FrameAllocator::new();
+static KERNEL_PAGE_TABLE: KernelPageMapper = KernelPageMapper::new();
+/// Address of the page table entry for `ZERO_PAGE`, 0 before `init`.
+static ZERO_PAGE_ENTRY: AtomicUsize = AtomicUsize::new(0);
+/// Address of the page table entry for `COPY_PAGE`, 0 before `init`.
+static COPY_PAGE_ENTRY: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init(bootstrap_frame_alloc_start_physical: usize, boot_info: &BootInfo) {
+    log!("{}-level paging, {}-bit virtual addresses", paging_levels(), virtual_address_bits());
+    layout::init();
     let mut bootstrap_frame_allocator =
         BootstrapFrameAllocator::new(PhysicalAddress::new(bootstrap_frame_alloc_start_physical));
     let mut page_mapper = PageMapper::init_kernel_table();
@@ -25,14 +55,44 @@ pub fn init(bootstrap_frame_alloc_start_physical: usize, multiboot_info: &Multib
     test_page_mapper(
         &mut page_mapper,
         &mut bootstrap_frame_allocator,
-        multiboot_addr,
+        boot_info.address(),
     );
 
-    let fa = FrameAllocatorInner::new(bootstrap_frame_allocator, multiboot_info, &mut page_mapper);
-    unsafe {
-        FRAME_ALLOCATOR.inner = Mutex::new(Some(fa));
-        KERNEL_PAGE_TABLE.inner = Mutex::new(Some(page_mapper));
-    }
+    let fa = FrameAllocatorInner::new(bootstrap_frame_allocator, boot_info, &mut page_mapper);
+    *FRAME_ALLOCATOR.inner.lock() = Some(fa);
+    *KERNEL_PAGE_TABLE.inner.lock() = Some(page_mapper);
+    ZERO_PAGE_ENTRY.store(init_slot(ZERO_PAGE), Ordering::Relaxed);
+    COPY_PAGE_ENTRY.store(init_slot(COPY_PAGE), Ordering::Relaxed);
+}
+
+/// Build the page tables down to the slot at `address` by mapping it once, and
+/// return the address of its entry. Unmapping leaves the tables in place.
+fn init_slot(address: VirtualAddress) -> usize {
+    let page = Page::from_virtual_address(address);
+    let frame = allocate_frame().expect("No frame for a page slot.");
+    KERNEL_PAGE_TABLE
+        .map(page, frame, &mut &FRAME_ALLOCATOR)
+        .expect("Failure mapping a page slot.");
+    let entry = KERNEL_PAGE_TABLE
+        .update_entry(page, |entry| {
+            entry.clear();
+            entry as *mut PageTableEntry as usize
+        })
+        .expect("A page slot has no page table entry.");
+    FRAME_ALLOCATOR.deallocate_frame(frame);
+    entry
+}
+
+/// Depth of the active paging hierarchy, 4 or 5 (LA57).
+#[inline]
+pub fn paging_levels() -> usize {
+    unsafe { PAGING_LEVELS as usize }
+}
+
+/// Number of implemented virtual address bits for the active paging depth.
+#[inline]
+pub fn virtual_address_bits() -> usize {
+    12 + 9 * paging_levels()
 }
 
 pub fn map(start: VirtualAddress, length: usize) -> Result<(), PagingError> {
@@ -42,25 +102,208 @@ pub fn map(start: VirtualAddress, length: usize) -> Result<(), PagingError> {
     for i in 0..num_frames {
         let virtual_address = VirtualAddress::new(start.0 + (i * PAGE_SIZE));
         let page = Page::from_virtual_address(virtual_address);
-        unsafe {
-            let frame = FRAME_ALLOCATOR.allocate_frame().unwrap();
-            log!("map 0x{:x} to 0x{:x}", page.virtual_address().0, frame.physical_address().0);
-
-            if KERNEL_PAGE_TABLE.is_mapped(page) {
-                continue;
-            }
+        let frame = FRAME_ALLOCATOR.allocate_frame().unwrap();
+        log!("map 0x{:x} to 0x{:x}", page.virtual_address().0, frame.physical_address().0);
 
-            KERNEL_PAGE_TABLE.map(page, frame, &mut FRAME_ALLOCATOR)?;
+        if KERNEL_PAGE_TABLE.is_mapped(page) {
+            continue;
         }
+
+        KERNEL_PAGE_TABLE.map(page, frame, &mut &FRAME_ALLOCATOR)?;
     }
     Ok(())
 }
 
+/// Undo `map`: unmap the pages in `length` bytes from `start` and give their frames
+/// back to the frame allocator. Pages that aren't mapped are skipped.
+pub fn unmap(start: VirtualAddress, length: usize) {
+    for i in 0..length / PAGE_SIZE {
+        let page = Page::from_virtual_address(VirtualAddress::new(start.0 + i * PAGE_SIZE));
+        let frame = KERNEL_PAGE_TABLE
+            .update_entry(page, |entry| {
+                if !entry.is_used() {
+                    return None;
+                }
+                let frame = entry.frame();
+                entry.clear();
+                Some(frame)
+            })
+            .flatten();
+        if let Some(frame) = frame {
+            FRAME_ALLOCATOR.deallocate_frame(frame);
+        }
+    }
+}
+
 pub fn map_frame(page: Page, frame: Frame) -> Result<(), PagingError> {
-    unsafe {
-        KERNEL_PAGE_TABLE.map(page, frame, &mut FRAME_ALLOCATOR)?;
+    KERNEL_PAGE_TABLE.map(page, frame, &mut &FRAME_ALLOCATOR)
+}
+
+/// Map `length` bytes of physical memory starting at `start`, e.g. a boot module or
+/// firmware table, into the vmalloc region. The frames must already be reserved.
+pub fn map_physical_region(start: PhysicalAddress, length: usize) -> Result<VirtualAddress, PagingError> {
+    map_region(start, length, PTE_WRITE)
+}
+
+/// Like `map_physical_region`, but uncached, for memory mapped device registers.
+/// Undo it with `unmap_physical_region`.
+pub fn map_mmio_region(start: PhysicalAddress, length: usize) -> Result<VirtualAddress, PagingError> {
+    map_region(start, length, PTE_WRITE | PTE_WRITE_THROUGH | PTE_CACHE_DISABLE)
+}
+
+fn map_region(start: PhysicalAddress, length: usize, flags: u64) -> Result<VirtualAddress, PagingError> {
+    let offset = start.0 % PAGE_SIZE;
+    let first_frame = Frame::from_physical_address(PhysicalAddress::new(start.0 - offset));
+    let pages = (offset + length + PAGE_SIZE - 1) / PAGE_SIZE;
+    let virtual_start = layout::allocate_virtual_range(pages * PAGE_SIZE).ok_or(PagingError::Unknown)?;
+
+    for i in 0..pages {
+        let page = Page::from_virtual_address(VirtualAddress::new(virtual_start.0 + i * PAGE_SIZE));
+        let frame = Frame {
+            frame_number: first_frame.frame_number + i,
+        };
+        KERNEL_PAGE_TABLE.map_with_flags(page, frame, flags, &mut &FRAME_ALLOCATOR)?;
     }
-    Ok(())
+    Ok(VirtualAddress::new(virtual_start.0 + offset))
+}
+
+/// Unmap a region mapped with `map_physical_region` and free its address range. The
+/// frames stay reserved.
+pub fn unmap_physical_region(address: VirtualAddress, start: PhysicalAddress, length: usize) {
+    let offset = start.0 % PAGE_SIZE;
+    let first_frame = Frame::from_physical_address(PhysicalAddress::new(start.0 - offset));
+    let pages = (offset + length + PAGE_SIZE - 1) / PAGE_SIZE;
+
+    for i in 0..pages {
+        let page = Page::from_virtual_address(VirtualAddress::new(address.0 - offset + i * PAGE_SIZE));
+        let frame = Frame {
+            frame_number: first_frame.frame_number + i,
+        };
+        KERNEL_PAGE_TABLE
+            .unmap(page, frame, &mut &FRAME_ALLOCATOR)
+            .expect("Failure unmapping physical region.");
+    }
+    layout::free_virtual_range(VirtualAddress::new(address.0 - offset), pages * PAGE_SIZE);
+}
+
+/// Give the frames of a physical range back to the frame allocator, e.g. memory the
+/// firmware or boot loader is done with. Frames only partially covered by the range
+/// are kept, they may still hold whatever sits next to it. Returns the number of
+/// frames freed.
+pub fn free_physical_range(start: PhysicalAddress, length: usize) -> usize {
+    let first_frame = (start.0 + PAGE_SIZE - 1) / PAGE_SIZE;
+    let last_frame = (start.0 + length) / PAGE_SIZE;
+
+    for frame_number in first_frame..last_frame {
+        FRAME_ALLOCATOR.deallocate_frame(Frame { frame_number });
+    }
+    last_frame.saturating_sub(first_frame)
+}
+
+/// Unmap a region mapped with `map_physical_region` and give its frames back to the
+/// frame allocator.
+pub fn release_physical_region(address: VirtualAddress, start: PhysicalAddress, length: usize) {
+    unmap_physical_region(address, start, length);
+    free_physical_range(start, length);
+}
+
+pub fn allocate_frame() -> Option<Frame> {
+    FRAME_ALLOCATOR.allocate_frame()
+}
+
+/// Allocate a frame whose contents are guaranteed to be zero. Served from the
+/// pre-zeroed pool when possible, otherwise the frame is cleared right away.
+pub fn allocate_zeroed_frame() -> Option<Frame> {
+    if let Some(frame) = FRAME_ALLOCATOR.allocate_zeroed_frame() {
+        return Some(frame);
+    }
+    let frame = FRAME_ALLOCATOR.allocate_frame()?;
+    zero_frame(frame);
+    Some(frame)
+}
+
+/// Top the pre-zeroed frame pool back up once it runs low. This is meant to be
+/// called from the idle loop so the zeroing cost stays off the mapping path.
+pub fn refill_zeroed_frames() {
+    if FRAME_ALLOCATOR.zeroed.lock().len() >= ZEROED_POOL_LOW_WATERMARK {
+        return;
+    }
+
+    while !FRAME_ALLOCATOR.zeroed.lock().is_full() {
+        let frame = match FRAME_ALLOCATOR.allocate_frame() {
+            Some(frame) => frame,
+            None => return,
+        };
+        zero_frame(frame);
+
+        if let Err(frame) = FRAME_ALLOCATOR.zeroed.lock().push(frame) {
+            FRAME_ALLOCATOR.deallocate_frame(frame);
+            return;
+        }
+    }
+}
+
+/// Run `test` over `frame` while it is mapped at the temporary page. The test gets
+/// a pointer to the start of the page and the frame's physical address. Frames that
+/// fail stay reserved so they are never handed out. Returns None, without running
+/// the test, if the frame is not currently free.
+pub fn test_free_frame<F>(frame: Frame, test: F) -> Option<bool>
+where
+    F: FnOnce(*mut u64, PhysicalAddress) -> bool,
+{
+    match *FRAME_ALLOCATOR.inner.lock() {
+        Some(ref mut fa) => {
+            if !fa.reserve_frame(frame) {
+                return None;
+            }
+        }
+        None => return None,
+    }
+
+    let page = Page::from_virtual_address(TEMPORARY_PAGE);
+    KERNEL_PAGE_TABLE
+        .map(page, frame, &mut &FRAME_ALLOCATOR)
+        .expect("Failure mapping the temporary page.");
+    let passed = test(TEMPORARY_PAGE.0 as *mut u64, frame.physical_address());
+    KERNEL_PAGE_TABLE
+        .unmap(page, frame, &mut &FRAME_ALLOCATOR)
+        .expect("Failure unmapping the temporary page.");
+
+    if passed {
+        FRAME_ALLOCATOR.deallocate_frame(frame);
+    }
+    Some(passed)
+}
+
+/// Clear a frame by mapping it at `ZERO_PAGE`. Interrupts are kept off while the
+/// slot is in use, so a handler zeroing a frame can't reuse it underneath us.
+fn zero_frame(frame: Frame) {
+    let entry = ZERO_PAGE_ENTRY.load(Ordering::Relaxed) as *mut PageTableEntry;
+    assert!(!entry.is_null(), "zero_frame used before memory::init.");
+    let page = Page::from_virtual_address(ZERO_PAGE);
+    crate::arch::interrupt::without_interrupts(|| unsafe {
+        (*entry).set_frame(frame, PTE_WRITE | PTE_PRESENT);
+        flush(page);
+        core::ptr::write_bytes(ZERO_PAGE.0 as *mut u8, 0, PAGE_SIZE);
+        (*entry).clear();
+        flush(page);
+    });
+}
+
+/// Copy the page at `source` into `frame` by mapping the frame at `COPY_PAGE`,
+/// with interrupts off like `zero_frame`. Takes no locks, so the page fault
+/// handler can use it whatever the faulting code holds.
+fn copy_to_frame(source: VirtualAddress, frame: Frame) {
+    let entry = COPY_PAGE_ENTRY.load(Ordering::Relaxed) as *mut PageTableEntry;
+    assert!(!entry.is_null(), "copy_to_frame used before memory::init.");
+    let page = Page::from_virtual_address(COPY_PAGE);
+    crate::arch::interrupt::without_interrupts(|| unsafe {
+        (*entry).set_frame(frame, PTE_WRITE | PTE_PRESENT);
+        flush(page);
+        core::ptr::copy_nonoverlapping(source.0 as *const u8, COPY_PAGE.0 as *mut u8, PAGE_SIZE);
+        (*entry).clear();
+        flush(page);
+    });
 }
 
 fn test_page_mapper(
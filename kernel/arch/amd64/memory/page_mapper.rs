@@ -1,22 +1,38 @@
-use super::page_table::{PageTableEntry, Table, PTE_PRESENT, PTE_WRITE};
+use super::page_table::{PageTableEntry, Table, PTE_HUGE, PTE_PRESENT, PTE_WRITE};
+use core::fmt;
 use crate::memory::{
     addr::VirtualAddress, frame::Frame, page::Page, FrameAllocatorAPI, PagingError,
 };
+use core::arch::asm;
 use spin::mutex::Mutex;
 
 // Recursive page table constants.
-// Note: the recursive entry is at index 510.
-const P4_TABLE_BASE: VirtualAddress = VirtualAddress(0xffff_ff7f_bfdf_e000);
+// Note: the recursive entry is at index 510 of the root table, which is the PML4, or the
+// PML5 when five-level paging is active.
 const RECURSIVE_INDEX: usize = 510;
+const MAX_PAGING_LEVELS: usize = 5;
 
 pub struct PageMapper<'a> {
     root: &'a mut Table,
+    levels: usize,
 }
 
 impl<'a> PageMapper<'a> {
     pub fn init_kernel_table() -> Self {
+        let levels = super::paging_levels();
+        let root_page = recursive_page(levels, &[]);
+
+        if levels == 5 {
+            // start.S shares its PML4 between both hierarchies. Below a PML5 the PML4's own
+            // recursive slot would just alias the PML4 as a PDPT, so drop it.
+            let kernel_pml4 = recursive_page(levels, &[511]);
+            Table::from_virtual_address(kernel_pml4.virtual_address())[RECURSIVE_INDEX].clear();
+            flush_all();
+        }
+
         Self {
-            root: Table::from_virtual_address(P4_TABLE_BASE),
+            root: Table::from_virtual_address(root_page.virtual_address()),
+            levels,
         }
     }
 
@@ -36,19 +52,42 @@ impl<'a> PageMapper<'a> {
         }
     }
 
+    /// The index into each table on the walk down to `page`, starting with the root.
+    /// Only the first `self.levels` entries are meaningful.
+    fn offsets(&self, page: Page) -> [usize; MAX_PAGING_LEVELS] {
+        let all = [
+            page.pml5_offset(),
+            page.pml4_offset(),
+            page.pdpt_offset(),
+            page.pd_offset(),
+            page.pt_offset(),
+        ];
+        let mut offsets = [0; MAX_PAGING_LEVELS];
+        offsets[..self.levels].copy_from_slice(&all[MAX_PAGING_LEVELS - self.levels..]);
+        offsets
+    }
+
     fn next_table<FA>(entry: &mut PageTableEntry, next: Page, alloc: &mut FA) -> &'a mut Table
     where
         FA: FrameAllocatorAPI,
     {
+        let table = unsafe { &mut *(next.virtual_address().0 as *mut Table) };
+
         if !entry.is_used() {
-            if let Some(frame) = alloc.allocate_frame() {
+            if let Some(frame) = alloc.allocate_zeroed_frame() {
+                entry.set_frame(frame, PTE_WRITE | PTE_PRESENT);
+                flush(next);
+            } else if let Some(frame) = alloc.allocate_frame() {
                 entry.set_frame(frame, PTE_WRITE | PTE_PRESENT);
+                flush(next);
+                // The new table is reachable through the recursive mapping now,
+                // so clear it before anything walks through it.
+                table.zero();
             } else {
                 panic!("Failed to allocate frame for next_table.");
             }
         }
 
-        let table = unsafe { &mut *(next.virtual_address().0 as *mut Table) };
         return table;
     }
 
@@ -56,37 +95,36 @@ impl<'a> PageMapper<'a> {
     where
         FA: FrameAllocatorAPI,
     {
-        let pdpt_page = recursive_page(
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-        );
-        let pdpt = PageMapper::next_table(&mut self.root[page.pml4_offset()], pdpt_page, alloc);
-
-        let pd_page = recursive_page(
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-            page.pdpt_offset(),
-        );
-        let pd = PageMapper::next_table(&mut pdpt[page.pdpt_offset()], pd_page, alloc);
+        self.map_with_flags(page, frame, PTE_WRITE, alloc)
+    }
 
-        let pt_page = recursive_page(
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-            page.pdpt_offset(),
-            page.pd_offset(),
-        );
+    /// Map `page` to `frame` with `flags` (PTE_WRITE, caching bits) on the last
+    /// level entry. Tables created on the way are always writable.
+    pub fn map_with_flags<FA>(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        flags: u64,
+        alloc: &mut FA,
+    ) -> Result<(), PagingError>
+    where
+        FA: FrameAllocatorAPI,
+    {
+        let offsets = self.offsets(page);
+        let leaf = self.levels - 1;
 
-        let pt = PageMapper::next_table(&mut pd[page.pd_offset()], pt_page, alloc);
-        let entry = &mut pt[page.pt_offset()];
+        let mut table: &mut Table = self.root;
+        for depth in 0..leaf {
+            let next = recursive_page(self.levels, &offsets[..=depth]);
+            table = PageMapper::next_table(&mut table[offsets[depth]], next, alloc);
+        }
 
+        let entry = &mut table[offsets[leaf]];
         if entry.is_used() {
             return Err(PagingError::Unknown);
         }
 
-        entry.set_frame(frame, PTE_WRITE | PTE_PRESENT);
+        entry.set_frame(frame, flags | PTE_PRESENT);
 
         Ok(())
     }
@@ -95,87 +133,137 @@ impl<'a> PageMapper<'a> {
     where
         FA: FrameAllocatorAPI,
     {
-        let pml4_entry = &mut self.root[page.pml4_offset()];
-        assert!(pml4_entry.is_used());
+        let offsets = self.offsets(page);
+        let leaf = self.levels - 1;
 
-        let pdpt_page = recursive_page(
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-        );
-        let pdpt_table = PageMapper::next_table(pml4_entry, pdpt_page, alloc);
-        let pdpt_entry = &mut pdpt_table[page.pdpt_offset()];
-        assert!(pdpt_entry.is_used());
-
-        let pd_page = recursive_page(
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-            page.pdpt_offset(),
-        );
-        let pd_table = PageMapper::next_table(pdpt_entry, pd_page, alloc);
-        let pd_entry = &mut pd_table[page.pd_offset()];
-        assert!(pd_entry.is_used());
+        let mut table: &mut Table = self.root;
+        for depth in 0..leaf {
+            let entry = &mut table[offsets[depth]];
+            assert!(entry.is_used());
+            let next = recursive_page(self.levels, &offsets[..=depth]);
+            table = PageMapper::next_table(entry, next, alloc);
+        }
 
-        let pt_page = recursive_page(
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-            page.pdpt_offset(),
-            page.pd_offset(),
-        );
-        let pt_table = PageMapper::next_table(pd_entry, pt_page, alloc);
-        let pt_entry = &mut pt_table[page.pt_offset()];
+        let pt_entry = &mut table[offsets[leaf]];
         assert!(pt_entry.is_used());
         assert!(frame == pt_entry.frame());
 
+        pt_entry.clear();
+        flush(page);
+
         Ok(())
     }
 
     pub fn is_mapped(&self, page: Page) -> bool {
-        if !self.root[page.pml4_offset()].is_used() {
-            return false;
+        let offsets = self.offsets(page);
+        let leaf = self.levels - 1;
+
+        let mut table: &Table = self.root;
+        for depth in 0..leaf {
+            if !table[offsets[depth]].is_used() {
+                return false;
+            }
+            let next = recursive_page(self.levels, &offsets[..=depth]);
+            table = Table::from_virtual_address(next.virtual_address());
         }
 
-        let pdpt_page = recursive_page(
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-        );
-        let pdpt = unsafe { &mut *(pdpt_page.virtual_address().0 as *mut Table) };
+        return table[offsets[leaf]].is_used();
+    }
+
+    /// The last level entry for `page`, if all the tables above it exist.
+    pub fn entry_mut(&mut self, page: Page) -> Option<&mut PageTableEntry> {
+        let offsets = self.offsets(page);
+        let leaf = self.levels - 1;
 
-        if !pdpt[page.pdpt_offset()].is_used() {
-            return false;
+        let mut table: &mut Table = self.root;
+        for depth in 0..leaf {
+            let entry = &table[offsets[depth]];
+            if !entry.is_used() || entry.entry() & PTE_HUGE != 0 {
+                return None;
+            }
+            let next = recursive_page(self.levels, &offsets[..=depth]);
+            table = Table::from_virtual_address(next.virtual_address());
         }
+        Some(&mut table[offsets[leaf]])
+    }
 
-        let pd_page = recursive_page(
-            RECURSIVE_INDEX,
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-            page.pdpt_offset(),
-        );
-        let pd = unsafe { &mut *(pd_page.virtual_address().0 as *mut Table) };
+    /// Every entry on the way down to `page`, stopping at the first one that is
+    /// unused or maps a huge page.
+    pub fn walk(&self, page: Page) -> PageWalk {
+        let offsets = self.offsets(page);
+        let mut walk = PageWalk {
+            levels: self.levels,
+            offsets,
+            entries: [0; MAX_PAGING_LEVELS],
+            depth: 0,
+        };
+
+        let mut table: &Table = self.root;
+        for depth in 0..self.levels {
+            let entry = table[offsets[depth]].entry();
+            walk.entries[depth] = entry;
+            walk.depth = depth + 1;
+            if entry == 0 || depth == self.levels - 1 || entry & PTE_HUGE != 0 {
+                break;
+            }
+            let next = recursive_page(self.levels, &offsets[..=depth]);
+            table = Table::from_virtual_address(next.virtual_address());
+        }
+        walk
+    }
+}
+
+/// The page table entries used to translate an address, root first.
+pub struct PageWalk {
+    levels: usize,
+    offsets: [usize; MAX_PAGING_LEVELS],
+    entries: [u64; MAX_PAGING_LEVELS],
+    depth: usize,
+}
 
-        if !pd[page.pd_offset()].is_used() {
-            return false;
+impl fmt::Display for PageWalk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const NAMES: [&str; MAX_PAGING_LEVELS] = ["PML5", "PML4", "PDPT", "PD", "PT"];
+        let names = &NAMES[MAX_PAGING_LEVELS - self.levels..];
+        for depth in 0..self.depth {
+            if depth > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}[{}]=0x{:x}", names[depth], self.offsets[depth], self.entries[depth])?;
         }
+        Ok(())
+    }
+}
 
-        let pt_page = recursive_page(
-            RECURSIVE_INDEX,
-            page.pml4_offset(),
-            page.pdpt_offset(),
-            page.pd_offset(),
-        );
-        let pt = unsafe { &mut *(pt_page.virtual_address().0 as *mut Table) };
+/// Invalidate the TLB entry for a single page.
+#[inline]
+pub fn flush(page: Page) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) page.virtual_address().0, options(nostack, preserves_flags));
+    }
+}
 
-        return pt[page.pt_offset()].is_used();
+/// Invalidate all non-global TLB entries by reloading CR3.
+pub fn flush_all() {
+    unsafe {
+        asm!("mov {0}, cr3", "mov cr3, {0}", out(reg) _, options(nostack, preserves_flags));
     }
 }
 
+/// The page through which the recursive mapping exposes the table found by following
+/// `path` (table offsets, root first) down from the root. An empty path gives the root.
 #[inline]
-fn recursive_page(pml4_index: usize, pdpt_index: usize, pd_index: usize, pt_index: usize) -> Page {
-    let addr: usize = (pml4_index << 39) | (pdpt_index << 30) | (pd_index << 21) | (pt_index << 12);
+fn recursive_page(levels: usize, path: &[usize]) -> Page {
+    let recursive_levels = levels - path.len();
+    let mut addr: usize = 0;
+    for level in 0..levels {
+        let index = if level < recursive_levels {
+            RECURSIVE_INDEX
+        } else {
+            path[level - recursive_levels]
+        };
+        addr |= index << (12 + 9 * (levels - 1 - level));
+    }
     Page::from_virtual_address(VirtualAddress(addr))
 }
 
@@ -190,7 +278,7 @@ impl<'a> KernelPageMapper<'a> {
         }
     }
 
-    pub fn map<FA>(&mut self, page: Page, frame: Frame, alloc: &mut FA) -> Result<(), PagingError>
+    pub fn map<FA>(&self, page: Page, frame: Frame, alloc: &mut FA) -> Result<(), PagingError>
     where
         FA: FrameAllocatorAPI,
     {
@@ -201,10 +289,51 @@ impl<'a> KernelPageMapper<'a> {
         }
     }
 
-    pub fn is_mapped(&mut self, page: Page) -> bool {
+    pub fn map_with_flags<FA>(
+        &self,
+        page: Page,
+        frame: Frame,
+        flags: u64,
+        alloc: &mut FA,
+    ) -> Result<(), PagingError>
+    where
+        FA: FrameAllocatorAPI,
+    {
+        if let Some(ref mut page_mapper) = *self.inner.lock() {
+            page_mapper.map_with_flags(page, frame, flags, alloc)
+        } else {
+            Err(PagingError::Unknown)
+        }
+    }
+
+    pub fn unmap<FA>(&self, page: Page, frame: Frame, alloc: &mut FA) -> Result<(), PagingError>
+    where
+        FA: FrameAllocatorAPI,
+    {
+        if let Some(ref mut page_mapper) = *self.inner.lock() {
+            page_mapper.unmap(page, frame, alloc)
+        } else {
+            Err(PagingError::Unknown)
+        }
+    }
+
+    pub fn is_mapped(&self, page: Page) -> bool {
         if let Some(ref mut page_mapper) = *self.inner.lock() {
             return page_mapper.is_mapped(page);
         }
         false
     }
+
+    /// Run `f` on the last level entry for `page`, flushing the page afterwards.
+    /// None if the page has no such entry.
+    pub fn update_entry<T>(&self, page: Page, f: impl FnOnce(&mut PageTableEntry) -> T) -> Option<T> {
+        let mut guard = self.inner.lock();
+        let result = f(guard.as_mut()?.entry_mut(page)?);
+        flush(page);
+        Some(result)
+    }
+
+    pub fn walk(&self, page: Page) -> Option<PageWalk> {
+        Some(self.inner.lock().as_ref()?.walk(page))
+    }
 }
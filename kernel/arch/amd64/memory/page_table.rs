@@ -4,6 +4,16 @@ use core::ops::{Index, IndexMut};
 
 pub const PTE_PRESENT: u64 = 1;
 pub const PTE_WRITE: u64 = 1 << 1;
+pub const PTE_WRITE_THROUGH: u64 = 1 << 3;
+/// Together with PTE_WRITE_THROUGH this makes the page uncacheable under the
+/// default PAT, as memory mapped device registers need.
+pub const PTE_CACHE_DISABLE: u64 = 1 << 4;
+/// Maps a 1GB or 2MB page when set in a PDPT or PD entry.
+pub const PTE_HUGE: u64 = 1 << 7;
+/// Software bit: the page is shared read-only and gets copied on the first write.
+pub const PTE_COPY_ON_WRITE: u64 = 1 << 9;
+/// Every bit of an entry but the frame address, i.e. the flags and NX.
+pub const PTE_FLAGS_MASK: u64 = !0x000f_ffff_ffff_f000;
 
 #[derive(Debug)]
 #[repr(transparent)]
@@ -18,12 +28,16 @@ impl PageTableEntry {
         self.0 = frame.physical_address() | options;
     }
 
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+
     pub fn entry(&self) -> u64 {
         self.0
     }
 
     pub fn frame(&self) -> Frame {
-        Frame::from_physical_address(PhysicalAddress::new(self.0 as usize))
+        Frame::from_physical_address(PhysicalAddress::new((self.0 & !PTE_FLAGS_MASK) as usize))
     }
 }
 
@@ -38,6 +52,14 @@ impl Table {
     pub fn from_virtual_address<'a>(address: VirtualAddress) -> &'a mut Table {
         return unsafe { &mut *(address.0 as *mut Table) };
     }
+
+    /// Clears every entry. Frames fresh from the allocator hold whatever was
+    /// left in RAM, which would otherwise be read as (bogus) mappings.
+    pub fn zero(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.clear();
+        }
+    }
 }
 
 impl Index<usize> for Table {
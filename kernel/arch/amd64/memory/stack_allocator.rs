@@ -0,0 +1,101 @@
+use super::{layout, PAGE_SIZE};
+use crate::memory::{addr::VirtualAddress, page::Page, PagingError};
+use spin::mutex::Mutex;
+
+// Kernel stacks are carved out of the stack region of the kernel layout. Every
+// stack is preceded by an unmapped guard page, so running off the bottom of a
+// stack faults instead of silently overwriting whatever lives below it.
+const MAX_STACKS: usize = 64;
+
+static STACK_ALLOCATOR: Mutex<StackAllocator> = Mutex::new(StackAllocator::new());
+
+/// A mapped kernel stack. Stacks grow down, so `top` is the initial stack pointer.
+#[derive(Clone, Copy)]
+pub struct Stack {
+    top: VirtualAddress,
+    bottom: VirtualAddress,
+}
+
+impl Stack {
+    pub fn top(&self) -> VirtualAddress {
+        self.top
+    }
+
+    pub fn guard_page(&self) -> Page {
+        Page::from_virtual_address(VirtualAddress::new(self.bottom.0 - PAGE_SIZE))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct StackRecord {
+    stack: Stack,
+    owner: &'static str,
+}
+
+struct StackAllocator {
+    next: usize,
+    stacks: [Option<StackRecord>; MAX_STACKS],
+}
+
+impl StackAllocator {
+    const fn new() -> Self {
+        Self {
+            next: 0,
+            stacks: [None; MAX_STACKS],
+        }
+    }
+
+    fn allocate(&mut self, size: usize, owner: &'static str) -> Result<Stack, PagingError> {
+        let slot = self
+            .stacks
+            .iter()
+            .position(|s| s.is_none())
+            .ok_or(PagingError::Unknown)?;
+
+        let region = layout::stack_region();
+        if self.next == 0 {
+            self.next = region.start.0;
+        }
+
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        // Leave the first page of the range unmapped, it is the guard page.
+        let bottom = self.next + PAGE_SIZE;
+        let top = bottom + pages * PAGE_SIZE;
+        if pages == 0 || top > region.end.0 {
+            return Err(PagingError::Unknown);
+        }
+
+        super::map(VirtualAddress::new(bottom), pages * PAGE_SIZE)?;
+        self.next = top;
+
+        let stack = Stack {
+            top: VirtualAddress::new(top),
+            bottom: VirtualAddress::new(bottom),
+        };
+        self.stacks[slot] = Some(StackRecord { stack, owner });
+        Ok(stack)
+    }
+
+    fn guard_page_owner(&self, address: VirtualAddress) -> Option<&'static str> {
+        let page = Page::from_virtual_address(address);
+        self.stacks
+            .iter()
+            .flatten()
+            .find(|record| record.stack.guard_page().page_number == page.page_number)
+            .map(|record| record.owner)
+    }
+}
+
+/// Allocate and map a kernel stack of at least `size` bytes, with an unmapped
+/// guard page directly below it. `owner` names the stack in overflow reports.
+pub fn allocate_stack(size: usize, owner: &'static str) -> Result<Stack, PagingError> {
+    STACK_ALLOCATOR.lock().allocate(size, owner)
+}
+
+/// If `address` falls in the guard page of a kernel stack, return the owner of
+/// that stack. Called from the page fault handler, so it never spins on the lock.
+pub fn guard_page_owner(address: VirtualAddress) -> Option<&'static str> {
+    STACK_ALLOCATOR
+        .try_lock()
+        .and_then(|allocator| allocator.guard_page_owner(address))
+}
@@ -1,9 +1,56 @@
 // x86 port IO
 #[path = "../x86_common/io.rs"]
-mod x86_io;
+pub mod x86_io;
 
 // Debug output channel (uses serial)
 #[path = "../x86_common/debug.rs"]
 pub mod debug;
+pub mod cpu;
+pub mod extable;
+pub mod gdt;
+pub mod hpet;
 pub mod interrupt;
 pub mod memory;
+pub mod pit;
+pub mod power;
+pub mod random;
+pub mod registers;
+pub mod time;
+pub mod tss;
+pub mod uaccess;
+
+/// Link-time virtual address of the kernel image.
+pub const KERNEL_BASE: usize = 0xFFFF_FFFF_8000_0000;
+
+extern "C" {
+    // Offset start.S relocated the kernel by, zero when KASLR is disabled.
+    static kernel_slide: usize;
+}
+
+/// Virtual address the kernel image is actually running at.
+pub fn kernel_base() -> usize {
+    unsafe { KERNEL_BASE + kernel_slide }
+}
+
+/// Abandon the current stack for `stack` and call `entry(argument)` on it. The
+/// old stack is never unwound, so `argument` may point into it.
+pub fn switch_stack(stack: &memory::stack_allocator::Stack, entry: extern "C" fn(usize) -> !, argument: usize) -> ! {
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {top}",
+            "call {entry}",
+            "ud2",
+            top = in(reg) stack.top().0,
+            entry = in(reg) entry,
+            in("rdi") argument,
+            options(noreturn),
+        );
+    }
+}
+
+/// Halt the CPU until the next interrupt arrives.
+pub fn wait_for_interrupt() {
+    unsafe {
+        core::arch::asm!("hlt", options(nomem, nostack));
+    }
+}
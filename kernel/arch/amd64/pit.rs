@@ -0,0 +1,153 @@
+// The 8254 programmable interval timer. Channel 0 is wired to IRQ 0, channel 2
+// is gated and read back through port B of the keyboard controller, which
+// makes it usable for busy-wait delays without any interrupt.
+use crate::arch::x86_io::{inb, outb};
+use spin::mutex::{Mutex, MutexGuard};
+
+/// Input clock of every channel, in Hz.
+pub const FREQUENCY: u32 = 1_193_182;
+/// Longest delay a single count covers, in microseconds.
+pub const MAX_DELAY_US: u32 = (0xFFFF as u64 * 1_000_000 / FREQUENCY as u64) as u32;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Port B of the keyboard controller: bit 0 gates channel 2, bit 1 connects it
+/// to the speaker and bit 5 reads back its output.
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUTPUT: u8 = 1 << 5;
+
+// Command byte fields.
+const ACCESS_LOW_HIGH: u8 = 0b11 << 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    /// Raises IRQ 0.
+    Zero,
+    /// Gated by, and read back through, port B.
+    Two,
+}
+
+impl Channel {
+    fn port(&self) -> u16 {
+        match self {
+            Channel::Zero => CHANNEL_0,
+            Channel::Two => CHANNEL_2,
+        }
+    }
+
+    fn select(&self) -> u8 {
+        match self {
+            Channel::Zero => 0 << 6,
+            Channel::Two => 2 << 6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Mode 0, interrupt on terminal count: the output goes high once, when the
+    /// count runs out.
+    OneShot,
+    /// Mode 2: the output pulses every `count` ticks.
+    RateGenerator,
+}
+
+impl Mode {
+    fn bits(&self) -> u8 {
+        match self {
+            Mode::OneShot => 0 << 1,
+            Mode::RateGenerator => 2 << 1,
+        }
+    }
+}
+
+/// The command port is shared by all channels, and channel 2 by every delay.
+static PIT: Mutex<()> = Mutex::new(());
+
+/// Ticks of the input clock in `microseconds`.
+fn ticks(microseconds: u64) -> u64 {
+    microseconds * FREQUENCY as u64 / 1_000_000
+}
+
+/// Load `channel` with `count` ticks in `mode`, 0 meaning 65536.
+unsafe fn load(channel: Channel, mode: Mode, count: u16) {
+    outb(COMMAND, channel.select() | ACCESS_LOW_HIGH | mode.bits());
+    outb(channel.port(), count as u8);
+    outb(channel.port(), (count >> 8) as u8);
+}
+
+/// Program `channel` to count `count` ticks in `mode`, 0 meaning 65536. Channel 2
+/// only counts while its gate is open, see `set_channel_2_gate`.
+pub fn program(channel: Channel, mode: Mode, count: u16) {
+    let _guard = PIT.lock();
+    unsafe {
+        load(channel, mode, count);
+    }
+}
+
+pub fn set_channel_2_gate(open: bool) {
+    unsafe {
+        let value = inb(PORT_B) & !(PORT_B_GATE | PORT_B_SPEAKER);
+        outb(PORT_B, if open { value | PORT_B_GATE } else { value });
+    }
+}
+
+pub fn channel_2_output() -> bool {
+    unsafe { inb(PORT_B) & PORT_B_OUTPUT != 0 }
+}
+
+/// Raise IRQ 0 `hz` times a second from channel 0, for when there is no local
+/// APIC timer. Rates below 19 Hz get the slowest the PIT can do, about 18.2 Hz.
+pub fn start_periodic(hz: usize) {
+    let count = FREQUENCY as usize / hz.max(1);
+    // A count of 0 is 65536.
+    program(Channel::Zero, Mode::RateGenerator, count.min(0x10000) as u16);
+}
+
+/// A delay loaded into channel 2 by `prepare_delay`, which runs when `wait` is
+/// called. Holds the PIT until then, so don't take long in between.
+pub struct Delay {
+    _guard: MutexGuard<'static, ()>,
+}
+
+/// Load channel 2 with `microseconds`, at most `MAX_DELAY_US`. Splitting the
+/// load from the wait keeps the port IO out of what is being timed, e.g. when
+/// calibrating another timer against the PIT.
+pub fn prepare_delay(microseconds: u32) -> Delay {
+    let guard = PIT.lock();
+    let count = ticks(microseconds.min(MAX_DELAY_US) as u64).max(1);
+    set_channel_2_gate(false);
+    unsafe {
+        load(Channel::Two, Mode::OneShot, count as u16);
+    }
+    Delay { _guard: guard }
+}
+
+impl Delay {
+    /// Open the gate and spin until the count runs out.
+    pub fn wait(self) {
+        set_channel_2_gate(true);
+        while !channel_2_output() {
+            core::hint::spin_loop();
+        }
+        set_channel_2_gate(false);
+    }
+}
+
+/// Spin for `microseconds`.
+pub fn delay_us(microseconds: u64) {
+    let mut remaining = microseconds;
+    while remaining > 0 {
+        let chunk = remaining.min(MAX_DELAY_US as u64);
+        prepare_delay(chunk as u32).wait();
+        remaining -= chunk;
+    }
+}
+
+/// Spin for `milliseconds`.
+pub fn delay_ms(milliseconds: u64) {
+    delay_us(milliseconds * 1000);
+}
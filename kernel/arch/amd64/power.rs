@@ -0,0 +1,54 @@
+use super::x86_io::{inb, outb};
+use core::arch::asm;
+
+const KEYBOARD_CONTROLLER_STATUS: u16 = 0x64;
+const KEYBOARD_CONTROLLER_COMMAND: u16 = 0x64;
+/// Status register bit: the controller hasn't consumed the last command yet.
+const INPUT_BUFFER_FULL: u8 = 1 << 1;
+/// Pulse the CPU reset line.
+const PULSE_RESET_LINE: u8 = 0xFE;
+
+pub fn disable_interrupts() {
+    unsafe { asm!("cli", options(nomem, nostack)) };
+}
+
+/// Stop the CPU for good.
+pub fn halt() -> ! {
+    disable_interrupts();
+    loop {
+        unsafe { asm!("hlt", options(nomem, nostack)) };
+    }
+}
+
+/// Busy wait for roughly `microseconds`, using the POST code port (~1us per write).
+pub fn delay(microseconds: usize) {
+    for _ in 0..microseconds {
+        unsafe { outb(0x80, 0) };
+    }
+}
+
+/// Ask the 8042 keyboard controller to reset the machine.
+pub fn keyboard_controller_reset() {
+    for _ in 0..0x10000 {
+        if unsafe { inb(KEYBOARD_CONTROLLER_STATUS) } & INPUT_BUFFER_FULL == 0 {
+            break;
+        }
+        delay(1);
+    }
+    unsafe { outb(KEYBOARD_CONTROLLER_COMMAND, PULSE_RESET_LINE) };
+}
+
+/// Reset the CPU by loading an empty IDT and raising an exception. With no
+/// handlers the double fault turns into a triple fault, which resets.
+pub fn triple_fault() -> ! {
+    #[repr(C, packed)]
+    struct IdtPointer {
+        limit: u16,
+        base: u64,
+    }
+    let empty = IdtPointer { limit: 0, base: 0 };
+    unsafe {
+        asm!("lidt [{}]", "int3", in(reg) &empty, options(nostack));
+    }
+    halt()
+}
@@ -0,0 +1,45 @@
+use core::arch::asm;
+
+/// A best-effort random number for layout randomisation. Uses RDRAND when the CPU
+/// has it, falling back to a mix of the timestamp counter otherwise. Not suitable
+/// for anything cryptographic.
+pub fn random_u64() -> u64 {
+    if super::cpu::has_feature(super::cpu::Feature::Rdrand) {
+        for _ in 0..10 {
+            if let Some(value) = rdrand() {
+                return value;
+            }
+        }
+    }
+    mix(rdtsc())
+}
+
+fn rdrand() -> Option<u64> {
+    let value: u64;
+    let ok: u8;
+    unsafe {
+        asm!("rdrand {0}", "setc {1}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+    }
+    if ok != 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+pub fn rdtsc() -> u64 {
+    let high: u32;
+    let low: u32;
+    unsafe {
+        asm!("rdtsc", out("edx") high, out("eax") low, options(nomem, nostack));
+    }
+    ((high as u64) << 32) | (low as u64)
+}
+
+// splitmix64 finaliser, spreads the low-entropy TSC bits over the whole word.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
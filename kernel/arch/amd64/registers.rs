@@ -0,0 +1,42 @@
+use super::extable::extable_entry;
+use core::arch::asm;
+
+/// RFLAGS.IF, set while maskable interrupts are enabled.
+pub const RFLAGS_INTERRUPT_ENABLE: u64 = 1 << 9;
+
+pub fn read_rflags() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) value, options(nomem, preserves_flags));
+    }
+    value
+}
+
+/// Read a model specific register that may not exist: rdmsr raises #GP for an
+/// unimplemented MSR, which comes back as None.
+pub fn read_msr_safe(msr: u32) -> Option<u64> {
+    let (low, high): (u32, u32);
+    let failed: u32;
+    unsafe {
+        asm!(
+            "xor {failed:e}, {failed:e}",
+            "2: rdmsr",
+            "3:",
+            ".pushsection .text.fixup, \"ax\"",
+            "4: mov {failed:e}, 1",
+            "jmp 3b",
+            ".popsection",
+            extable_entry!("2b", "4b"),
+            failed = out(reg) failed,
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack),
+        );
+    }
+    if failed == 0 {
+        Some((high as u64) << 32 | low as u64)
+    } else {
+        None
+    }
+}
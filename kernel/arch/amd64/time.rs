@@ -0,0 +1,289 @@
+// Timekeeping. Monotonic time is read from the best rated clocksource: the
+// TSC when its frequency is known and it keeps a constant rate, else the HPET
+// main counter, else the count of timer ticks. A TSC that drifts against the
+// HPET or the tick is dropped while running, as it can under emulation.
+use super::cpu::{self, Feature};
+use super::{hpet, interrupt, pit};
+use crate::cmdline::ParamKind;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+/// Length and number of the TSC calibration runs.
+const CALIBRATION_MS: u32 = 10;
+const CALIBRATION_RUNS: usize = 3;
+/// How far apart the calibration runs may be, in parts per thousand.
+const CALIBRATION_TOLERANCE: u64 = 10;
+/// How often, in ticks, and by how much the TSC may disagree with the reference
+/// before it is considered unstable. Half a second and 62.5 ms.
+const WATCHDOG_INTERVAL_DIVISOR: u64 = 2;
+const WATCHDOG_THRESHOLD_NS: u64 = NANOSECONDS_PER_SECOND / 16;
+
+/// Ratings of the clocksources, higher is better. 0 means unusable.
+const RATING_TSC_INVARIANT: u32 = 300;
+const RATING_HPET: u32 = 250;
+const RATING_TSC: u32 = 150;
+const RATING_TICKS: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clocksource {
+    Tsc,
+    Hpet,
+    /// Timer interrupts counted since boot, from the local APIC timer or PIT.
+    Ticks,
+}
+
+const CLOCKSOURCES: [Clocksource; 3] = [Clocksource::Tsc, Clocksource::Hpet, Clocksource::Ticks];
+const NO_CLOCKSOURCE: usize = usize::MAX;
+
+/// Clocksource to use if usable, `clocksource=<tsc|hpet|ticks>`.
+static PREFERRED: AtomicUsize = AtomicUsize::new(NO_CLOCKSOURCE);
+kernel_param!(CLOCKSOURCE_PARAM, "clocksource", ParamKind::Custom(parse_clocksource));
+
+fn parse_clocksource(value: &'static str) -> Result<(), &'static str> {
+    let source = Clocksource::from_name(value).ok_or("expected tsc, hpet or ticks")?;
+    PREFERRED.store(source.index(), Ordering::Relaxed);
+    Ok(())
+}
+
+static RATINGS: [AtomicU32; 3] = [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)];
+/// Counts per second of each clocksource.
+static FREQUENCIES: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+// The current clocksource, and the time and count it took over at. Switching
+// happens with interrupts disabled and makes `SEQUENCE` odd meanwhile, so an
+// interrupted reader can tell it has to try again.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+static CURRENT: AtomicUsize = AtomicUsize::new(NO_CLOCKSOURCE);
+static BASE_NS: AtomicU64 = AtomicU64::new(0);
+static BASE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// Counts of the TSC and the reference at the last watchdog check.
+static WATCHDOG_TSC: AtomicU64 = AtomicU64::new(0);
+static WATCHDOG_REFERENCE: AtomicU64 = AtomicU64::new(0);
+
+impl Clocksource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Clocksource::Tsc => "tsc",
+            Clocksource::Hpet => "hpet",
+            Clocksource::Ticks => "ticks",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Clocksource> {
+        CLOCKSOURCES.iter().copied().find(|source| source.name() == name)
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn read(&self) -> u64 {
+        match self {
+            Clocksource::Tsc => rdtsc(),
+            Clocksource::Hpet => hpet::counter().unwrap_or(0),
+            Clocksource::Ticks => TICKS.load(Ordering::Relaxed),
+        }
+    }
+
+    fn frequency(&self) -> u64 {
+        FREQUENCIES[self.index()].load(Ordering::Relaxed)
+    }
+
+    /// How good the clocksource is, 0 if it can't be used.
+    pub fn rating(&self) -> u32 {
+        RATINGS[self.index()].load(Ordering::Relaxed)
+    }
+
+    fn nanoseconds(&self, count: u64) -> u64 {
+        (count as u128 * NANOSECONDS_PER_SECOND as u128 / self.frequency().max(1) as u128) as u64
+    }
+}
+
+fn rdtsc() -> u64 {
+    let high: u32;
+    let low: u32;
+    unsafe {
+        asm!("rdtsc", out("edx") high, out("eax") low, options(nomem, nostack, preserves_flags));
+    }
+    (high as u64) << 32 | low as u64
+}
+
+/// Measure the TSC frequency once, against the HPET if there is one, else the
+/// PIT.
+fn measure_tsc() -> u64 {
+    interrupt::without_interrupts(|| {
+        if let (Some(start), Some(period)) = (hpet::counter(), hpet::period_fs()) {
+            let tsc_start = rdtsc();
+            hpet::delay_us(CALIBRATION_MS as u64 * 1000);
+            let tsc_end = rdtsc();
+            let elapsed_fs = (hpet::counter().unwrap_or(start) - start) as u128 * period as u128;
+            ((tsc_end - tsc_start) as u128 * 1_000_000_000_000_000 / elapsed_fs.max(1)) as u64
+        } else {
+            let delay = pit::prepare_delay(CALIBRATION_MS * 1000);
+            let tsc_start = rdtsc();
+            delay.wait();
+            let tsc_end = rdtsc();
+            (tsc_end - tsc_start) * 1000 / CALIBRATION_MS as u64
+        }
+    })
+}
+
+/// The TSC frequency: what CPUID reports if anything, else the average of a few
+/// measurements. None if they disagree too much to trust the TSC.
+fn calibrate_tsc() -> Option<u64> {
+    if let Some(frequency) = cpu::info().tsc_frequency {
+        return Some(frequency);
+    }
+    let mut runs = [0u64; CALIBRATION_RUNS];
+    for run in runs.iter_mut() {
+        *run = measure_tsc();
+    }
+    let min = runs.iter().copied().min().unwrap_or(0);
+    let max = runs.iter().copied().max().unwrap_or(0);
+    if min == 0 || (max - min) * 1000 > max * CALIBRATION_TOLERANCE {
+        log_at!(
+            crate::logging::Level::Warn,
+            "TSC calibration unstable, {} to {} kHz",
+            min / 1000,
+            max / 1000
+        );
+        return None;
+    }
+    Some(runs.iter().sum::<u64>() / CALIBRATION_RUNS as u64)
+}
+
+/// Rate the clocksources and start monotonic time on the best one. Needs the
+/// timer interrupt running, for the tick count.
+pub fn init() {
+    let hz = interrupt::timer_hz() as u64;
+    FREQUENCIES[Clocksource::Ticks.index()].store(hz, Ordering::Relaxed);
+    RATINGS[Clocksource::Ticks.index()].store(RATING_TICKS, Ordering::Relaxed);
+
+    if let Some(frequency) = hpet::frequency() {
+        FREQUENCIES[Clocksource::Hpet.index()].store(frequency, Ordering::Relaxed);
+        RATINGS[Clocksource::Hpet.index()].store(RATING_HPET, Ordering::Relaxed);
+    }
+
+    if cpu::has_feature(Feature::Tsc) {
+        if let Some(frequency) = calibrate_tsc() {
+            let invariant = cpu::has_feature(Feature::InvariantTsc);
+            FREQUENCIES[Clocksource::Tsc.index()].store(frequency, Ordering::Relaxed);
+            let rating = if invariant { RATING_TSC_INVARIANT } else { RATING_TSC };
+            RATINGS[Clocksource::Tsc.index()].store(rating, Ordering::Relaxed);
+            log!(
+                "TSC: {} kHz, {}",
+                frequency / 1000,
+                if invariant { "invariant" } else { "not invariant" }
+            );
+        }
+    }
+
+    let preferred = CLOCKSOURCES.get(PREFERRED.load(Ordering::Relaxed)).copied();
+    match preferred {
+        Some(source) if source.rating() > 0 => switch_to(source),
+        Some(source) => {
+            log_at!(crate::logging::Level::Warn, "clocksource {} unavailable", source.name());
+            switch_to(best());
+        }
+        None => switch_to(best()),
+    }
+    reset_watchdog();
+}
+
+fn best() -> Clocksource {
+    CLOCKSOURCES
+        .iter()
+        .copied()
+        .max_by_key(Clocksource::rating)
+        .unwrap_or(Clocksource::Ticks)
+}
+
+fn switch_to(source: Clocksource) {
+    interrupt::without_interrupts(|| {
+        let now = monotonic_now();
+        SEQUENCE.fetch_add(1, Ordering::Release);
+        BASE_COUNT.store(source.read(), Ordering::Relaxed);
+        BASE_NS.store(now, Ordering::Relaxed);
+        CURRENT.store(source.index(), Ordering::Relaxed);
+        SEQUENCE.fetch_add(1, Ordering::Release);
+    });
+    log!("clocksource: {} ({} kHz)", source.name(), source.frequency() / 1000);
+}
+
+/// The clocksource monotonic time is read from, None before `init`.
+pub fn current() -> Option<Clocksource> {
+    CLOCKSOURCES.get(CURRENT.load(Ordering::Relaxed)).copied()
+}
+
+/// Nanoseconds since `init`, never going backwards. 0 before then.
+pub fn monotonic_now() -> u64 {
+    loop {
+        let sequence = SEQUENCE.load(Ordering::Acquire);
+        let source = match CLOCKSOURCES.get(CURRENT.load(Ordering::Relaxed)) {
+            Some(source) => *source,
+            None => return 0,
+        };
+        let base_ns = BASE_NS.load(Ordering::Relaxed);
+        let count = source.read().wrapping_sub(BASE_COUNT.load(Ordering::Relaxed));
+        if sequence % 2 == 0 && SEQUENCE.load(Ordering::Acquire) == sequence {
+            return base_ns + source.nanoseconds(count);
+        }
+    }
+}
+
+/// Count a timer interrupt, and every so often check the TSC hasn't drifted.
+pub fn tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    // A 32-bit HPET counter is only extended if it is read once per wrap, which
+    // nothing else guarantees while another clocksource is in use.
+    if hpet::has_32bit_counter() {
+        hpet::counter();
+    }
+    let interval = (Clocksource::Ticks.frequency() / WATCHDOG_INTERVAL_DIVISOR).max(1);
+    if current().is_some() && Clocksource::Tsc.rating() > 0 && ticks % interval == 0 {
+        check_tsc();
+    }
+}
+
+/// What the TSC is checked against: the HPET if there is one, else the tick.
+fn watchdog_reference() -> Clocksource {
+    if Clocksource::Hpet.rating() > 0 {
+        Clocksource::Hpet
+    } else {
+        Clocksource::Ticks
+    }
+}
+
+fn reset_watchdog() {
+    WATCHDOG_TSC.store(rdtsc(), Ordering::Relaxed);
+    WATCHDOG_REFERENCE.store(watchdog_reference().read(), Ordering::Relaxed);
+}
+
+fn check_tsc() {
+    let reference = watchdog_reference();
+    let tsc_elapsed = rdtsc().wrapping_sub(WATCHDOG_TSC.load(Ordering::Relaxed));
+    let reference_elapsed = reference
+        .read()
+        .wrapping_sub(WATCHDOG_REFERENCE.load(Ordering::Relaxed));
+    reset_watchdog();
+
+    let tsc_ns = Clocksource::Tsc.nanoseconds(tsc_elapsed);
+    let reference_ns = reference.nanoseconds(reference_elapsed);
+    if tsc_ns.abs_diff(reference_ns) <= WATCHDOG_THRESHOLD_NS {
+        return;
+    }
+    log_at!(
+        crate::logging::Level::Warn,
+        "TSC unstable: {} ns against {} ns from {}",
+        tsc_ns,
+        reference_ns,
+        reference.name()
+    );
+    RATINGS[Clocksource::Tsc.index()].store(0, Ordering::Relaxed);
+    if current() == Some(Clocksource::Tsc) {
+        switch_to(best());
+    }
+}
@@ -0,0 +1,63 @@
+use super::memory::stack_allocator::allocate_stack;
+use core::mem::size_of;
+
+/// IST slots. IST indices in the IDT are 1-based, slot 0 of the table below is
+/// referenced as index 1. Each exception that can be caused by a broken kernel
+/// stack, or arrive at any point at all, gets a stack of its own. Page faults
+/// don't: they can nest, and a nested one would start over at the top of the
+/// same IST stack.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 1;
+pub const NMI_IST_INDEX: u16 = 2;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 3;
+
+const IST_STACKS: [(u16, &str); 3] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault IST"),
+    (NMI_IST_INDEX, "NMI IST"),
+    (MACHINE_CHECK_IST_INDEX, "machine check IST"),
+];
+const IST_STACK_SIZE: usize = 4 * 4096;
+/// Stack loaded on a switch from ring 3 to ring 0.
+const PRIVILEGE_STACK_SIZE: usize = 4 * 4096;
+
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        Self {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            iomap_base: size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// Allocate the ring 0 and interrupt stacks into the TSS. The descriptor and the
+/// task register are set up by gdt::init, which calls this.
+pub fn init() -> &'static TaskStateSegment {
+    let rsp0 = allocate_stack(PRIVILEGE_STACK_SIZE, "ring 0")
+        .expect("Failure allocating the ring 0 stack.");
+
+    unsafe {
+        TSS.privilege_stack_table[0] = rsp0.top().0 as u64;
+        for (index, owner) in IST_STACKS {
+            let stack = allocate_stack(IST_STACK_SIZE, owner).expect("Failure allocating an IST stack.");
+            TSS.interrupt_stack_table[index as usize - 1] = stack.top().0 as u64;
+        }
+        &*core::ptr::addr_of!(TSS)
+    }
+}
@@ -0,0 +1,92 @@
+use super::extable::extable_entry;
+use super::memory::virtual_address_bits;
+use core::arch::asm;
+
+/// Why an access to memory the kernel doesn't trust failed.
+#[derive(Debug)]
+pub enum AccessError {
+    /// The range isn't entirely in the user half of the address space.
+    BadAddress,
+    /// The access faulted.
+    Fault,
+}
+
+/// Whether `length` bytes from `address` lie in the lower, user, half of the
+/// address space.
+fn is_user_range(address: usize, length: usize) -> bool {
+    let user_end = 1usize << (virtual_address_bits() - 1);
+    address.checked_add(length).map_or(false, |end| end <= user_end)
+}
+
+/// Copy with `rep movsb`, which leaves the number of bytes still to go in rcx
+/// when it faults. Returns that number, zero if everything was copied.
+unsafe fn copy_with_fixup(destination: *mut u8, source: *const u8, length: usize) -> usize {
+    let remaining: usize;
+    asm!(
+        "2: rep movsb",
+        "3:",
+        extable_entry!("2b", "3b"),
+        inout("rcx") length => remaining,
+        inout("rdi") destination => _,
+        inout("rsi") source => _,
+        options(nostack, preserves_flags),
+    );
+    remaining
+}
+
+/// Fill `destination` from user memory at `source`.
+pub fn copy_from_user(destination: &mut [u8], source: usize) -> Result<(), AccessError> {
+    if !is_user_range(source, destination.len()) {
+        return Err(AccessError::BadAddress);
+    }
+    match unsafe { copy_with_fixup(destination.as_mut_ptr(), source as *const u8, destination.len()) } {
+        0 => Ok(()),
+        _ => Err(AccessError::Fault),
+    }
+}
+
+/// Copy `source` to user memory at `destination`.
+pub fn copy_to_user(destination: usize, source: &[u8]) -> Result<(), AccessError> {
+    if !is_user_range(destination, source.len()) {
+        return Err(AccessError::BadAddress);
+    }
+    match unsafe { copy_with_fixup(destination as *mut u8, source.as_ptr(), source.len()) } {
+        0 => Ok(()),
+        _ => Err(AccessError::Fault),
+    }
+}
+
+// Single, naturally sized reads that return None instead of faulting, e.g. to
+// check whether a device answers at an MMIO address.
+macro_rules! probe_read {
+    ($name:ident, $type:ty, $instruction:literal) => {
+        pub unsafe fn $name(address: *const $type) -> Option<$type> {
+            let value: u64;
+            let failed: u32;
+            asm!(
+                "xor {failed:e}, {failed:e}",
+                concat!("2: ", $instruction),
+                "3:",
+                ".pushsection .text.fixup, \"ax\"",
+                "4: mov {failed:e}, 1",
+                "jmp 3b",
+                ".popsection",
+                extable_entry!("2b", "4b"),
+                address = in(reg) address,
+                value = out(reg) value,
+                failed = out(reg) failed,
+                options(nostack, readonly),
+            );
+            if failed == 0 {
+                Some(value as $type)
+            } else {
+                None
+            }
+        }
+    };
+}
+
+probe_read!(probe_read_u8, u8, "movzx {value:e}, byte ptr [{address}]");
+probe_read!(probe_read_u16, u16, "movzx {value:e}, word ptr [{address}]");
+probe_read!(probe_read_u32, u32, "mov {value:e}, dword ptr [{address}]");
+probe_read!(probe_read_u64, u64, "mov {value}, qword ptr [{address}]");
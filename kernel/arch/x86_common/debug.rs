@@ -1,3 +1,24 @@
+use crate::cmdline::ParamKind;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Base IO port of the UART used for debug output, `serial=ttyS<n>` or `serial=<port>`.
+static SERIAL_PORT: AtomicU16 = AtomicU16::new(0x3F8);
+kernel_param!(SERIAL_PARAM, "serial", ParamKind::Custom(parse_serial_port));
+
+fn parse_serial_port(value: &'static str) -> Result<(), &'static str> {
+    let port = match value {
+        "ttyS0" => 0x3F8,
+        "ttyS1" => 0x2F8,
+        "ttyS2" => 0x3E8,
+        "ttyS3" => 0x2E8,
+        _ => crate::cmdline::parse_usize(value)
+            .filter(|port| *port <= 0xFFF8)
+            .ok_or("expected ttyS0-3 or an IO port")? as u16,
+    };
+    SERIAL_PORT.store(port, Ordering::Relaxed);
+    Ok(())
+}
+
 /// Write a string to the output channel
 ///
 /// This method is unsafe because it does port accesses without synchronisation
@@ -7,16 +28,29 @@ pub unsafe fn puts(s: &str) {
     }
 }
 
+/// Read a byte from the serial port, if one has arrived
+///
+/// This method is unsafe because it does port accesses without synchronisation
+pub unsafe fn getb() -> Option<u8> {
+    let port = SERIAL_PORT.load(Ordering::Relaxed);
+    // Line status bit 0: data ready
+    if (crate::arch::x86_io::inb(port + 5) & 0x01) == 0 {
+        return None;
+    }
+    Some(crate::arch::x86_io::inb(port))
+}
+
 /// Write a single byte to the output channel
 ///
 /// This method is unsafe because it does port accesses without synchronisation
 pub unsafe fn putb(b: u8) {
+    let port = SERIAL_PORT.load(Ordering::Relaxed);
     // Wait for the serial port's fifo to not be empty
-    while (crate::arch::x86_io::inb(0x3F8 + 5) & 0x20) == 0 {
+    while (crate::arch::x86_io::inb(port + 5) & 0x20) == 0 {
         // Do nothing
     }
     // Send the byte out the serial port
-    crate::arch::x86_io::outb(0x3F8, b);
+    crate::arch::x86_io::outb(port, b);
 
     // Also send to the bochs 0xe9 hack
     crate::arch::x86_io::outb(0xe9, b);
@@ -15,24 +15,24 @@ pub unsafe fn inb(port: u16) -> u8 {
 
 /// Write a word (16-bits) to the specified port
 pub unsafe fn outw(port: u16, val: u16) {
-    asm!("outw ax, dx", in("ax") val, in("dx") port);
+    asm!("out dx, ax", in("ax") val, in("dx") port);
 }
 
 /// Read a word (16-bits) from the specified port
 pub unsafe fn inw(port: u16) -> u16 {
     let ret: u16;
-    asm!("inw ax, dx", out("ax") ret, in("dx") port);
+    asm!("in ax, dx", out("ax") ret, in("dx") port);
     return ret;
 }
 
 /// Write a long/double-word (32-bits) to the specified port
 pub unsafe fn outl(port: u16, val: u32) {
-    asm!("outl eax, dx", in("eax") val, in("dx") port);
+    asm!("out dx, eax", in("eax") val, in("dx") port);
 }
 
 /// Read a long/double-word (32-bits) from the specified port
 pub unsafe fn inl(port: u16) -> u32 {
     let ret: u32;
-    asm!("inl dx, eax", out("eax") ret, in("dx") port);
+    asm!("in eax, dx", out("eax") ret, in("dx") port);
     return ret;
 }
@@ -0,0 +1,135 @@
+use crate::multiboot::{self, FramebufferInfo, MMapEntryType, Module, MultibootInfo};
+use crate::multiboot2::{self, Multiboot2Info};
+
+/// Which protocol the boot loader used to hand over control.
+pub enum BootProtocol {
+    Multiboot1(MultibootInfo),
+    Multiboot2(Multiboot2Info),
+}
+
+/// Boot loader provided information, independent of the boot protocol.
+pub struct BootInfo {
+    protocol: BootProtocol,
+    address: usize,
+    pub cmdline: Option<&'static str>,
+    pub boot_loader_name: Option<&'static str>,
+    pub framebuffer: Option<FramebufferInfo>,
+    /// Physical address of the ACPI RSDP, if the boot loader passed one along.
+    pub rsdp: Option<usize>,
+}
+
+impl BootInfo {
+    /// Interpret `address` according to the `magic` value start.S saved from eax.
+    pub fn new(magic: u32, address: usize) -> Self {
+        match magic {
+            multiboot::BOOTLOADER_MAGIC => {
+                let info = MultibootInfo::new(address);
+                Self {
+                    cmdline: info.cmdline(),
+                    boot_loader_name: info.boot_loader_name(),
+                    framebuffer: info.framebuffer_info(),
+                    rsdp: None,
+                    protocol: BootProtocol::Multiboot1(info),
+                    address,
+                }
+            }
+            multiboot2::BOOTLOADER_MAGIC => {
+                let info = Multiboot2Info::new(address);
+                Self {
+                    cmdline: info.cmdline(),
+                    boot_loader_name: info.boot_loader_name(),
+                    framebuffer: info.framebuffer_info(),
+                    rsdp: info.rsdp(),
+                    protocol: BootProtocol::Multiboot2(info),
+                    address,
+                }
+            }
+            _ => panic!("Unknown boot loader magic 0x{:x}", magic),
+        }
+    }
+
+    /// Physical address of the boot loader's info structure.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    pub fn protocol(&self) -> &BootProtocol {
+        &self.protocol
+    }
+
+    pub fn memory_map(&self) -> MemoryMapIter {
+        match &self.protocol {
+            BootProtocol::Multiboot1(info) => MemoryMapIter::Multiboot1(info.mmap_iter()),
+            BootProtocol::Multiboot2(info) => MemoryMapIter::Multiboot2(info.mmap_iter()),
+        }
+    }
+
+    pub fn modules(&self) -> ModuleIter {
+        match &self.protocol {
+            BootProtocol::Multiboot1(info) => ModuleIter::Multiboot1(info.modules_iter()),
+            BootProtocol::Multiboot2(info) => ModuleIter::Multiboot2(info.tags_iter()),
+        }
+    }
+}
+
+/// A region of the physical memory map.
+#[derive(Clone, Copy)]
+pub struct MemoryRegion {
+    base_addr: u64,
+    length: u64,
+    entry_type: MMapEntryType,
+}
+
+impl MemoryRegion {
+    pub fn base_addr(&self) -> u64 {
+        self.base_addr
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn entry_type(&self) -> MMapEntryType {
+        self.entry_type
+    }
+}
+
+pub enum MemoryMapIter {
+    Multiboot1(multiboot::MMapIter),
+    Multiboot2(multiboot2::MMapIter),
+}
+
+impl Iterator for MemoryMapIter {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            MemoryMapIter::Multiboot1(iter) => iter.next().map(|entry| MemoryRegion {
+                base_addr: entry.base_addr(),
+                length: entry.length(),
+                entry_type: entry.entry_type(),
+            }),
+            MemoryMapIter::Multiboot2(iter) => iter.next().map(|entry| MemoryRegion {
+                base_addr: entry.base_addr(),
+                length: entry.length(),
+                entry_type: entry.entry_type(),
+            }),
+        }
+    }
+}
+
+pub enum ModuleIter {
+    Multiboot1(multiboot::ModuleIter),
+    Multiboot2(multiboot2::TagIter),
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ModuleIter::Multiboot1(iter) => iter.next(),
+            ModuleIter::Multiboot2(iter) => iter.find_map(multiboot2::Tag::module),
+        }
+    }
+}
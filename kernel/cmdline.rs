@@ -0,0 +1,175 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// A kernel command line parameter. Subsystems declare these with `kernel_param!`,
+/// which places them in the `.kparams` section so `init` can find them all without
+/// a central list. `#[repr(C)]` as the section is read back as a plain array
+/// between two linker symbols.
+#[repr(C)]
+pub struct KernelParam {
+    pub name: &'static str,
+    pub kind: ParamKind,
+}
+
+pub enum ParamKind {
+    /// `name` on its own sets the flag, `name=0`/`name=off` etc. clear it again.
+    Flag(&'static FlagParam),
+    /// A number, decimal or 0x-prefixed hex, with an optional K/M/G suffix.
+    Usize(&'static UsizeParam),
+    Str(&'static StrParam),
+    /// Parsed by the owning subsystem. Returns a description of the problem on error.
+    Custom(fn(&'static str) -> Result<(), &'static str>),
+}
+
+pub struct FlagParam(AtomicBool);
+
+impl FlagParam {
+    pub const fn new(default: bool) -> Self {
+        Self(AtomicBool::new(default))
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct UsizeParam {
+    value: AtomicUsize,
+    min: usize,
+    max: usize,
+}
+
+impl UsizeParam {
+    pub const fn new(default: usize, min: usize, max: usize) -> Self {
+        Self {
+            value: AtomicUsize::new(default),
+            min,
+            max,
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+pub struct StrParam(Mutex<Option<&'static str>>);
+
+impl StrParam {
+    pub const fn new(default: Option<&'static str>) -> Self {
+        Self(Mutex::new(default))
+    }
+
+    pub fn get(&self) -> Option<&'static str> {
+        *self.0.lock()
+    }
+
+    /// Like `get`, but returns None instead of spinning if the value is being updated.
+    pub fn try_get(&self) -> Option<&'static str> {
+        self.0.try_lock().and_then(|value| *value)
+    }
+
+    pub fn set(&self, value: &'static str) {
+        *self.0.lock() = Some(value);
+    }
+}
+
+// Only the addresses of these matter, like `kernel_end` in main.rs.
+extern "C" {
+    static __kparams_start: u8;
+    static __kparams_end: u8;
+}
+
+fn registered_params() -> &'static [KernelParam] {
+    unsafe {
+        let start = &__kparams_start as *const u8 as *const KernelParam;
+        let end = &__kparams_end as *const u8 as *const KernelParam;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Parse the boot loader supplied command line and apply it to the registered
+/// parameters. Problems are logged and otherwise ignored, a bad parameter should
+/// never stop the boot.
+pub fn init(cmdline: Option<&'static str>) {
+    let cmdline = match cmdline {
+        Some(cmdline) => cmdline,
+        None => return,
+    };
+    log!("command line: {}", cmdline);
+
+    for arg in cmdline.split_whitespace() {
+        let (key, value) = match arg.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (arg, None),
+        };
+
+        let param = match registered_params().iter().find(|p| p.name == key) {
+            Some(param) => param,
+            None => {
+                // Multiboot loaders put the path of the kernel image first.
+                if value.is_none() && key.starts_with('/') {
+                    continue;
+                }
+                log!("warning: unknown kernel parameter '{}'", key);
+                continue;
+            }
+        };
+
+        if let Err(error) = apply(param, value) {
+            log!("warning: ignoring kernel parameter '{}': {}", arg, error);
+        }
+    }
+}
+
+fn apply(param: &KernelParam, value: Option<&'static str>) -> Result<(), &'static str> {
+    match param.kind {
+        ParamKind::Flag(flag) => {
+            let enabled = match value {
+                None => true,
+                Some(value) => parse_bool(value).ok_or("expected a boolean")?,
+            };
+            flag.0.store(enabled, Ordering::Relaxed);
+        }
+        ParamKind::Usize(number) => {
+            let parsed = parse_usize(value.ok_or("expected a value")?).ok_or("expected a number")?;
+            if parsed < number.min || parsed > number.max {
+                return Err("value out of range");
+            }
+            number.value.store(parsed, Ordering::Relaxed);
+        }
+        ParamKind::Str(string) => {
+            let value = value.ok_or("expected a value")?;
+            if value.is_empty() {
+                return Err("expected a value");
+            }
+            string.set(value);
+        }
+        ParamKind::Custom(parse) => parse(value.ok_or("expected a value")?)?,
+    }
+    Ok(())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "on" | "yes" | "true" => Some(true),
+        "0" | "off" | "no" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse a decimal or 0x-prefixed hex number with an optional K, M or G suffix.
+pub fn parse_usize(value: &str) -> Option<usize> {
+    let (digits, multiplier) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 1 << 10),
+        b'M' | b'm' => (&value[..value.len() - 1], 1 << 20),
+        b'G' | b'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+
+    let number = match digits.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<usize>().ok()?,
+    };
+    number.checked_mul(multiplier)
+}
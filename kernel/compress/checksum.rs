@@ -0,0 +1,105 @@
+// Checksums used by the compressed stream formats.
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 (IEEE), as used by gzip.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data.iter() {
+        crc = CRC32_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Adler-32, as used by zlib.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the most bytes that can be summed before b can overflow.
+    for chunk in data.chunks(5552) {
+        for byte in chunk.iter() {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+const PRIME32_1: u32 = 2_654_435_761;
+const PRIME32_2: u32 = 2_246_822_519;
+const PRIME32_3: u32 = 3_266_489_917;
+const PRIME32_4: u32 = 668_265_263;
+const PRIME32_5: u32 = 374_761_393;
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn xxh32_round(acc: u32, lane: u32) -> u32 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME32_2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME32_1)
+}
+
+/// xxHash32, as used by the LZ4 frame format.
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let mut remaining = data;
+    let mut hash = if data.len() >= 16 {
+        let mut v1 = seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2);
+        let mut v2 = seed.wrapping_add(PRIME32_2);
+        let mut v3 = seed;
+        let mut v4 = seed.wrapping_sub(PRIME32_1);
+        while remaining.len() >= 16 {
+            v1 = xxh32_round(v1, read_u32(&remaining[0..]));
+            v2 = xxh32_round(v2, read_u32(&remaining[4..]));
+            v3 = xxh32_round(v3, read_u32(&remaining[8..]));
+            v4 = xxh32_round(v4, read_u32(&remaining[12..]));
+            remaining = &remaining[16..];
+        }
+        v1.rotate_left(1)
+            .wrapping_add(v2.rotate_left(7))
+            .wrapping_add(v3.rotate_left(12))
+            .wrapping_add(v4.rotate_left(18))
+    } else {
+        seed.wrapping_add(PRIME32_5)
+    };
+
+    hash = hash.wrapping_add(data.len() as u32);
+    while remaining.len() >= 4 {
+        hash = hash
+            .wrapping_add(read_u32(remaining).wrapping_mul(PRIME32_3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME32_4);
+        remaining = &remaining[4..];
+    }
+    for b in remaining.iter() {
+        hash = hash
+            .wrapping_add((*b as u32).wrapping_mul(PRIME32_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME32_1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME32_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME32_3);
+    hash ^ (hash >> 16)
+}
@@ -0,0 +1,358 @@
+use super::checksum::{adler32, crc32};
+use super::{DecompressError, OutputBuffer};
+
+// DEFLATE (RFC 1951) decoder, with the gzip (RFC 1952) and zlib (RFC 1950)
+// wrappers. Huffman codes are decoded a bit at a time from the canonical code
+// counts, which is slow-ish but needs no large tables.
+
+const MAX_BITS: usize = 15;
+const MAX_LITERAL_CODES: usize = 286;
+const MAX_DISTANCE_CODES: usize = 30;
+const FIXED_LITERAL_CODES: usize = 288;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths are stored in, in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Reads bits least significant first, as DEFLATE packs them.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, DecompressError> {
+        while self.bit_count < count {
+            let byte = *self.data.get(self.position).ok_or(DecompressError::Truncated)?;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.position += 1;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    /// Drop the rest of the current byte. Bytes are only fetched when needed, so
+    /// fewer than 8 bits are ever buffered.
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    /// Offset of the next unread byte, only meaningful after `align_to_byte`.
+    fn byte_position(&self) -> usize {
+        self.position
+    }
+}
+
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; MAX_BITS + 1],
+    /// Symbols ordered by code.
+    symbols: [u16; FIXED_LITERAL_CODES],
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, DecompressError> {
+        let mut huffman = Self {
+            counts: [0; MAX_BITS + 1],
+            symbols: [0; FIXED_LITERAL_CODES],
+        };
+        for length in lengths.iter() {
+            huffman.counts[*length as usize] += 1;
+        }
+        huffman.counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for length in 1..=MAX_BITS {
+            left <<= 1;
+            left -= huffman.counts[length] as i32;
+            if left < 0 {
+                return Err(DecompressError::Corrupt("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + huffman.counts[length];
+        }
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                huffman.symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        Ok(huffman)
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, DecompressError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(DecompressError::Corrupt("invalid Huffman code"))
+    }
+}
+
+fn stored_block(reader: &mut BitReader, output: &mut OutputBuffer) -> Result<(), DecompressError> {
+    reader.align_to_byte();
+    let start = reader.byte_position();
+    let header = reader.data.get(start..start + 4).ok_or(DecompressError::Truncated)?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let inverted = u16::from_le_bytes([header[2], header[3]]);
+    if length != !inverted {
+        return Err(DecompressError::Corrupt("stored block length check failed"));
+    }
+
+    let data = reader
+        .data
+        .get(start + 4..start + 4 + length as usize)
+        .ok_or(DecompressError::Truncated)?;
+    output.extend_from_slice(data)?;
+    reader.position = start + 4 + length as usize;
+    Ok(())
+}
+
+fn compressed_block(
+    reader: &mut BitReader,
+    output: &mut OutputBuffer,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), DecompressError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            output.push(symbol as u8)?;
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(DecompressError::Corrupt("invalid length code"));
+        }
+        let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol = distances.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(DecompressError::Corrupt("invalid distance code"));
+        }
+        let distance =
+            DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+        output.copy_match(distance, length)?;
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), DecompressError> {
+    let mut lengths = [0u8; FIXED_LITERAL_CODES];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let literals = Huffman::new(&lengths)?;
+    let distances = Huffman::new(&[5; MAX_DISTANCE_CODES])?;
+    Ok((literals, distances))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), DecompressError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > MAX_LITERAL_CODES || distance_count > MAX_DISTANCE_CODES {
+        return Err(DecompressError::Corrupt("too many Huffman codes"));
+    }
+
+    let mut lengths = [0u8; MAX_LITERAL_CODES + MAX_DISTANCE_CODES];
+    for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        lengths[*index] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&lengths[..19])?;
+
+    let total = literal_count + distance_count;
+    let mut index = 0;
+    while index < total {
+        let symbol = code_lengths.decode(reader)?;
+        if symbol < 16 {
+            lengths[index] = symbol as u8;
+            index += 1;
+            continue;
+        }
+
+        let (value, repeat) = match symbol {
+            16 => {
+                if index == 0 {
+                    return Err(DecompressError::Corrupt("repeat with no previous length"));
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > total {
+            return Err(DecompressError::Corrupt("too many code lengths"));
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    if lengths[256] == 0 {
+        return Err(DecompressError::Corrupt("missing end-of-block code"));
+    }
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..total])?;
+    Ok((literals, distances))
+}
+
+/// Decode one DEFLATE stream. Returns the number of input bytes it took up.
+fn inflate(input: &[u8], output: &mut OutputBuffer) -> Result<usize, DecompressError> {
+    let mut reader = BitReader::new(input);
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored_block(&mut reader, output)?,
+            1 => {
+                let (literals, distances) = fixed_codes()?;
+                compressed_block(&mut reader, output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                compressed_block(&mut reader, output, &literals, &distances)?;
+            }
+            _ => return Err(DecompressError::Corrupt("invalid block type")),
+        }
+        if last {
+            reader.align_to_byte();
+            return Ok(reader.byte_position());
+        }
+    }
+}
+
+fn read_u32_le(input: &[u8], offset: usize) -> Result<u32, DecompressError> {
+    let bytes = input.get(offset..offset + 4).ok_or(DecompressError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Skip a NUL terminated header string starting at `offset`.
+fn skip_string(input: &[u8], offset: usize) -> Result<usize, DecompressError> {
+    let rest = input.get(offset..).ok_or(DecompressError::Truncated)?;
+    let end = rest.iter().position(|b| *b == 0).ok_or(DecompressError::Truncated)?;
+    Ok(offset + end + 1)
+}
+
+/// Decompress a gzip file. Concatenated members are decompressed one after the
+/// other, as gzip itself does, and trailing zero padding is ignored.
+pub fn gunzip(input: &[u8], output: &mut OutputBuffer) -> Result<(), DecompressError> {
+    const FHCRC: u8 = 1 << 1;
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+    const FCOMMENT: u8 = 1 << 4;
+
+    let mut offset = 0;
+    loop {
+        let header = input.get(offset..offset + 10).ok_or(DecompressError::Truncated)?;
+        if header[0] != 0x1f || header[1] != 0x8b {
+            return Err(DecompressError::Corrupt("bad gzip magic"));
+        }
+        if header[2] != 8 {
+            return Err(DecompressError::Corrupt("unsupported gzip compression method"));
+        }
+        let flags = header[3];
+        offset += 10;
+
+        if flags & FEXTRA != 0 {
+            let extra = input.get(offset..offset + 2).ok_or(DecompressError::Truncated)?;
+            offset += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        if flags & FNAME != 0 {
+            offset = skip_string(input, offset)?;
+        }
+        if flags & FCOMMENT != 0 {
+            offset = skip_string(input, offset)?;
+        }
+        if flags & FHCRC != 0 {
+            offset += 2;
+        }
+
+        let member_start = output.len();
+        offset += inflate(input.get(offset..).ok_or(DecompressError::Truncated)?, output)?;
+
+        let expected_crc = read_u32_le(input, offset)?;
+        let expected_size = read_u32_le(input, offset + 4)?;
+        offset += 8;
+
+        let member = output.since(member_start);
+        let actual_crc = crc32(member);
+        if actual_crc != expected_crc {
+            return Err(DecompressError::ChecksumMismatch {
+                expected: expected_crc,
+                actual: actual_crc,
+            });
+        }
+        // ISIZE is the size modulo 2^32.
+        if member.len() as u32 != expected_size {
+            return Err(DecompressError::LengthMismatch {
+                expected: expected_size as u64,
+                actual: member.len() as u64,
+            });
+        }
+
+        if !input[offset..].starts_with(&[0x1f, 0x8b]) {
+            return Ok(());
+        }
+    }
+}
+
+/// Decompress a zlib stream.
+pub fn zlib(input: &[u8], output: &mut OutputBuffer) -> Result<(), DecompressError> {
+    let header = input.get(..2).ok_or(DecompressError::Truncated)?;
+    if header[1] & (1 << 5) != 0 {
+        return Err(DecompressError::Corrupt("zlib preset dictionaries are not supported"));
+    }
+
+    let length = inflate(&input[2..], output)?;
+    let bytes = input.get(2 + length..2 + length + 4).ok_or(DecompressError::Truncated)?;
+    let expected = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let actual = adler32(output.since(0));
+    if actual != expected {
+        return Err(DecompressError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
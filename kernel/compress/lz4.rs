@@ -0,0 +1,172 @@
+use super::checksum::xxh32;
+use super::{DecompressError, OutputBuffer};
+
+// LZ4 frame format, as written by the `lz4` command line tool. A frame is a header
+// followed by blocks of LZ4 compressed (or stored) data and an end mark, with
+// optional xxHash32 checksums over the header, each block and the whole content.
+
+pub const FRAME_MAGIC: u32 = 0x184D_2204;
+/// Skippable frames use magics 0x184D2A50 to 0x184D2A5F.
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const SKIPPABLE_MASK: u32 = 0xFFFF_FFF0;
+
+const FLAG_VERSION_MASK: u8 = 0b1100_0000;
+const FLAG_VERSION_01: u8 = 0b0100_0000;
+const FLAG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLAG_CONTENT_SIZE: u8 = 1 << 3;
+const FLAG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLAG_DICTIONARY_ID: u8 = 1 << 0;
+
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+const MIN_MATCH: usize = 4;
+
+fn read_u32_le(input: &[u8], offset: usize) -> Result<u32, DecompressError> {
+    let bytes = input.get(offset..offset + 4).ok_or(DecompressError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read an LZ4 length that continues in 255 byte steps once its 4 bit field is saturated.
+fn read_length(block: &[u8], position: &mut usize, initial: usize) -> Result<usize, DecompressError> {
+    let mut length = initial;
+    if initial == 15 {
+        loop {
+            let byte = *block.get(*position).ok_or(DecompressError::Truncated)?;
+            *position += 1;
+            length += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(length)
+}
+
+/// Decode one LZ4 block. Matches may reach back into earlier blocks, which are
+/// still in the output buffer.
+fn decompress_block(block: &[u8], output: &mut OutputBuffer) -> Result<(), DecompressError> {
+    let mut position = 0;
+    loop {
+        let token = *block.get(position).ok_or(DecompressError::Truncated)?;
+        position += 1;
+
+        let literal_length = read_length(block, &mut position, (token >> 4) as usize)?;
+        let literals = block
+            .get(position..position + literal_length)
+            .ok_or(DecompressError::Truncated)?;
+        output.extend_from_slice(literals)?;
+        position += literal_length;
+
+        // The last sequence of a block is literals only.
+        if position == block.len() {
+            return Ok(());
+        }
+
+        let offset = block.get(position..position + 2).ok_or(DecompressError::Truncated)?;
+        let distance = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        position += 2;
+
+        let match_length = read_length(block, &mut position, (token & 0xf) as usize)? + MIN_MATCH;
+        output.copy_match(distance, match_length)?;
+    }
+}
+
+/// Decode one frame starting at the beginning of `input`. Returns the number of
+/// input bytes it took up.
+fn decompress_frame(input: &[u8], output: &mut OutputBuffer) -> Result<usize, DecompressError> {
+    let flags = *input.get(4).ok_or(DecompressError::Truncated)?;
+    if flags & FLAG_VERSION_MASK != FLAG_VERSION_01 {
+        return Err(DecompressError::Corrupt("unsupported LZ4 frame version"));
+    }
+
+    // Descriptor: FLG, BD, then the optional content size and dictionary ID.
+    let mut descriptor_end = 6;
+    let content_size_offset = descriptor_end;
+    if flags & FLAG_CONTENT_SIZE != 0 {
+        descriptor_end += 8;
+    }
+    if flags & FLAG_DICTIONARY_ID != 0 {
+        return Err(DecompressError::Corrupt("LZ4 dictionaries are not supported"));
+    }
+    let descriptor = input.get(4..descriptor_end).ok_or(DecompressError::Truncated)?;
+    let header_checksum = *input.get(descriptor_end).ok_or(DecompressError::Truncated)?;
+    let expected = ((xxh32(descriptor, 0) >> 8) & 0xff) as u8;
+    if header_checksum != expected {
+        return Err(DecompressError::ChecksumMismatch {
+            expected: expected as u32,
+            actual: header_checksum as u32,
+        });
+    }
+
+    let frame_start = output.len();
+    let mut position = descriptor_end + 1;
+    loop {
+        let block_header = read_u32_le(input, position)?;
+        position += 4;
+        if block_header == 0 {
+            break;
+        }
+
+        let block_size = (block_header & !BLOCK_UNCOMPRESSED) as usize;
+        let block = input
+            .get(position..position + block_size)
+            .ok_or(DecompressError::Truncated)?;
+        position += block_size;
+
+        if flags & FLAG_BLOCK_CHECKSUM != 0 {
+            let expected = read_u32_le(input, position)?;
+            position += 4;
+            let actual = xxh32(block, 0);
+            if actual != expected {
+                return Err(DecompressError::ChecksumMismatch { expected, actual });
+            }
+        }
+
+        if block_header & BLOCK_UNCOMPRESSED != 0 {
+            output.extend_from_slice(block)?;
+        } else {
+            decompress_block(block, output)?;
+        }
+    }
+
+    let content = output.since(frame_start);
+    if flags & FLAG_CONTENT_SIZE != 0 {
+        let bytes = &input[content_size_offset..content_size_offset + 8];
+        let mut size_bytes = [0u8; 8];
+        size_bytes.copy_from_slice(bytes);
+        let expected = u64::from_le_bytes(size_bytes);
+        if content.len() as u64 != expected {
+            return Err(DecompressError::LengthMismatch {
+                expected,
+                actual: content.len() as u64,
+            });
+        }
+    }
+    if flags & FLAG_CONTENT_CHECKSUM != 0 {
+        let expected = read_u32_le(input, position)?;
+        position += 4;
+        let actual = xxh32(content, 0);
+        if actual != expected {
+            return Err(DecompressError::ChecksumMismatch { expected, actual });
+        }
+    }
+    Ok(position)
+}
+
+/// Decompress a sequence of LZ4 frames, skipping skippable frames.
+pub fn decompress_frames(input: &[u8], output: &mut OutputBuffer) -> Result<(), DecompressError> {
+    let mut offset = 0;
+    while offset + 4 <= input.len() {
+        let magic = read_u32_le(input, offset)?;
+        if magic == FRAME_MAGIC {
+            offset += decompress_frame(&input[offset..], output)?;
+        } else if magic & SKIPPABLE_MASK == SKIPPABLE_MAGIC {
+            offset += 8 + read_u32_le(input, offset + 4)? as usize;
+        } else if offset == 0 {
+            return Err(DecompressError::Corrupt("bad LZ4 magic"));
+        } else {
+            // Trailing padding after the last frame.
+            break;
+        }
+    }
+    Ok(())
+}
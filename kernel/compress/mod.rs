@@ -0,0 +1,182 @@
+mod checksum;
+mod inflate;
+mod lz4;
+
+use crate::arch::memory::{allocate_frame, layout, map_frame, unmap, PAGE_SIZE};
+use crate::memory::addr::VirtualAddress;
+use crate::memory::page::Page;
+use core::fmt;
+
+/// Address space reserved for a decompressed image. Only the part that is actually
+/// written gets backed by frames.
+const MAX_OUTPUT_SIZE: usize = 1024 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    Gzip,
+    Zlib,
+    Lz4,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zlib => "zlib",
+            Format::Lz4 => "lz4",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecompressError {
+    /// The input ended in the middle of the compressed stream.
+    Truncated,
+    Corrupt(&'static str),
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The decompressed size doesn't match the size recorded in the stream.
+    LengthMismatch { expected: u64, actual: u64 },
+    OutputTooLarge,
+    OutOfMemory,
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecompressError::Truncated => write!(f, "input truncated"),
+            DecompressError::Corrupt(reason) => write!(f, "corrupt stream: {}", reason),
+            DecompressError::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch, expected 0x{:08x}, got 0x{:08x}", expected, actual)
+            }
+            DecompressError::LengthMismatch { expected, actual } => {
+                write!(f, "length mismatch, expected {} bytes, got {}", expected, actual)
+            }
+            DecompressError::OutputTooLarge => write!(f, "output larger than {} bytes", MAX_OUTPUT_SIZE),
+            DecompressError::OutOfMemory => write!(f, "out of memory"),
+        }
+    }
+}
+
+/// Identify the compression format of `data` from its magic bytes. Uncompressed
+/// archives are recognised first: the zlib header is only a checksum over two
+/// bytes, which the start of a tar member name can match.
+pub fn detect(data: &[u8]) -> Option<Format> {
+    let cpio = data.starts_with(b"0707");
+    let ustar = data.len() >= 262 && &data[257..262] == b"ustar";
+    if cpio || ustar {
+        None
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        Some(Format::Gzip)
+    } else if data.starts_with(&lz4::FRAME_MAGIC.to_le_bytes()) {
+        Some(Format::Lz4)
+    } else if data.len() >= 2
+        // Deflate with at most a 32K window, and no preset dictionary.
+        && data[0] & 0x0f == 8
+        && data[0] >> 4 <= 7
+        && data[1] & 0x20 == 0
+        && (data[0] as u16 * 256 + data[1] as u16) % 31 == 0
+    {
+        Some(Format::Zlib)
+    } else {
+        None
+    }
+}
+
+/// Decompress `input` into freshly mapped memory. Checksums recorded in the stream
+/// are verified.
+pub fn decompress(format: Format, input: &[u8]) -> Result<&'static [u8], DecompressError> {
+    let mut output = OutputBuffer::new(MAX_OUTPUT_SIZE)?;
+    match format {
+        Format::Gzip => inflate::gunzip(input, &mut output)?,
+        Format::Zlib => inflate::zlib(input, &mut output)?,
+        Format::Lz4 => lz4::decompress_frames(input, &mut output)?,
+    }
+    Ok(output.into_slice())
+}
+
+/// Decompression output. A range of the vmalloc region is reserved up front and
+/// frames are mapped into it as the output grows. Dropping it, e.g. when the input
+/// turns out to be corrupt, releases both.
+pub struct OutputBuffer {
+    base: *mut u8,
+    len: usize,
+    mapped: usize,
+    capacity: usize,
+}
+
+impl OutputBuffer {
+    fn new(capacity: usize) -> Result<Self, DecompressError> {
+        let base = layout::allocate_virtual_range(capacity).ok_or(DecompressError::OutOfMemory)?;
+        Ok(Self {
+            base: base.0 as *mut u8,
+            len: 0,
+            mapped: 0,
+            capacity,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    fn reserve(&mut self, additional: usize) -> Result<(), DecompressError> {
+        let needed = self.len + additional;
+        if needed > self.capacity {
+            return Err(DecompressError::OutputTooLarge);
+        }
+
+        while self.mapped < needed {
+            let frame = allocate_frame().ok_or(DecompressError::OutOfMemory)?;
+            let page = Page::from_virtual_address(VirtualAddress::new(self.base as usize + self.mapped));
+            map_frame(page, frame).map_err(|_| DecompressError::OutOfMemory)?;
+            self.mapped += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, byte: u8) -> Result<(), DecompressError> {
+        self.reserve(1)?;
+        unsafe { *self.base.add(self.len) = byte };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) -> Result<(), DecompressError> {
+        self.reserve(bytes.len())?;
+        unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.base.add(self.len), bytes.len()) };
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    /// Append `length` bytes copied from `distance` bytes back. The source and the
+    /// destination may overlap, that's how runs are encoded.
+    pub fn copy_match(&mut self, distance: usize, length: usize) -> Result<(), DecompressError> {
+        if distance == 0 || distance > self.len {
+            return Err(DecompressError::Corrupt("match distance too far back"));
+        }
+        self.reserve(length)?;
+        for _ in 0..length {
+            unsafe { *self.base.add(self.len) = *self.base.add(self.len - distance) };
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Output written since `start`.
+    pub fn since(&self, start: usize) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.base.add(start), self.len - start) }
+    }
+
+    fn into_slice(self) -> &'static [u8] {
+        let output = core::mem::ManuallyDrop::new(self);
+        unsafe { core::slice::from_raw_parts(output.base, output.len) }
+    }
+}
+
+impl Drop for OutputBuffer {
+    fn drop(&mut self) {
+        let base = VirtualAddress::new(self.base as usize);
+        unmap(base, self.mapped);
+        layout::free_virtual_range(base, self.capacity);
+    }
+}
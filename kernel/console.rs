@@ -0,0 +1,94 @@
+use crate::arch::debug::{getb, putb};
+use spin::Mutex;
+
+// A tiny line based console on the debug serial port, polled from the idle loop.
+// Mostly useful to stop automated runs: `shutdown` powers QEMU off.
+
+const MAX_LINE: usize = 80;
+const PROMPT: &str = "> ";
+
+struct Line {
+    buffer: [u8; MAX_LINE],
+    length: usize,
+}
+
+static LINE: Mutex<Line> = Mutex::new(Line {
+    buffer: [0; MAX_LINE],
+    length: 0,
+});
+
+const COMMANDS: &[(&str, &str, fn())] = &[
+    ("help", "list the commands", help),
+    ("reboot", "reset the machine", reboot),
+    ("shutdown", "turn the machine off", shutdown),
+    ("panic", "panic, to test the panic= policy", panic),
+];
+
+fn help() {
+    for (name, description, _) in COMMANDS {
+        log!("{:10} {}", name, description);
+    }
+}
+
+fn reboot() {
+    crate::power::reboot();
+}
+
+fn shutdown() {
+    crate::power::shutdown();
+}
+
+fn panic() {
+    panic!("requested from the console");
+}
+
+fn write(text: &str) {
+    for byte in text.bytes() {
+        unsafe { putb(byte) };
+    }
+}
+
+fn execute(line: &str) {
+    let command = line.trim();
+    if command.is_empty() {
+        return;
+    }
+    match COMMANDS.iter().find(|(name, _, _)| *name == command) {
+        Some((_, _, run)) => run(),
+        None => log!("unknown command '{}', try 'help'", command),
+    }
+}
+
+/// Handle the input that has arrived on the serial port since the last call.
+pub fn poll() {
+    while let Some(byte) = unsafe { getb() } {
+        let mut line = LINE.lock();
+        match byte {
+            b'\r' | b'\n' => {
+                write("\n");
+                let mut command = [0; MAX_LINE];
+                let length = line.length;
+                command[..length].copy_from_slice(&line.buffer[..length]);
+                line.length = 0;
+                // Commands may log or never return, don't hold the lock.
+                drop(line);
+                execute(core::str::from_utf8(&command[..length]).unwrap_or(""));
+                write(PROMPT);
+            }
+            // Backspace and delete
+            0x08 | 0x7f => {
+                if line.length > 0 {
+                    line.length -= 1;
+                    write("\x08 \x08");
+                }
+            }
+            b' '..=b'~' if line.length < MAX_LINE => {
+                let length = line.length;
+                line.buffer[length] = byte;
+                line.length += 1;
+                unsafe { putb(byte) };
+            }
+            _ => {}
+        }
+    }
+}
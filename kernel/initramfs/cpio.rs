@@ -0,0 +1,76 @@
+use super::{normalize, File, FileKind};
+use alloc::vec::Vec;
+
+// "New ASCII" cpio format, as produced by `cpio -H newc`. Every header field is
+// 8 hex digits, the name and the file data are each padded to a multiple of 4.
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+pub fn is_cpio(archive: &[u8]) -> bool {
+    archive.starts_with(b"070701") || archive.starts_with(b"070702")
+}
+
+fn field(header: &[u8], index: usize) -> Result<u32, &'static str> {
+    // Field 0 is the 6 byte magic, the rest are 8 bytes each.
+    let start = 6 + index * 8;
+    let digits = core::str::from_utf8(&header[start..start + 8]).map_err(|_| "bad cpio header")?;
+    u32::from_str_radix(digits, 16).map_err(|_| "bad cpio header")
+}
+
+fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+pub fn parse(archive: &'static [u8], files: &mut Vec<File>) -> Result<(), &'static str> {
+    let mut offset = 0;
+    loop {
+        if offset + HEADER_SIZE > archive.len() {
+            return Err("truncated cpio archive");
+        }
+        let header = &archive[offset..offset + HEADER_SIZE];
+        if !is_cpio(header) {
+            return Err("bad cpio magic");
+        }
+
+        let mode = field(header, 1)?;
+        let file_size = field(header, 6)? as usize;
+        let name_size = field(header, 11)? as usize;
+
+        let name_start = offset + HEADER_SIZE;
+        let data_start = align4(name_start + name_size);
+        let data_end = data_start + file_size;
+        if name_size == 0 || data_end > archive.len() {
+            return Err("truncated cpio archive");
+        }
+
+        // name_size includes the terminating NUL.
+        let name = core::str::from_utf8(&archive[name_start..name_start + name_size - 1])
+            .map_err(|_| "cpio file name is not UTF-8")?;
+        if name == TRAILER {
+            return Ok(());
+        }
+
+        let kind = match mode & S_IFMT {
+            S_IFDIR => Some(FileKind::Directory),
+            S_IFREG => Some(FileKind::Regular),
+            S_IFLNK => Some(FileKind::Symlink),
+            // Device nodes, fifos and sockets mean nothing without a real filesystem.
+            _ => None,
+        };
+        let path = normalize(name);
+        if let (Some(kind), false) = (kind, path.is_empty()) {
+            files.push(File {
+                path,
+                kind,
+                data: &archive[data_start..data_end],
+            });
+        }
+
+        offset = align4(data_end);
+    }
+}
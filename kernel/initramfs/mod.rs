@@ -0,0 +1,157 @@
+mod cpio;
+mod ustar;
+
+use crate::arch::memory::{map_physical_region, release_physical_region};
+use crate::boot_info::BootInfo;
+use crate::compress;
+use crate::memory::addr::PhysicalAddress;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    Regular,
+    Directory,
+    Symlink,
+}
+
+/// A file in the initramfs. Paths are relative to the archive root and have no
+/// leading `/` or `./`. The contents point straight into the boot module.
+pub struct File {
+    path: &'static str,
+    kind: FileKind,
+    data: &'static [u8],
+}
+
+impl File {
+    pub fn path(&self) -> &'static str {
+        self.path
+    }
+
+    pub fn kind(&self) -> FileKind {
+        self.kind
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Copy file contents starting at `offset` into `buffer`. Returns the number of
+    /// bytes read, 0 at the end of the file.
+    pub fn read_at(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        if offset >= self.data.len() {
+            return 0;
+        }
+        let count = buffer.len().min(self.data.len() - offset);
+        buffer[..count].copy_from_slice(&self.data[offset..offset + count]);
+        count
+    }
+}
+
+static FILES: Mutex<Option<&'static [File]>> = Mutex::new(None);
+
+/// Strip the leading `/` and `./` archivers like to put on member names.
+fn normalize(path: &str) -> &str {
+    let mut path = path;
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else {
+            break;
+        }
+    }
+    match path.trim_end_matches('/') {
+        "." => "",
+        path => path,
+    }
+}
+
+/// Unpack the first boot module, if there is one, as a newc cpio or ustar archive.
+/// Needs the heap for the file table.
+pub fn init(boot_info: &BootInfo) {
+    let module = match boot_info.modules().next() {
+        Some(module) => module,
+        None => {
+            log!("no boot module, running without an initramfs");
+            return;
+        }
+    };
+    log!(
+        "initramfs module at 0x{:x}-0x{:x} ({})",
+        module.start(),
+        module.end(),
+        module.string().unwrap_or("")
+    );
+
+    let module_start = PhysicalAddress::new(module.start() as usize);
+    let address = map_physical_region(module_start, module.len())
+        .expect("Failure mapping the initramfs module.");
+    let mut archive: &'static [u8] = unsafe { core::slice::from_raw_parts(address.0 as *const u8, module.len()) };
+
+    if let Some(format) = compress::detect(archive) {
+        match compress::decompress(format, archive) {
+            Ok(decompressed) => {
+                log!(
+                    "initramfs: {} compressed, {} -> {} bytes ({}.{}x)",
+                    format.name(),
+                    archive.len(),
+                    decompressed.len(),
+                    decompressed.len() / archive.len(),
+                    decompressed.len() * 10 / archive.len() % 10
+                );
+                // The compressed copy isn't needed anymore.
+                release_physical_region(address, module_start, module.len());
+                archive = decompressed;
+            }
+            Err(error) => {
+                log!("initramfs: {} decompression failed: {}", format.name(), error);
+                return;
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    let parsed = if cpio::is_cpio(archive) {
+        cpio::parse(archive, &mut files)
+    } else if ustar::is_ustar(archive) {
+        ustar::parse(archive, &mut files)
+    } else {
+        Err("unknown archive format")
+    };
+    if let Err(error) = parsed {
+        log!("initramfs: {}, {} files read before the error", error, files.len());
+    }
+
+    log!("initramfs: {} files", files.len());
+    *FILES.lock() = Some(files.leak());
+    for file in read_dir("/") {
+        log!("initramfs: /{} ({:?}, {} bytes)", file.path(), file.kind(), file.len());
+    }
+}
+
+fn files() -> &'static [File] {
+    FILES.lock().unwrap_or(&[])
+}
+
+/// Look up a file by path.
+pub fn open(path: &str) -> Option<&'static File> {
+    let path = normalize(path);
+    files().iter().find(|file| file.path == path)
+}
+
+/// The direct children of the directory at `path`.
+pub fn read_dir<'a>(path: &'a str) -> impl Iterator<Item = &'static File> + 'a {
+    let directory = normalize(path);
+    files().iter().filter(move |file| {
+        let relative = if directory.is_empty() {
+            Some(file.path)
+        } else {
+            file.path
+                .strip_prefix(directory)
+                .and_then(|rest| rest.strip_prefix('/'))
+        };
+        relative.map_or(false, |rest| !rest.is_empty() && !rest.contains('/'))
+    })
+}
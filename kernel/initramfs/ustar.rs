@@ -0,0 +1,86 @@
+use super::{normalize, File, FileKind};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// POSIX ustar: a 512 byte header per member followed by its data, padded to 512
+// bytes. Numeric fields are NUL or space terminated octal. Two zero blocks end it.
+const BLOCK_SIZE: usize = 512;
+
+pub fn is_ustar(archive: &[u8]) -> bool {
+    archive.len() >= BLOCK_SIZE && &archive[257..262] == b"ustar"
+}
+
+fn string_field(header: &'static [u8], start: usize, length: usize) -> Result<&'static str, &'static str> {
+    let bytes = &header[start..start + length];
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(length);
+    core::str::from_utf8(&bytes[..end]).map_err(|_| "tar file name is not UTF-8")
+}
+
+fn octal_field(header: &[u8], start: usize, length: usize) -> Result<usize, &'static str> {
+    let mut value = 0;
+    for b in header[start..start + length].iter() {
+        match b {
+            b'0'..=b'7' => value = value * 8 + (b - b'0') as usize,
+            b' ' if value == 0 => {}
+            b' ' | 0 => break,
+            _ => return Err("bad tar header"),
+        }
+    }
+    Ok(value)
+}
+
+pub fn parse(archive: &'static [u8], files: &mut Vec<File>) -> Result<(), &'static str> {
+    let mut offset = 0;
+    while offset + BLOCK_SIZE <= archive.len() {
+        let header = &archive[offset..offset + BLOCK_SIZE];
+        if header.iter().all(|b| *b == 0) {
+            return Ok(());
+        }
+        if !is_ustar(header) {
+            return Err("bad tar magic");
+        }
+
+        let size = octal_field(header, 124, 12)?;
+        let data_start = offset + BLOCK_SIZE;
+        let data_end = data_start + size;
+        if data_end > archive.len() {
+            return Err("truncated tar archive");
+        }
+
+        let kind = match header[156] {
+            b'0' | 0 => Some(FileKind::Regular),
+            b'2' => Some(FileKind::Symlink),
+            b'5' => Some(FileKind::Directory),
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            let name = string_field(header, 0, 100)?;
+            let prefix = string_field(header, 345, 155)?;
+            let path = if prefix.is_empty() {
+                normalize(name)
+            } else {
+                // Long names are split over the prefix and name fields, the joined
+                // path has to live as long as the file table.
+                let mut joined = String::from(prefix);
+                joined.push('/');
+                joined.push_str(name);
+                normalize(alloc::boxed::Box::leak(joined.into_boxed_str()))
+            };
+            // A symlink's target is stored in the header instead of the data.
+            let data = if kind == FileKind::Symlink {
+                let target = string_field(header, 157, 100)?;
+                target.as_bytes()
+            } else {
+                &archive[data_start..data_end]
+            };
+
+            if !path.is_empty() {
+                files.push(File { path, kind, data });
+            }
+        }
+
+        offset = data_start + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+    }
+    Ok(())
+}
@@ -1,6 +1,88 @@
+use crate::cmdline::{ParamKind, StrParam};
 use core::fmt;
 use core::sync::atomic;
 
+/// Message severity. A message is printed if its level is at or below the threshold
+/// for its module.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "off" | "0" => Some(Level::Off),
+            "error" | "1" => Some(Level::Error),
+            "warn" | "2" => Some(Level::Warn),
+            "info" | "3" => Some(Level::Info),
+            "debug" | "4" => Some(Level::Debug),
+            "trace" | "5" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// Threshold for modules without a filter, set with `loglevel=<level>`.
+static LOG_LEVEL: atomic::AtomicUsize = atomic::AtomicUsize::new(Level::Info as usize);
+/// Per-module thresholds, `log=<module>:<level>,...`. Module paths are given without
+/// the crate name, e.g. `log=memory:warn,arch::amd64::interrupt:debug`.
+static LOG_FILTERS: StrParam = StrParam::new(None);
+
+kernel_param!(LOG_LEVEL_PARAM, "loglevel", ParamKind::Custom(parse_log_level));
+kernel_param!(LOG_FILTERS_PARAM, "log", ParamKind::Custom(parse_log_filters));
+
+fn parse_log_level(value: &'static str) -> Result<(), &'static str> {
+    let level = Level::from_name(value).ok_or("unknown log level")?;
+    LOG_LEVEL.store(level as usize, atomic::Ordering::Relaxed);
+    Ok(())
+}
+
+fn parse_log_filters(value: &'static str) -> Result<(), &'static str> {
+    for filter in value.split(',') {
+        let (module, level) = filter.split_once(':').ok_or("expected <module>:<level>")?;
+        if module.is_empty() || Level::from_name(level).is_none() {
+            return Err("expected <module>:<level>");
+        }
+    }
+    LOG_FILTERS.set(value);
+    Ok(())
+}
+
+/// Returns true if a message at `level` from `module` should be printed. The most
+/// specific (longest) matching module filter wins over the global level.
+pub fn enabled(module: &str, level: Level) -> bool {
+    let module = module.split_once("::").map_or("", |(_, rest)| rest);
+    let mut threshold = LOG_LEVEL.load(atomic::Ordering::Relaxed);
+
+    // Don't spin on the filter lock, a message from an interrupt handler that
+    // races with parsing just gets the global level.
+    if let Some(filters) = LOG_FILTERS.try_get() {
+        let mut best_match = 0;
+        for filter in filters.split(',') {
+            let (prefix, filter_level) = match filter.split_once(':') {
+                Some(f) => f,
+                None => continue,
+            };
+            let matches = module == prefix
+                || (module.starts_with(prefix) && module[prefix.len()..].starts_with("::"));
+            if matches && prefix.len() > best_match {
+                if let Some(filter_level) = Level::from_name(filter_level) {
+                    best_match = prefix.len();
+                    threshold = filter_level as usize;
+                }
+            }
+        }
+    }
+
+    level != Level::Off && (level as usize) <= threshold
+}
+
 /// A formatter object
 pub struct Writer(bool);
 
@@ -16,10 +98,17 @@ impl Writer {
         // This "acquires" the lock (actually just disables output if paralel writes are attempted
         let mut ret = Writer(!LOGGING_LOCK.swap(true, atomic::Ordering::Acquire));
 
-        // Print the module name before returning (prefixes all messages)
+        // Print the time since boot and the module name before returning (prefixes all messages)
         {
             use core::fmt::Write;
-            let _ = write!(&mut ret, "[{}] ", module);
+            let now = crate::arch::time::monotonic_now();
+            let _ = write!(
+                &mut ret,
+                "[{:5}.{:06}] [{}] ",
+                now / 1_000_000_000,
+                now % 1_000_000_000 / 1000,
+                module
+            );
         }
 
         ret
@@ -1,11 +1,36 @@
 /// A very primitive logging macro
 ///
 /// Obtaines a logger instance (locking the log channel) with the current module name passed
-/// then passes the standard format! arguments to it
+/// then passes the standard format! arguments to it. Messages are logged at the info level,
+/// see `log_at!` for other levels.
 macro_rules! log{
 	( $($arg:tt)* ) => ({
-		// Import the Writer trait (required by write!)
-		use core::fmt::Write;
-		let _ = write!(&mut crate::logging::Writer::get(module_path!()), $($arg)*);
+		log_at!(crate::logging::Level::Info, $($arg)*)
 	})
 }
+
+/// Log a message at a specific `crate::logging::Level`, subject to the `loglevel` and
+/// `log` command line filters.
+macro_rules! log_at{
+	( $level:expr, $($arg:tt)* ) => ({
+		if crate::logging::enabled(module_path!(), $level) {
+			// Import the Writer trait (required by write!)
+			use core::fmt::Write;
+			let _ = write!(&mut crate::logging::Writer::get(module_path!()), $($arg)*);
+		}
+	})
+}
+
+/// Register a kernel command line parameter, see `crate::cmdline`.
+///
+/// `kernel_param!(MEMTEST_PARAM, "memtest", ParamKind::Flag(&MEMTEST));`
+macro_rules! kernel_param{
+	( $ident:ident, $name:expr, $kind:expr ) => {
+		#[used]
+		#[link_section = ".kparams"]
+		static $ident: crate::cmdline::KernelParam = crate::cmdline::KernelParam {
+			name: $name,
+			kind: $kind,
+		};
+	};
+}
@@ -2,7 +2,6 @@
 #![feature(asm_sym)]
 #![feature(asm_const)]
 #![feature(default_alloc_error_handler)]
-#![feature(naked_functions)]
 #![feature(panic_info_message)]
 #![feature(ptr_to_from_bits)]
 #![no_std]
@@ -19,34 +18,62 @@ extern crate lazy_static;
 extern crate pic8259;
 extern crate spin;
 
+mod acpi;
 #[cfg(target_arch = "x86_64")]
 #[path = "arch/amd64/mod.rs"]
 pub mod arch;
 pub mod unwind;
 
+mod boot_info;
+mod cmdline;
+mod compress;
+mod console;
+mod initramfs;
 mod logging;
 mod memory;
 mod multiboot;
+mod multiboot2;
+mod power;
 
 use self::arch::memory::PAGE_SIZE;
-use self::memory::addr::VirtualAddress;
+use self::boot_info::BootInfo;
+use self::cmdline::{ParamKind, StrParam};
 
-const KERNEL_BASE: usize = 0xFFFFFFFF80000000;
+/// Size of the stack kmain moves to once memory is up.
+const KERNEL_STACK_SIZE: usize = 64 * 1024;
+
+/// Path of the first user program, `init=<path>`.
+static INIT_PATH: StrParam = StrParam::new(Some("/init"));
+kernel_param!(INIT_PATH_PARAM, "init", ParamKind::Str(&INIT_PATH));
 
 // Kernel entrypoint (called by arch/<foo>/start.S)
 #[no_mangle]
-pub extern "C" fn kmain(multiboot_ptr: usize) {
+pub extern "C" fn kmain(multiboot_ptr: usize, multiboot_magic: u32) {
     extern "C" {
         static kernel_end: u8;
     }
 
     log!("Hello world! :)");
     let kend_vaddr: usize = unsafe { &kernel_end as *const _ as usize };
-    let kend_phys_addr = kend_vaddr - KERNEL_BASE;
+    let kend_phys_addr = kend_vaddr - arch::kernel_base();
     // page align heap start
     let bootstrap_frame_alloc_start = kend_phys_addr + PAGE_SIZE - (kend_phys_addr % PAGE_SIZE);
     log!("kendvaddr: {:x}", kend_vaddr);
-    memory::init(multiboot_ptr, bootstrap_frame_alloc_start, kend_vaddr);
+    let boot_info = BootInfo::new(multiboot_magic, multiboot_ptr);
+    cmdline::init(boot_info.cmdline);
+    arch::cpu::init();
+    memory::init(&boot_info, bootstrap_frame_alloc_start);
+
+    // start.S's stack is small and has nothing below it to catch an overflow,
+    // carry on from a guarded one.
+    let stack = arch::memory::stack_allocator::allocate_stack(KERNEL_STACK_SIZE, "kmain")
+        .expect("Failure allocating the kernel stack.");
+    arch::switch_stack(&stack, kmain_continue, &boot_info as *const BootInfo as usize);
+}
+
+/// The rest of kmain, on the stack allocated for it.
+extern "C" fn kmain_continue(boot_info: usize) -> ! {
+    let boot_info = unsafe { &*(boot_info as *const BootInfo) };
 
     use alloc::vec::Vec;
     // Test the linked_list_allocator by allocating a larger size than the biggest slab.
@@ -55,5 +82,34 @@ pub extern "C" fn kmain(multiboot_ptr: usize) {
         nums.push(i);
     }
 
+    initramfs::init(boot_info);
+    acpi::init(boot_info);
+    arch::hpet::init();
+
     arch::interrupt::init();
+    arch::time::init();
+    if let Some(init_path) = INIT_PATH.get() {
+        match initramfs::open(init_path) {
+            Some(init) if init.kind() != initramfs::FileKind::Regular => {
+                log!("init: {} is not a regular file", init_path)
+            }
+            Some(init) => {
+                let mut magic = [0u8; 4];
+                init.read_at(0, &mut magic);
+                log!(
+                    "init: {} ({} bytes){}",
+                    init_path,
+                    init.len(),
+                    if magic == *b"\x7fELF" { "" } else { ", not an ELF image" }
+                )
+            }
+            None => log!("init: {} not found in the initramfs", init_path),
+        }
+    }
+
+    loop {
+        arch::memory::refill_zeroed_frames();
+        console::poll();
+        arch::wait_for_interrupt();
+    }
 }
@@ -46,10 +46,12 @@ pub struct VirtualAddress(pub usize);
 impl VirtualAddress {
     #[inline]
     pub fn new(address: usize) -> Self {
-        // Cannonical virtual address form for x86_64 sign-extends bit 47.
+        // Cannonical virtual address form for x86_64 sign-extends the top implemented bit,
+        // bit 47 with four-level paging or bit 56 with five-level paging.
+        let bits = crate::arch::memory::virtual_address_bits();
         let mut addr = address;
-        if addr & (1 << 47) > 0 {
-            addr = addr | (0xFFFF << 48);
+        if addr & (1 << (bits - 1)) > 0 {
+            addr = addr | (usize::MAX << bits);
         }
         Self(addr)
     }
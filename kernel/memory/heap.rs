@@ -3,8 +3,11 @@ use spin::Mutex;
 
 use super::addr::VirtualAddress;
 use super::linked_list_heap::LinkedListHeap;
+use crate::cmdline::{ParamKind, UsizeParam};
 
-const INITIAL_HEAP_SIZE: usize = 2 * 1024 * 1024;
+/// Size of the initially mapped heap, `heap_size=<bytes>` (K/M/G suffixes accepted).
+static INITIAL_HEAP_SIZE: UsizeParam = UsizeParam::new(2 * 1024 * 1024, 64 * 1024, 1024 * 1024 * 1024);
+kernel_param!(HEAP_SIZE_PARAM, "heap_size", ParamKind::Usize(&INITIAL_HEAP_SIZE));
 
 #[global_allocator]
 static mut HEAP: Heap = Heap::new();
@@ -15,8 +18,10 @@ pub fn init(heap_start: usize) {
         let aligned_heap_start_ptr = aligned_heap_start_ptr
             .add(aligned_heap_start_ptr.align_offset(core::mem::size_of::<*mut u8>()));
         let heap_start = VirtualAddress::new(aligned_heap_start_ptr.to_bits());
-        let _ = crate::arch::memory::map(heap_start, INITIAL_HEAP_SIZE).unwrap();
-        let heap = HeapInner::new(heap_start.0 as *mut u8);
+        let page_size = crate::arch::memory::PAGE_SIZE;
+        let heap_size = (INITIAL_HEAP_SIZE.get() + page_size - 1) & !(page_size - 1);
+        let _ = crate::arch::memory::map(heap_start, heap_size).unwrap();
+        let heap = HeapInner::new(heap_start.0 as *mut u8, heap_size);
         HEAP.inner = Mutex::new(Some(heap));
     }
 }
@@ -108,8 +113,8 @@ struct HeapInner {
 }
 
 impl HeapInner {
-    unsafe fn new(heap_start: *mut u8) -> Self {
-        let allocation_size = INITIAL_HEAP_SIZE / 7;
+    unsafe fn new(heap_start: *mut u8, heap_size: usize) -> Self {
+        let allocation_size = heap_size / 7;
         Self {
             slab_16_bytes: Slab::new(
                 heap_start.offset(0 * allocation_size as isize),
@@ -0,0 +1,123 @@
+use super::addr::PhysicalAddress;
+use super::frame::Frame;
+use crate::arch::memory::{test_free_frame, PAGE_SIZE};
+use crate::boot_info::BootInfo;
+use crate::multiboot::MMapEntryType;
+use core::ptr::{read_volatile, write_volatile};
+
+const WORDS_PER_FRAME: usize = PAGE_SIZE / core::mem::size_of::<u64>();
+
+/// Boot-time RAM test, enabled with `memtest` on the kernel command line.
+///
+/// Every free frame in the available regions of the memory map is mapped in turn
+/// and put through the patterns below. Frames that fail are left reserved in the
+/// frame allocator so nothing ever gets placed on them.
+pub fn run(info: &BootInfo) {
+    log!("running memtest, this may take a while...");
+    let mut tested: usize = 0;
+    let mut failed: usize = 0;
+
+    for entry in info.memory_map() {
+        if !matches!(entry.entry_type(), MMapEntryType::Available) {
+            continue;
+        }
+
+        let start = entry.base_addr() as usize;
+        let end = start + entry.length() as usize;
+        let first_frame = (start + PAGE_SIZE - 1) / PAGE_SIZE;
+        let last_frame = end / PAGE_SIZE;
+
+        for frame_number in first_frame..last_frame {
+            let frame = Frame { frame_number };
+            match test_free_frame(frame, test_frame) {
+                Some(true) => tested += 1,
+                Some(false) => {
+                    tested += 1;
+                    failed += 1;
+                }
+                None => {}
+            }
+        }
+    }
+
+    log!(
+        "memtest complete: {} frames ({} KiB) tested, {} defective frames reserved",
+        tested,
+        tested * PAGE_SIZE / 1024,
+        failed
+    );
+}
+
+fn test_frame(words: *mut u64, frame_address: PhysicalAddress) -> bool {
+    let tests: [(&str, fn(*mut u64, PhysicalAddress) -> bool); 3] = [
+        ("walking ones", walking_ones),
+        ("address in address", address_in_address),
+        ("moving inversions", moving_inversions),
+    ];
+
+    for (name, test) in tests.iter() {
+        if !test(words, frame_address) {
+            log!("memtest: frame {} failed the {} test", frame_address, name);
+            return false;
+        }
+    }
+    true
+}
+
+/// Fill the frame with every single-bit pattern in turn, catching stuck data bits.
+fn walking_ones(words: *mut u64, _frame_address: PhysicalAddress) -> bool {
+    for bit in 0..64 {
+        let pattern = 1u64 << bit;
+        if !fill_and_verify(words, |_| pattern) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Store each word's own physical address in it, catching aliased address lines.
+fn address_in_address(words: *mut u64, frame_address: PhysicalAddress) -> bool {
+    fill_and_verify(words, |i| (frame_address.0 + i * core::mem::size_of::<u64>()) as u64)
+}
+
+/// Write a pattern, then walk up checking and inverting each word, then walk back
+/// down checking and restoring it. Catches coupling faults between neighbouring cells.
+fn moving_inversions(words: *mut u64, _frame_address: PhysicalAddress) -> bool {
+    for pattern in [0u64, 0x5555_5555_5555_5555] {
+        unsafe {
+            for i in 0..WORDS_PER_FRAME {
+                write_volatile(words.add(i), pattern);
+            }
+            for i in 0..WORDS_PER_FRAME {
+                if read_volatile(words.add(i)) != pattern {
+                    return false;
+                }
+                write_volatile(words.add(i), !pattern);
+            }
+            for i in (0..WORDS_PER_FRAME).rev() {
+                if read_volatile(words.add(i)) != !pattern {
+                    return false;
+                }
+                write_volatile(words.add(i), pattern);
+            }
+        }
+    }
+    true
+}
+
+fn fill_and_verify<F>(words: *mut u64, pattern: F) -> bool
+where
+    F: Fn(usize) -> u64,
+{
+    unsafe {
+        for i in 0..WORDS_PER_FRAME {
+            write_volatile(words.add(i), pattern(i));
+        }
+        for i in 0..WORDS_PER_FRAME {
+            if read_volatile(words.add(i)) != pattern(i) {
+                return false;
+            }
+        }
+    }
+    true
+}
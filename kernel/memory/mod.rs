@@ -4,8 +4,10 @@ pub mod heap;
 pub mod page;
 
 mod linked_list_heap;
+mod memtest;
 
-use super::multiboot::MultibootInfo;
+use super::boot_info::{BootInfo, BootProtocol};
+use super::cmdline::{FlagParam, ParamKind};
 use frame::Frame;
 
 #[allow(dead_code)]
@@ -19,28 +21,75 @@ pub enum PagingError {
 pub trait FrameAllocatorAPI {
     fn allocate_frame(&mut self) -> Option<Frame>;
     fn deallocate_frame(&mut self, frame: Frame);
+
+    /// Returns a frame that is already known to be filled with zeroes, if the
+    /// allocator has one ready. Callers fall back to allocate_frame() and
+    /// clear the frame themselves when this returns None.
+    fn allocate_zeroed_frame(&mut self) -> Option<Frame> {
+        None
+    }
 }
 
-pub fn init(multiboot_addr: usize, bootstrap_frame_alloc_start: usize, heap_start_virtual: usize) {
-    let multiboot_info = MultibootInfo::new(multiboot_addr);
-    log!("flags: 0x{:x}", multiboot_info.flags());
-    log!("mem_lower: 0x{:x}", multiboot_info.mem_lower());
-    log!("mem_upper: 0x{:x}", multiboot_info.mem_upper());
-    log!("mmap_addr: 0x{:x}", multiboot_info.mmap_addr());
-    log!("mmap_length: 0x{:x}", multiboot_info.mmap_length());
+/// Run the boot-time RAM test before bringing up the heap.
+static MEMTEST: FlagParam = FlagParam::new(false);
+kernel_param!(MEMTEST_PARAM, "memtest", ParamKind::Flag(&MEMTEST));
+
+pub fn init(boot_info: &BootInfo, bootstrap_frame_alloc_start: usize) {
+    match boot_info.protocol() {
+        BootProtocol::Multiboot1(info) => {
+            log!("booted via multiboot, flags: 0x{:x}", info.flags());
+            log!("mem_lower: 0x{:x}", info.mem_lower());
+            log!("mem_upper: 0x{:x}", info.mem_upper());
+        }
+        BootProtocol::Multiboot2(info) => {
+            log!("booted via multiboot2, info size: 0x{:x}", info.total_size());
+            if let Some((mem_lower, mem_upper)) = info.basic_meminfo() {
+                log!("mem_lower: 0x{:x}", mem_lower);
+                log!("mem_upper: 0x{:x}", mem_upper);
+            }
+        }
+    }
+    if let Some(name) = boot_info.boot_loader_name {
+        log!("boot loader: {}", name);
+    }
 
-    let mmap_iter = multiboot_info.mmap_iter();
-    for entry in mmap_iter {
+    for entry in boot_info.memory_map() {
         log!(
-            "size: {}, base_addr: 0x{:x}, length: 0x{:x}, entry_type: {:?}",
-            entry.size(),
+            "base_addr: 0x{:x}, length: 0x{:x}, entry_type: {:?}",
             entry.base_addr(),
             entry.length(),
             entry.entry_type()
         );
     }
 
-    super::arch::memory::init(bootstrap_frame_alloc_start, &multiboot_info, multiboot_addr);
-    heap::init(heap_start_virtual);
+    let bootstrap_frame_alloc_start = skip_boot_modules(boot_info, bootstrap_frame_alloc_start);
+    super::arch::memory::init(bootstrap_frame_alloc_start, boot_info);
+
+    if MEMTEST.get() {
+        memtest::run(boot_info);
+    }
+
+    heap::init(super::arch::memory::layout::heap_region().start.0);
     log!("memory module init complete.");
 }
+
+/// Boot modules are often loaded right after the kernel image (QEMU does this), which
+/// is exactly where the bootstrap frame allocator wants to start. Move its start past
+/// any module that begins within `BOOTSTRAP_WINDOW` of it.
+fn skip_boot_modules(boot_info: &BootInfo, mut start: usize) -> usize {
+    const BOOTSTRAP_WINDOW: usize = 4 * 1024 * 1024;
+    let page_size = super::arch::memory::PAGE_SIZE;
+
+    let mut moved = true;
+    while moved {
+        moved = false;
+        for module in boot_info.modules() {
+            let (module_start, module_end) = (module.start() as usize, module.end() as usize);
+            if module_start < start + BOOTSTRAP_WINDOW && module_end > start {
+                start = (module_end + page_size - 1) & !(page_size - 1);
+                moved = true;
+            }
+        }
+    }
+    start
+}
@@ -12,6 +12,12 @@ impl Page {
         VirtualAddress::new(self.page_number * PAGE_SIZE)
     }
 
+    /// Only meaningful with five-level paging (LA57).
+    #[inline]
+    pub fn pml5_offset(&self) -> usize {
+        (self.virtual_address().0 >> 48) & 0x1FF
+    }
+
     #[inline]
     pub fn pml4_offset(&self) -> usize {
         (self.virtual_address().0 >> 39) & 0x1FF
@@ -1,6 +1,9 @@
 use core::iter::Iterator;
 use core::mem::size_of;
 
+/// Value the boot loader leaves in eax when it booted us through Multiboot 1.
+pub const BOOTLOADER_MAGIC: u32 = 0x2BAD_B002;
+
 pub struct MultibootInfo {
     raw_data: *const u8,
 }
@@ -34,6 +37,53 @@ impl MultibootInfo {
         unsafe { *(self.raw_data.offset(8) as *const u32) }
     }
 
+    /// BIOS disk the boot loader loaded the kernel from, as (drive, part1, part2, part3).
+    pub fn boot_device(&self) -> Option<[u8; 4]> {
+        if !self.flag_is_set(1 << 1) {
+            return None;
+        }
+
+        let device = self.read_u32(12);
+        Some([
+            (device >> 24) as u8,
+            (device >> 16) as u8,
+            (device >> 8) as u8,
+            device as u8,
+        ])
+    }
+
+    /// The kernel command line passed by the boot loader, if any.
+    pub fn cmdline(&self) -> Option<&'static str> {
+        if !self.flag_is_set(1 << 2) {
+            return None;
+        }
+
+        c_string(self.read_u32(16))
+    }
+
+    pub fn modules_iter(&self) -> ModuleIter {
+        if !self.flag_is_set(1 << 3) {
+            return ModuleIter::new(core::ptr::null(), 0);
+        }
+
+        ModuleIter::new(self.read_u32(24) as usize as *const Module, self.read_u32(20))
+    }
+
+    /// Section header table of the kernel ELF image. Bits 4 (a.out symbols) and 5 are
+    /// mutually exclusive, we only understand the ELF variant.
+    pub fn elf_sections_iter(&self) -> ElfSectionIter {
+        if !self.flag_is_set(1 << 5) {
+            return ElfSectionIter::new(core::ptr::null(), 0, 0, 0);
+        }
+
+        ElfSectionIter::new(
+            self.read_u32(36) as usize as *const u8,
+            self.read_u32(28),
+            self.read_u32(32),
+            self.read_u32(40),
+        )
+    }
+
     pub fn mmap_iter(&self) -> MMapIter {
         MMapIter::new(self.mmap_addr() as *const u8, self.mmap_length())
     }
@@ -53,13 +103,120 @@ impl MultibootInfo {
         unsafe { *(self.raw_data.offset(48) as *const u32) }
     }
 
+    pub fn drives_iter(&self) -> DriveIter {
+        if !self.flag_is_set(1 << 7) {
+            return DriveIter::new(core::ptr::null(), 0);
+        }
+
+        DriveIter::new(self.read_u32(56) as usize as *const u8, self.read_u32(52))
+    }
+
+    /// Physical address of the BIOS ROM configuration table (INT 15h, AH=C0h).
+    pub fn config_table(&self) -> Option<u32> {
+        if !self.flag_is_set(1 << 8) {
+            return None;
+        }
+
+        Some(self.read_u32(60))
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        if !self.flag_is_set(1 << 9) {
+            return None;
+        }
+
+        c_string(self.read_u32(64))
+    }
+
+    pub fn apm_table(&self) -> Option<ApmTable> {
+        if !self.flag_is_set(1 << 10) {
+            return None;
+        }
+
+        let addr = self.read_u32(68) as usize as *const ApmTable;
+        unsafe { Some(core::ptr::read_unaligned(addr)) }
+    }
+
+    pub fn vbe_info(&self) -> Option<VbeInfo> {
+        if !self.flag_is_set(1 << 11) {
+            return None;
+        }
+
+        Some(VbeInfo {
+            control_info: self.read_u32(72),
+            mode_info: self.read_u32(76),
+            mode: self.read_u16(80),
+            interface_seg: self.read_u16(82),
+            interface_off: self.read_u16(84),
+            interface_len: self.read_u16(86),
+        })
+    }
+
+    pub fn framebuffer_info(&self) -> Option<FramebufferInfo> {
+        if !self.flag_is_set(1 << 12) {
+            return None;
+        }
+
+        let color_info = match self.read_u8(109) {
+            0 => FramebufferColorInfo::Indexed {
+                palette_addr: self.read_u32(110),
+                palette_num_colors: self.read_u16(114),
+            },
+            1 => FramebufferColorInfo::Rgb {
+                red_field_position: self.read_u8(110),
+                red_mask_size: self.read_u8(111),
+                green_field_position: self.read_u8(112),
+                green_mask_size: self.read_u8(113),
+                blue_field_position: self.read_u8(114),
+                blue_mask_size: self.read_u8(115),
+            },
+            _ => FramebufferColorInfo::EgaText,
+        };
+
+        Some(FramebufferInfo {
+            addr: unsafe { core::ptr::read_unaligned(self.raw_data.offset(88) as *const u64) },
+            pitch: self.read_u32(96),
+            width: self.read_u32(100),
+            height: self.read_u32(104),
+            bpp: self.read_u8(108),
+            color_info,
+        })
+    }
+
+    #[inline]
+    fn read_u8(&self, offset: isize) -> u8 {
+        unsafe { *self.raw_data.offset(offset) }
+    }
+
+    #[inline]
+    fn read_u16(&self, offset: isize) -> u16 {
+        unsafe { core::ptr::read_unaligned(self.raw_data.offset(offset) as *const u16) }
+    }
+
+    #[inline]
+    fn read_u32(&self, offset: isize) -> u32 {
+        unsafe { core::ptr::read_unaligned(self.raw_data.offset(offset) as *const u32) }
+    }
+
     #[inline]
     fn flag_is_set(&self, flag: u32) -> bool {
         (self.flags() & flag) != 0
     }
 }
 
-#[derive(Debug)]
+/// Read a NUL-terminated string the boot loader left at physical address `addr`.
+fn c_string(addr: u32) -> Option<&'static str> {
+    if addr == 0 {
+        return None;
+    }
+
+    unsafe {
+        let cstr = core::ffi::CStr::from_ptr(addr as usize as *const core::ffi::c_char);
+        cstr.to_str().ok()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum MMapEntryType {
     Available,
     Reserved,
@@ -68,6 +225,19 @@ pub enum MMapEntryType {
     DefectiveRAM,
 }
 
+impl MMapEntryType {
+    /// Decode a memory type. Multiboot 1 and 2 share the same numbering.
+    pub fn from_raw(value: u32) -> Self {
+        match value {
+            1 => MMapEntryType::Available,
+            3 => MMapEntryType::ACPI,
+            4 => MMapEntryType::PreserveOnHibernate,
+            5 => MMapEntryType::DefectiveRAM,
+            _ => MMapEntryType::Reserved,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(packed)]
 pub struct MMapEntry {
@@ -93,13 +263,7 @@ impl MMapEntry {
     pub fn entry_type(&self) -> MMapEntryType {
         let value;
         unsafe { value = core::ptr::read_unaligned(core::ptr::addr_of!(self.entry_type)) }
-        match value {
-            1 => MMapEntryType::Available,
-            3 => MMapEntryType::ACPI,
-            4 => MMapEntryType::PreserveOnHibernate,
-            5 => MMapEntryType::DefectiveRAM,
-            _ => MMapEntryType::Reserved,
-        }
+        MMapEntryType::from_raw(value)
     }
 }
 
@@ -136,3 +300,355 @@ impl Iterator for MMapIter {
         Some(entry)
     }
 }
+
+/// A boot module loaded by the boot loader, e.g. an initramfs.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Module {
+    mod_start: u32,
+    mod_end: u32,
+    string: u32,
+    reserved: u32,
+}
+
+impl Module {
+    pub fn new(mod_start: u32, mod_end: u32, string: u32) -> Self {
+        Self {
+            mod_start,
+            mod_end,
+            string,
+            reserved: 0,
+        }
+    }
+
+    pub fn start(&self) -> u32 {
+        self.mod_start
+    }
+
+    pub fn end(&self) -> u32 {
+        self.mod_end
+    }
+
+    pub fn len(&self) -> usize {
+        (self.mod_end - self.mod_start) as usize
+    }
+
+    /// The string the boot loader associated with the module, usually its command line.
+    pub fn string(&self) -> Option<&'static str> {
+        c_string(self.string)
+    }
+}
+
+pub struct ModuleIter {
+    start: *const Module,
+    count: u32,
+    current: u32,
+}
+
+impl ModuleIter {
+    fn new(start: *const Module, count: u32) -> Self {
+        Self {
+            start,
+            count,
+            current: 0,
+        }
+    }
+}
+
+impl Iterator for ModuleIter {
+    type Item = Module;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current >= self.count {
+            return None;
+        }
+
+        let module = unsafe { core::ptr::read_unaligned(self.start.offset(self.current as isize)) };
+        self.current += 1;
+        Some(module)
+    }
+}
+
+#[derive(Debug)]
+pub enum ElfSectionType {
+    Unused,
+    ProgramBits,
+    SymbolTable,
+    StringTable,
+    RelaTable,
+    Hash,
+    Dynamic,
+    Note,
+    NoBits,
+    RelTable,
+    DynamicSymbolTable,
+    Other(u32),
+}
+
+/// An ELF64 section header. ELF32 headers are widened into one.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ElfSection {
+    name: u32,
+    section_type: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    addralign: u64,
+    entsize: u64,
+}
+
+/// An ELF32 section header as it sits in memory. The kernel image is converted to
+/// elf32-i386 for the boot loader, so these are the usual kind.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Elf32Section {
+    name: u32,
+    section_type: u32,
+    flags: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    addralign: u32,
+    entsize: u32,
+}
+
+impl From<Elf32Section> for ElfSection {
+    fn from(section: Elf32Section) -> Self {
+        Self {
+            name: section.name,
+            section_type: section.section_type,
+            flags: section.flags as u64,
+            addr: section.addr as u64,
+            offset: section.offset as u64,
+            size: section.size as u64,
+            link: section.link,
+            info: section.info,
+            addralign: section.addralign as u64,
+            entsize: section.entsize as u64,
+        }
+    }
+}
+
+impl ElfSection {
+    /// Offset of the section name in the section header string table.
+    pub fn name_index(&self) -> u32 {
+        self.name
+    }
+
+    pub fn section_type(&self) -> ElfSectionType {
+        match self.section_type {
+            0 => ElfSectionType::Unused,
+            1 => ElfSectionType::ProgramBits,
+            2 => ElfSectionType::SymbolTable,
+            3 => ElfSectionType::StringTable,
+            4 => ElfSectionType::RelaTable,
+            5 => ElfSectionType::Hash,
+            6 => ElfSectionType::Dynamic,
+            7 => ElfSectionType::Note,
+            8 => ElfSectionType::NoBits,
+            9 => ElfSectionType::RelTable,
+            11 => ElfSectionType::DynamicSymbolTable,
+            other => ElfSectionType::Other(other),
+        }
+    }
+
+    pub fn flags(&self) -> u64 {
+        self.flags
+    }
+
+    pub fn is_allocated(&self) -> bool {
+        self.flags & 0x2 != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & 0x1 != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & 0x4 != 0
+    }
+
+    pub fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+const ELF32_SECTION_SIZE: u32 = core::mem::size_of::<Elf32Section>() as u32;
+const ELF64_SECTION_SIZE: u32 = core::mem::size_of::<ElfSection>() as u32;
+
+pub struct ElfSectionIter {
+    start: *const u8,
+    count: u32,
+    entry_size: u32,
+    shstrndx: u32,
+    current: u32,
+}
+
+impl ElfSectionIter {
+    /// `entry_size` tells the header class apart: 40 bytes for ELF32, 64 for ELF64.
+    /// Anything else yields no sections.
+    pub fn new(start: *const u8, count: u32, entry_size: u32, shstrndx: u32) -> Self {
+        let count = match entry_size {
+            ELF32_SECTION_SIZE | ELF64_SECTION_SIZE => count,
+            _ => 0,
+        };
+        Self {
+            start,
+            count,
+            entry_size,
+            shstrndx,
+            current: 0,
+        }
+    }
+
+    /// Look up the name of `section` in the section header string table.
+    pub fn section_name(&self, section: &ElfSection) -> Option<&'static str> {
+        if self.shstrndx >= self.count {
+            return None;
+        }
+
+        let strtab = self.section_at(self.shstrndx);
+        c_string((strtab.addr + section.name as u64) as u32)
+    }
+
+    fn section_at(&self, index: u32) -> ElfSection {
+        let entry = unsafe { self.start.offset((index * self.entry_size) as isize) };
+        if self.entry_size == ELF32_SECTION_SIZE {
+            unsafe { core::ptr::read_unaligned(entry as *const Elf32Section) }.into()
+        } else {
+            unsafe { core::ptr::read_unaligned(entry as *const ElfSection) }
+        }
+    }
+}
+
+impl Iterator for ElfSectionIter {
+    type Item = ElfSection;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current >= self.count {
+            return None;
+        }
+
+        let section = self.section_at(self.current);
+        self.current += 1;
+        Some(section)
+    }
+}
+
+/// A BIOS drive description from the drives buffer.
+#[derive(Clone, Copy)]
+pub struct Drive {
+    pub number: u8,
+    /// 0 for CHS mode, 1 for LBA mode.
+    pub mode: u8,
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors: u8,
+}
+
+pub struct DriveIter {
+    start: *const u8,
+    length: u32,
+    current_offset: isize,
+}
+
+impl DriveIter {
+    fn new(start: *const u8, length: u32) -> Self {
+        Self {
+            start,
+            length,
+            current_offset: 0,
+        }
+    }
+}
+
+impl Iterator for DriveIter {
+    type Item = Drive;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_offset >= self.length as isize {
+            return None;
+        }
+
+        let drive;
+        let size;
+        unsafe {
+            let entry = self.start.offset(self.current_offset);
+            size = core::ptr::read_unaligned(entry as *const u32);
+            drive = Drive {
+                number: *entry.offset(4),
+                mode: *entry.offset(5),
+                cylinders: core::ptr::read_unaligned(entry.offset(6) as *const u16),
+                heads: *entry.offset(8),
+                sectors: *entry.offset(9),
+            };
+        }
+        // The size field covers the whole entry including the trailing port list.
+        self.current_offset += size.max(10) as isize;
+        Some(drive)
+    }
+}
+
+/// The Advanced Power Management table.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct ApmTable {
+    pub version: u16,
+    pub cseg: u16,
+    pub offset: u32,
+    pub cseg_16: u16,
+    pub dseg: u16,
+    pub flags: u16,
+    pub cseg_len: u16,
+    pub cseg_16_len: u16,
+    pub dseg_len: u16,
+}
+
+/// VESA BIOS Extensions information. The control and mode info fields are
+/// physical addresses of the structures returned by VBE functions 00h and 01h.
+#[derive(Clone, Copy, Debug)]
+pub struct VbeInfo {
+    pub control_info: u32,
+    pub mode_info: u32,
+    pub mode: u16,
+    pub interface_seg: u16,
+    pub interface_off: u16,
+    pub interface_len: u16,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FramebufferColorInfo {
+    Indexed {
+        palette_addr: u32,
+        palette_num_colors: u16,
+    },
+    Rgb {
+        red_field_position: u8,
+        red_mask_size: u8,
+        green_field_position: u8,
+        green_mask_size: u8,
+        blue_field_position: u8,
+        blue_mask_size: u8,
+    },
+    EgaText,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FramebufferInfo {
+    pub addr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    pub color_info: FramebufferColorInfo,
+}
@@ -0,0 +1,312 @@
+use crate::multiboot::{
+    ElfSectionIter, FramebufferColorInfo, FramebufferInfo, MMapEntryType, Module,
+};
+use core::iter::Iterator;
+
+/// Value the boot loader leaves in eax when it booted us through Multiboot2.
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOT_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_BASIC_MEMINFO: u32 = 4;
+const TAG_MMAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ELF_SECTIONS: u32 = 9;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+const TAG_EFI_MMAP: u32 = 17;
+
+/// The Multiboot2 boot information: a fixed 8 byte header followed by a list of
+/// 8 byte aligned tags, terminated by an end tag.
+/// Working from https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html.
+pub struct Multiboot2Info {
+    raw_data: *const u8,
+}
+
+impl Multiboot2Info {
+    pub fn new(multiboot_ptr: usize) -> Self {
+        Multiboot2Info {
+            raw_data: multiboot_ptr as *const u8,
+        }
+    }
+
+    pub fn total_size(&self) -> u32 {
+        unsafe { *(self.raw_data as *const u32) }
+    }
+
+    pub fn tags_iter(&self) -> TagIter {
+        TagIter {
+            current: unsafe { self.raw_data.offset(8) },
+            end: unsafe { self.raw_data.offset(self.total_size() as isize) },
+        }
+    }
+
+    fn find_tag(&self, tag_type: u32) -> Option<Tag> {
+        self.tags_iter().find(|tag| tag.tag_type() == tag_type)
+    }
+
+    pub fn cmdline(&self) -> Option<&'static str> {
+        self.find_tag(TAG_CMDLINE).and_then(|tag| tag.string(8))
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'static str> {
+        self.find_tag(TAG_BOOT_LOADER_NAME)
+            .and_then(|tag| tag.string(8))
+    }
+
+    /// Amount of lower and upper memory in KiB, as (mem_lower, mem_upper).
+    pub fn basic_meminfo(&self) -> Option<(u32, u32)> {
+        self.find_tag(TAG_BASIC_MEMINFO)
+            .map(|tag| (tag.read_u32(8), tag.read_u32(12)))
+    }
+
+    /// Every module gets its own tag, with the module string stored inline.
+    pub fn modules_iter(&self) -> impl Iterator<Item = Module> {
+        self.tags_iter().filter_map(Tag::module)
+    }
+
+    pub fn mmap_iter(&self) -> MMapIter {
+        match self.find_tag(TAG_MMAP) {
+            Some(tag) => MMapIter::new(
+                unsafe { tag.addr.offset(16) },
+                tag.size() - 16,
+                tag.read_u32(8),
+            ),
+            None => MMapIter::new(core::ptr::null(), 0, 0),
+        }
+    }
+
+    pub fn elf_sections_iter(&self) -> ElfSectionIter {
+        match self.find_tag(TAG_ELF_SECTIONS) {
+            Some(tag) => ElfSectionIter::new(
+                unsafe { tag.addr.offset(20) },
+                tag.read_u32(8),
+                tag.read_u32(12),
+                tag.read_u32(16),
+            ),
+            None => ElfSectionIter::new(core::ptr::null(), 0, 0, 0),
+        }
+    }
+
+    pub fn framebuffer_info(&self) -> Option<FramebufferInfo> {
+        let tag = self.find_tag(TAG_FRAMEBUFFER)?;
+        let color_info = match tag.read_u8(29) {
+            0 => FramebufferColorInfo::Indexed {
+                palette_addr: tag.addr as usize as u32 + 34,
+                palette_num_colors: tag.read_u16(32),
+            },
+            1 => FramebufferColorInfo::Rgb {
+                red_field_position: tag.read_u8(32),
+                red_mask_size: tag.read_u8(33),
+                green_field_position: tag.read_u8(34),
+                green_mask_size: tag.read_u8(35),
+                blue_field_position: tag.read_u8(36),
+                blue_mask_size: tag.read_u8(37),
+            },
+            _ => FramebufferColorInfo::EgaText,
+        };
+
+        Some(FramebufferInfo {
+            addr: unsafe { core::ptr::read_unaligned(tag.addr.offset(8) as *const u64) },
+            pitch: tag.read_u32(16),
+            width: tag.read_u32(20),
+            height: tag.read_u32(24),
+            bpp: tag.read_u8(28),
+            color_info,
+        })
+    }
+
+    /// Address of the boot loader's copy of the ACPI RSDP. The ACPI 2.0+ (XSDP) copy
+    /// is preferred over the 1.0 one when both are present.
+    pub fn rsdp(&self) -> Option<usize> {
+        self.find_tag(TAG_ACPI_NEW)
+            .or_else(|| self.find_tag(TAG_ACPI_OLD))
+            .map(|tag| tag.addr as usize + 8)
+    }
+
+    pub fn efi_mmap_iter(&self) -> EfiMMapIter {
+        match self.find_tag(TAG_EFI_MMAP) {
+            Some(tag) => EfiMMapIter {
+                start: unsafe { tag.addr.offset(16) },
+                length: tag.size() - 16,
+                descriptor_size: tag.read_u32(8),
+                current_offset: 0,
+            },
+            None => EfiMMapIter {
+                start: core::ptr::null(),
+                length: 0,
+                descriptor_size: 0,
+                current_offset: 0,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Tag {
+    addr: *const u8,
+}
+
+impl Tag {
+    pub fn tag_type(&self) -> u32 {
+        self.read_u32(0)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.read_u32(4)
+    }
+
+    pub fn module(self) -> Option<Module> {
+        if self.tag_type() != TAG_MODULE {
+            return None;
+        }
+
+        Some(Module::new(
+            self.read_u32(8),
+            self.read_u32(12),
+            self.addr as usize as u32 + 16,
+        ))
+    }
+
+    fn string(&self, offset: isize) -> Option<&'static str> {
+        unsafe {
+            let cstr = core::ffi::CStr::from_ptr(self.addr.offset(offset) as *const core::ffi::c_char);
+            cstr.to_str().ok()
+        }
+    }
+
+    fn read_u8(&self, offset: isize) -> u8 {
+        unsafe { *self.addr.offset(offset) }
+    }
+
+    fn read_u16(&self, offset: isize) -> u16 {
+        unsafe { core::ptr::read_unaligned(self.addr.offset(offset) as *const u16) }
+    }
+
+    fn read_u32(&self, offset: isize) -> u32 {
+        unsafe { core::ptr::read_unaligned(self.addr.offset(offset) as *const u32) }
+    }
+}
+
+pub struct TagIter {
+    current: *const u8,
+    end: *const u8,
+}
+
+impl Iterator for TagIter {
+    type Item = Tag;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current >= self.end {
+            return None;
+        }
+
+        let tag = Tag { addr: self.current };
+        if tag.tag_type() == TAG_END || tag.size() < 8 {
+            return None;
+        }
+
+        // Tags are padded so the next one starts 8 byte aligned.
+        let size = (tag.size() as usize + 7) & !7;
+        self.current = unsafe { self.current.add(size) };
+        Some(tag)
+    }
+}
+
+/// A memory map entry from the Multiboot2 mmap tag. Unlike Multiboot 1 the entry size
+/// is given once in the tag header instead of per entry.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct MMapEntry {
+    base_addr: u64,
+    length: u64,
+    entry_type: u32,
+    reserved: u32,
+}
+
+impl MMapEntry {
+    pub fn base_addr(&self) -> u64 {
+        self.base_addr
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn entry_type(&self) -> MMapEntryType {
+        MMapEntryType::from_raw(self.entry_type)
+    }
+}
+
+pub struct MMapIter {
+    start: *const u8,
+    length: u32,
+    entry_size: u32,
+    current_offset: isize,
+}
+
+impl MMapIter {
+    fn new(start: *const u8, length: u32, entry_size: u32) -> Self {
+        Self {
+            start,
+            length,
+            entry_size,
+            current_offset: 0,
+        }
+    }
+}
+
+impl Iterator for MMapIter {
+    type Item = MMapEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.entry_size == 0 || self.current_offset >= self.length as isize {
+            return None;
+        }
+
+        let entry = unsafe {
+            core::ptr::read_unaligned(self.start.offset(self.current_offset) as *const MMapEntry)
+        };
+        self.current_offset += self.entry_size as isize;
+        Some(entry)
+    }
+}
+
+/// A UEFI memory descriptor, as returned by GetMemoryMap().
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct EfiMemoryDescriptor {
+    pub memory_type: u32,
+    padding: u32,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub number_of_pages: u64,
+    pub attribute: u64,
+}
+
+pub struct EfiMMapIter {
+    start: *const u8,
+    length: u32,
+    descriptor_size: u32,
+    current_offset: isize,
+}
+
+impl Iterator for EfiMMapIter {
+    type Item = EfiMemoryDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.descriptor_size == 0 || self.current_offset >= self.length as isize {
+            return None;
+        }
+
+        let descriptor = unsafe {
+            core::ptr::read_unaligned(
+                self.start.offset(self.current_offset) as *const EfiMemoryDescriptor
+            )
+        };
+        self.current_offset += self.descriptor_size as isize;
+        Some(descriptor)
+    }
+}
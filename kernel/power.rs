@@ -0,0 +1,75 @@
+use crate::acpi;
+use crate::arch::power::{delay, disable_interrupts, halt, keyboard_controller_reset, triple_fault};
+use crate::arch::x86_io::outb;
+
+/// PM1 control register bits.
+const SCI_EN: u64 = 1 << 0;
+const SLP_TYP_SHIFT: u64 = 10;
+const SLP_TYP_MASK: u64 = 0x7 << SLP_TYP_SHIFT;
+const SLP_EN: u64 = 1 << 13;
+
+/// Soft-off sleep state.
+const S5: u8 = 5;
+
+/// Turn the machine off. Falls back to halting if ACPI can't do it.
+pub fn shutdown() -> ! {
+    log!("shutting down");
+    disable_interrupts();
+    if let Err(reason) = acpi_shutdown() {
+        log!("ACPI shutdown failed: {}", reason);
+    }
+    log!("halting, the machine can be turned off");
+    halt()
+}
+
+/// Reset the machine: the FADT reset register first, then the keyboard
+/// controller and as a last resort a triple fault.
+pub fn reboot() -> ! {
+    log!("rebooting");
+    disable_interrupts();
+    if let Some((register, value)) = acpi::fadt().and_then(|fadt| fadt.reset_register()) {
+        register.write(value as u64);
+        delay(50_000);
+        log!("reset register had no effect");
+    }
+    keyboard_controller_reset();
+    delay(50_000);
+    log!("keyboard controller reset had no effect");
+    triple_fault()
+}
+
+fn acpi_shutdown() -> Result<(), &'static str> {
+    let fadt = acpi::fadt().ok_or("no FADT")?;
+    let (slp_typ_a, slp_typ_b) = acpi::sleep_type(S5).ok_or("no \\_S5 object")?;
+    let pm1a = fadt.pm1a_control_block().ok_or("no PM1a control block")?;
+    let pm1b = fadt.pm1b_control_block();
+
+    // Firmware may still own the hardware, ask it to hand over.
+    if pm1a.read() & SCI_EN == 0 {
+        let smi_command_port = fadt.smi_command_port();
+        if smi_command_port == 0 || fadt.acpi_enable() == 0 {
+            return Err("ACPI mode can't be enabled");
+        }
+        unsafe { outb(smi_command_port as u16, fadt.acpi_enable()) };
+        let mut timeout = 3_000;
+        while pm1a.read() & SCI_EN == 0 {
+            if timeout == 0 {
+                return Err("timed out enabling ACPI mode");
+            }
+            timeout -= 1;
+            delay(1_000);
+        }
+    }
+
+    let sleep = |register: acpi::GenericAddress, slp_typ: u8| {
+        let value = register.read() & !SLP_TYP_MASK;
+        register.write(value | (slp_typ as u64) << SLP_TYP_SHIFT | SLP_EN);
+    };
+    sleep(pm1a, slp_typ_a);
+    if let Some(pm1b) = pm1b {
+        sleep(pm1b, slp_typ_b);
+    }
+
+    delay(100_000);
+    Err("the machine is still running")
+}
@@ -1,3 +1,28 @@
+use crate::cmdline::ParamKind;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// What to do once a panic has been logged, `panic=halt|reboot|shutdown`.
+static PANIC_ACTION: AtomicU8 = AtomicU8::new(PANIC_HALT);
+const PANIC_HALT: u8 = 0;
+const PANIC_REBOOT: u8 = 1;
+const PANIC_SHUTDOWN: u8 = 2;
+
+kernel_param!(PANIC_PARAM, "panic", ParamKind::Custom(parse_panic_action));
+
+fn parse_panic_action(value: &'static str) -> Result<(), &'static str> {
+    let action = match value {
+        "halt" => PANIC_HALT,
+        "reboot" => PANIC_REBOOT,
+        "shutdown" => PANIC_SHUTDOWN,
+        _ => return Err("expected halt, reboot or shutdown"),
+    };
+    PANIC_ACTION.store(action, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Set while handling a panic, so a panic during reboot or shutdown just halts.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 #[panic_handler]
 pub fn panic_implementation(info: &::core::panic::PanicInfo) -> ! {
     let (file, line) = match info.location() {
@@ -11,7 +36,14 @@ pub fn panic_implementation(info: &::core::panic::PanicInfo) -> ! {
     } else {
         log!("PANIC file='{}', line={} :: ?", file, line);
     }
-    loop {}
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        crate::arch::power::halt();
+    }
+    match PANIC_ACTION.load(Ordering::Relaxed) {
+        PANIC_REBOOT => crate::power::reboot(),
+        PANIC_SHUTDOWN => crate::power::shutdown(),
+        _ => crate::arch::power::halt(),
+    }
 }
 
 #[allow(non_camel_case_types)]